@@ -0,0 +1,151 @@
+#![no_std]
+use soroban_sdk::{
+    Address, Env, Map, Symbol, contract, contracterror, contractimpl, contracttype, symbol_short,
+};
+
+pub mod xasset {
+    #![allow(clippy::too_many_arguments)]
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/xasset.wasm");
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    /// CDP not found
+    CDPNotFound = 1,
+
+    /// A CDP cannot be transferred to its own owner
+    CannotTransferToSelf = 2,
+
+    /// The CDP's owner has not proposed transferring it to this recipient, or the proposal expired
+    NoCDPTransferProposed = 3,
+
+    /// A proposal must live until a ledger that has not yet closed
+    InvalidLedgerSequence = 4,
+}
+
+#[contracttype]
+pub enum DataKey {
+    /// Recipient a CDP's owner proposed transferring it to, by owner
+    Transfer(Address),
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Proposal to transfer a CDP, which the recipient may accept until `live_until_ledger`
+pub struct TransferProposal {
+    pub to: Address,
+    pub live_until_ledger: u32,
+}
+
+const XASSET_KEY: Symbol = symbol_short!("XASSET");
+
+/// Moves the CDPs of an xAsset contract between owners, whole or in part. A transfer takes two
+/// steps: the owner proposes a recipient, who then accepts. A split carves part of a CDP out for
+/// a recipient who signs along with the owner. The xAsset's admin must make this contract its CDP
+/// manager with `set_cdp_manager`.
+#[contract]
+pub struct CDPManager;
+
+#[contractimpl]
+impl CDPManager {
+    pub fn __constructor(env: &Env, xasset: Address) {
+        env.storage().instance().set(&XASSET_KEY, &xasset);
+    }
+
+    /// Return the xAsset contract whose CDPs are managed
+    pub fn xasset(env: &Env) -> Address {
+        env.storage().instance().get(&XASSET_KEY).unwrap()
+    }
+
+    /// Propose transferring `lender`'s CDP to `to`, who may accept with `accept_transfer` until
+    /// `live_until_ledger`. Replaces any earlier proposal; `None` withdraws it. Proposals expire,
+    /// so one cannot be accepted against a CDP its owner opens later.
+    pub fn propose_transfer(
+        env: &Env,
+        lender: Address,
+        to: Option<Address>,
+        live_until_ledger: u32,
+    ) -> Result<(), Error> {
+        lender.require_auth();
+        let key = DataKey::Transfer(lender.clone());
+        let Some(to) = to else {
+            env.storage().temporary().remove(&key);
+            return Ok(());
+        };
+        if to == lender {
+            return Err(Error::CannotTransferToSelf);
+        }
+        if Self::client(env).try_cdp(&lender).is_err() {
+            return Err(Error::CDPNotFound);
+        }
+        let sequence = env.ledger().sequence();
+        if live_until_ledger < sequence || live_until_ledger > env.ledger().max_live_until_ledger()
+        {
+            return Err(Error::InvalidLedgerSequence);
+        }
+        let proposal = TransferProposal {
+            to,
+            live_until_ledger,
+        };
+        env.storage().temporary().set(&key, &proposal);
+        let ttl = live_until_ledger - sequence;
+        env.storage().temporary().extend_ttl(&key, ttl, ttl);
+        Ok(())
+    }
+
+    /// Get the recipient `lender` proposed transferring its CDP to, while the proposal lives
+    pub fn pending_transfer(env: &Env, lender: Address) -> Option<Address> {
+        let proposal: TransferProposal =
+            env.storage().temporary().get(&DataKey::Transfer(lender))?;
+        (proposal.live_until_ledger >= env.ledger().sequence()).then_some(proposal.to)
+    }
+
+    /// Accept the CDP `from` proposed transferring to `to`. The recipient must not have a CDP of
+    /// its own.
+    pub fn accept_transfer(env: &Env, from: Address, to: Address) -> Result<(), Error> {
+        to.require_auth();
+        if Self::pending_transfer(env, from.clone()) != Some(to.clone()) {
+            return Err(Error::NoCDPTransferProposed);
+        }
+        env.storage()
+            .temporary()
+            .remove(&DataKey::Transfer(from.clone()));
+        let xasset = Self::client(env);
+        let cdp = xasset.cdp(&from);
+        xasset.move_cdp(
+            &from,
+            &to,
+            &cdp.xlm_deposited,
+            &cdp.collateral,
+            &cdp.asset_lent,
+        );
+        Ok(())
+    }
+
+    /// Split `lender`'s open CDP in two, carving `xlm`, `collateral` of registered types and
+    /// `asset_lent` of debt out of it into a new CDP owned by `to`, who must not have one yet.
+    /// Both CDPs must keep some collateral and meet the xAsset's MCR. Accrued interest is divided
+    /// in proportion to the debt.
+    pub fn split(
+        env: &Env,
+        lender: Address,
+        to: Address,
+        xlm: i128,
+        collateral: Map<Address, i128>,
+        asset_lent: i128,
+    ) {
+        lender.require_auth();
+        to.require_auth();
+        Self::client(env).move_cdp(&lender, &to, &xlm, &collateral, &asset_lent);
+    }
+}
+
+impl CDPManager {
+    fn client(env: &Env) -> xasset::Client<'_> {
+        xasset::Client::new(env, &Self::xasset(env))
+    }
+}
+
+mod test;
@@ -0,0 +1,220 @@
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{
+    IntoVal, String, Val, Vec, map,
+    testutils::{Address as _, Events, Ledger},
+    token::StellarAssetClient,
+    vec,
+};
+
+mod data_feed {
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/data_feed.wasm");
+}
+
+fn create_xasset<'a>(e: &Env, xlm_sac: &Address) -> xasset::Client<'a> {
+    let xlm = data_feed::Asset::Other(Symbol::new(e, "XLM"));
+    let usdt = data_feed::Asset::Other(Symbol::new(e, "USDT"));
+    let datafeed = e.register(
+        data_feed::WASM,
+        (
+            Address::generate(e),
+            Vec::from_array(e, [xlm.clone(), usdt.clone()]),
+            usdt.clone(),
+            14u32,
+            300u32,
+        ),
+    );
+    let datafeed_client = data_feed::Client::new(e, &datafeed);
+    datafeed_client.set_asset_price(&xlm, &10_000_000_000_000, &1000);
+    datafeed_client.set_asset_price(&usdt, &100_000_000_000_000, &1000);
+    let contract_id = e.register(
+        xasset::WASM,
+        (
+            Address::generate(e),
+            xlm_sac.clone(),
+            datafeed.clone(),
+            datafeed,
+            Symbol::new(e, "USDT"),
+            11_000u32,
+            String::from_str(e, "United States Dollar xAsset"),
+            String::from_str(e, "xUSD"),
+            7u32,
+            0u32,
+        ),
+    );
+    xasset::Client::new(e, &contract_id)
+}
+
+/// Error a failed xAsset call surfaces as in the manager
+fn xasset_error(error: xasset::Error) -> soroban_sdk::Error {
+    soroban_sdk::Error::from_contract_error(error as u32)
+}
+
+#[test]
+fn test_transfer_cdp() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let xlm_sac = e
+        .register_stellar_asset_contract_v2(Address::generate(&e))
+        .address();
+    let xlm = StellarAssetClient::new(&e, &xlm_sac);
+    let token = create_xasset(&e, &xlm_sac);
+    let manager_id = e.register(CDPManager, (token.address.clone(),));
+    let manager = CDPManagerClient::new(&e, &manager_id);
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    let carol = Address::generate(&e);
+    xlm.mint(&alice, &20_000_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    let live_until = e.ledger().sequence() + 100;
+
+    // Only the proposed recipient can accept
+    let result = manager.try_propose_transfer(&alice, &Some(alice.clone()), &live_until);
+    assert_eq!(result.unwrap_err().unwrap(), Error::CannotTransferToSelf);
+    let result = manager.try_propose_transfer(&carol, &Some(bob.clone()), &live_until);
+    assert_eq!(result.unwrap_err().unwrap(), Error::CDPNotFound);
+    manager.propose_transfer(&alice, &Some(bob.clone()), &live_until);
+    assert_eq!(manager.pending_transfer(&alice), Some(bob.clone()));
+    let result = manager.try_accept_transfer(&alice, &carol);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NoCDPTransferProposed);
+
+    // CDPs only move once the xAsset's admin makes this contract its CDP manager
+    assert!(manager.try_accept_transfer(&alice, &bob).is_err());
+    token.set_cdp_manager(&Some(manager_id.clone()));
+    let before = token.cdp(&alice);
+    let result = token.mock_auths(&[]).try_move_cdp(
+        &alice,
+        &bob,
+        &before.xlm_deposited,
+        &before.collateral,
+        &before.asset_lent,
+    );
+    assert!(result.is_err());
+    e.mock_all_auths();
+
+    manager.accept_transfer(&alice, &bob);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                token.address.clone(),
+                (Symbol::new(&e, "cdp_transfer"), alice.clone(), bob.clone()).into_val(&e),
+                map![
+                    &e,
+                    (
+                        Symbol::new(&e, "asset_lent"),
+                        IntoVal::<Env, Val>::into_val(&500_000_000i128, &e)
+                    ),
+                    (
+                        Symbol::new(&e, "xlm_deposited"),
+                        IntoVal::<Env, Val>::into_val(&10_000_000_000i128, &e)
+                    ),
+                ]
+                .into_val(&e),
+            ),
+        ]
+    );
+    assert!(token.try_cdp(&alice).is_err());
+    assert_eq!(manager.pending_transfer(&alice), None);
+    let after = token.cdp(&bob);
+    assert_eq!(after.lender, bob);
+    assert_eq!(after.xlm_deposited, before.xlm_deposited);
+    assert_eq!(after.asset_lent, before.asset_lent);
+
+    // The new owner controls the CDP, and its former owner is free to open another
+    token.withdraw_collateral(&bob, &1_000_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+
+    // A recipient with a CDP of its own cannot accept one
+    manager.propose_transfer(&bob, &Some(alice.clone()), &live_until);
+    assert!(manager.try_accept_transfer(&bob, &alice).is_err());
+    manager.propose_transfer(&bob, &None, &live_until);
+    assert_eq!(manager.pending_transfer(&bob), None);
+
+    // Proposals expire
+    manager.propose_transfer(&bob, &Some(carol.clone()), &live_until);
+    e.ledger().set_sequence_number(live_until + 1);
+    assert_eq!(manager.pending_transfer(&bob), None);
+    let result = manager.try_accept_transfer(&bob, &carol);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NoCDPTransferProposed);
+    let result = manager.try_propose_transfer(&bob, &Some(carol.clone()), &live_until);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidLedgerSequence);
+}
+
+#[test]
+fn test_split_cdp() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let xlm_sac = e
+        .register_stellar_asset_contract_v2(Address::generate(&e))
+        .address();
+    let xlm = StellarAssetClient::new(&e, &xlm_sac);
+    let token = create_xasset(&e, &xlm_sac);
+    let manager_id = e.register(CDPManager, (token.address.clone(),));
+    let manager = CDPManagerClient::new(&e, &manager_id);
+    token.set_cdp_manager(&Some(manager_id));
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    xlm.mint(&alice, &20_000_000_000);
+    xlm.mint(&bob, &20_000_000_000);
+
+    e.ledger().set_timestamp(1_700_000_000);
+    token.set_interest_rate(&1_000);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    e.ledger().set_timestamp(1_700_000_000 + 31_536_000);
+    let before = token.cdp(&alice);
+    assert!(before.accrued_interest.amount > 0);
+
+    // Both halves must stay collateralized, and keep some collateral
+    let none = Map::new(&e);
+    let result = manager.try_split(&alice, &bob, &1_000_000_000, &none, &250_000_000);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        xasset_error(xasset::Error::InsufficientCollateralization)
+    );
+    let result = manager.try_split(&alice, &bob, &10_000_000_000, &none, &0);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        xasset_error(xasset::Error::InsufficientCollateral)
+    );
+    let result = manager.try_split(&alice, &bob, &1_000_000_000, &none, &500_000_001);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        xasset_error(xasset::Error::RepaymentExceedsDebt)
+    );
+
+    // Bob takes 40% of the collateral and half the debt, with half its interest
+    manager.split(&alice, &bob, &4_000_000_000, &none, &250_000_000);
+    let remaining = token.cdp(&alice);
+    let carved = token.cdp(&bob);
+    assert_eq!(remaining.xlm_deposited, 6_000_000_000);
+    assert_eq!(carved.xlm_deposited, 4_000_000_000);
+    assert_eq!(
+        (remaining.asset_lent, carved.asset_lent),
+        (250_000_000, 250_000_000)
+    );
+    assert_eq!(
+        carved.accrued_interest.amount,
+        before.accrued_interest.amount / 2
+    );
+    assert_eq!(
+        remaining.accrued_interest.amount + carved.accrued_interest.amount,
+        before.accrued_interest.amount
+    );
+    assert_eq!(carved.status, xasset::CDPStatus::Open);
+
+    // Bob's new CDP is his own, and he cannot receive a second
+    token.withdraw_collateral(&bob, &1_000_000_000);
+    assert_eq!(
+        soroban_sdk::token::TokenClient::new(&e, &xlm_sac).balance(&bob),
+        21_000_000_000
+    );
+    let result = manager.try_split(&alice, &bob, &1_000_000_000, &none, &0);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        xasset_error(xasset::Error::CDPAlreadyExists)
+    );
+}
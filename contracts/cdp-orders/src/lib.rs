@@ -0,0 +1,230 @@
+#![no_std]
+use soroban_sdk::{
+    Address, Env, IntoVal, Symbol,
+    auth::{ContractContext, InvokerContractAuthEntry, SubContractInvocation},
+    contract, contracterror, contractevent, contractimpl, contracttype, symbol_short,
+    token::TokenClient,
+    vec,
+};
+
+pub mod xasset {
+    #![allow(clippy::too_many_arguments)]
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/xasset.wasm");
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    /// CDP not found
+    CDPNotFound = 1,
+
+    /// Order amount must be greater than 0, and its tip must not be negative
+    ValueNotPositive = 2,
+
+    /// No order of this kind is set on the CDP; see `set_order`
+    OrderNotFound = 3,
+
+    /// CDP's collateralization ratio is not below the order's trigger ratio
+    OrderNotTriggered = 4,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// What a CDP order does once triggered; a CDP holds at most one order of each
+pub enum CDPOrderAction {
+    /// Add XLM collateral, paid from the owner's XLM allowance to this contract
+    AddCollateral,
+    /// Repay debt, paid from the owner's xAsset allowance to this contract
+    RepayDebt,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Order any keeper may execute once its CDP's collateralization ratio drops below
+/// `trigger_ratio`
+pub struct CDPOrder {
+    /// Collateralization ratio, in basis points, below which the order may be executed
+    pub trigger_ratio: u32,
+    /// XLM to add or xAsset to repay; repayments are capped at the CDP's debt
+    pub amount: i128,
+    /// Paid to the executing keeper from the same allowance, in the same asset
+    pub tip: i128,
+}
+
+#[contracttype]
+pub enum DataKey {
+    /// Order on a CDP, by owner and action
+    Order(Address, CDPOrderAction),
+}
+
+/// Keeper executed an order on a CDP, adding `amount` of XLM or repaying `amount` of xAsset
+#[contractevent(topics = ["cdp_order"])]
+pub struct CDPOrderExecuted {
+    #[topic]
+    pub lender: Address,
+    #[topic]
+    pub keeper: Address,
+    pub action: CDPOrderAction,
+    pub amount: i128,
+    pub tip: i128,
+}
+
+const XASSET_KEY: Symbol = symbol_short!("XASSET");
+
+/// Stop-loss orders on the CDPs of an xAsset contract. Owners place an order per action, and any
+/// keeper may execute it once the CDP's ratio drops below its trigger, for a tip. Executing acts
+/// on the CDP as one of its owner's operators, so owners must make this contract their operator
+/// with the xAsset's `set_operator`. Orders are kept until executed or cancelled, even if the CDP
+/// is closed, and one placed before a CDP is closed executes on the next CDP its owner opens.
+#[contract]
+pub struct CDPOrders;
+
+#[contractimpl]
+impl CDPOrders {
+    pub fn __constructor(env: &Env, xasset: Address) {
+        env.storage().instance().set(&XASSET_KEY, &xasset);
+    }
+
+    /// Return the xAsset contract whose CDPs the orders are on
+    pub fn xasset(env: &Env) -> Address {
+        env.storage().instance().get(&XASSET_KEY).unwrap()
+    }
+
+    /// Place an order on `lender`'s CDP, replacing any earlier order with the same action; `None`
+    /// cancels it. The owner must keep an allowance to this contract covering the amount and
+    /// tip, in XLM to add collateral or in xAsset to repay debt.
+    pub fn set_order(
+        env: &Env,
+        lender: Address,
+        action: CDPOrderAction,
+        order: Option<CDPOrder>,
+    ) -> Result<(), Error> {
+        lender.require_auth();
+        if Self::client(env).try_cdp(&lender).is_err() {
+            return Err(Error::CDPNotFound);
+        }
+        let key = DataKey::Order(lender, action);
+        match order {
+            Some(order) => {
+                if order.amount <= 0 || order.tip < 0 {
+                    return Err(Error::ValueNotPositive);
+                }
+                env.storage().persistent().set(&key, &order);
+                let ttl = env.storage().max_ttl();
+                env.storage().persistent().extend_ttl(&key, ttl, ttl);
+            }
+            None => env.storage().persistent().remove(&key),
+        }
+        Ok(())
+    }
+
+    /// Get the order with the given action on `lender`'s CDP, if any
+    pub fn order(env: &Env, lender: Address, action: CDPOrderAction) -> Option<CDPOrder> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Order(lender, action))
+    }
+
+    /// Execute an order once its CDP's collateralization ratio is below the order's trigger,
+    /// paying the tip to `keeper`. Each order executes once.
+    ///
+    /// Repaying debt settles the CDP's accrued interest first, which is drawn from the owner's
+    /// allowances too: in XLM, up to the interest accruing in the next five minutes, if the
+    /// xAsset contract takes interest in XLM, and in xAsset otherwise. Whatever is left over is
+    /// returned to the owner.
+    pub fn execute(
+        env: &Env,
+        keeper: Address,
+        lender: Address,
+        action: CDPOrderAction,
+    ) -> Result<(), Error> {
+        keeper.require_auth();
+        let Some(order) = Self::order(env, lender.clone(), action) else {
+            return Err(Error::OrderNotFound);
+        };
+        let xasset = Self::client(env);
+        let cdp = xasset.cdp(&lender);
+        if cdp.collateralization_ratio >= order.trigger_ratio {
+            return Err(Error::OrderNotTriggered);
+        }
+        env.storage()
+            .persistent()
+            .remove(&DataKey::Order(lender.clone(), action));
+
+        let contract = env.current_contract_address();
+        let xlm = TokenClient::new(env, &xasset.xlm_sac());
+        let amount = match action {
+            CDPOrderAction::AddCollateral => {
+                xlm.transfer_from(&contract, &lender, &contract, &order.amount);
+                if order.tip > 0 {
+                    xlm.transfer_from(&contract, &lender, &keeper, &order.tip);
+                }
+                // The xAsset contract pulls the collateral from this contract, its caller's caller
+                env.authorize_as_current_contract(vec![
+                    env,
+                    InvokerContractAuthEntry::Contract(SubContractInvocation {
+                        context: ContractContext {
+                            contract: xlm.address.clone(),
+                            fn_name: Symbol::new(env, "transfer"),
+                            args: (contract.clone(), xasset.address.clone(), order.amount)
+                                .into_val(env),
+                        },
+                        sub_invocations: vec![env],
+                    }),
+                ]);
+                xasset.add_collateral_for(&contract, &lender, &order.amount);
+                order.amount
+            }
+            CDPOrderAction::RepayDebt => {
+                let amount = order.amount.min(cdp.asset_lent);
+                let interest = xasset.get_accrued_interest(&lender);
+                let (xlm_owed, xasset_owed) = match xasset.get_interest_payment() {
+                    xasset::InterestPayment::Xlm => (interest.approval_amount, amount + order.tip),
+                    _ => (0, amount + order.tip + interest.amount),
+                };
+                xasset.transfer_from(&contract, &lender, &contract, &xasset_owed);
+                if xlm_owed > 0 {
+                    xlm.transfer_from(&contract, &lender, &contract, &xlm_owed);
+                    xlm.approve(
+                        &contract,
+                        &xasset.address,
+                        &xlm_owed,
+                        &env.ledger().sequence(),
+                    );
+                }
+                if order.tip > 0 {
+                    xasset.transfer(&contract, &keeper, &order.tip);
+                }
+                xasset.repay_debt_for(&contract, &lender, &amount);
+                // Return what settling interest left over
+                let xlm_left = xlm.balance(&contract);
+                if xlm_left > 0 {
+                    xlm.transfer(&contract, &lender, &xlm_left);
+                }
+                let xasset_left = xasset.balance(&contract);
+                if xasset_left > 0 {
+                    xasset.transfer(&contract, &lender, &xasset_left);
+                }
+                amount
+            }
+        };
+        CDPOrderExecuted {
+            lender,
+            keeper,
+            action,
+            amount,
+            tip: order.tip,
+        }
+        .publish(env);
+        Ok(())
+    }
+}
+
+impl CDPOrders {
+    fn client(env: &Env) -> xasset::Client<'_> {
+        xasset::Client::new(env, &Self::xasset(env))
+    }
+}
+
+mod test;
@@ -0,0 +1,177 @@
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{
+    String, Vec,
+    testutils::{Address as _, Ledger, MockAuth, MockAuthInvoke},
+    token::StellarAssetClient,
+};
+
+mod data_feed {
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/data_feed.wasm");
+}
+
+fn create_xasset<'a>(e: &Env, xlm_sac: &Address) -> (xasset::Client<'a>, data_feed::Client<'a>) {
+    let xlm = data_feed::Asset::Other(Symbol::new(e, "XLM"));
+    let usdt = data_feed::Asset::Other(Symbol::new(e, "USDT"));
+    let datafeed = e.register(
+        data_feed::WASM,
+        (
+            Address::generate(e),
+            Vec::from_array(e, [xlm.clone(), usdt.clone()]),
+            usdt.clone(),
+            14u32,
+            300u32,
+        ),
+    );
+    let datafeed_client = data_feed::Client::new(e, &datafeed);
+    datafeed_client.set_asset_price(&xlm, &10_000_000_000_000, &1000);
+    datafeed_client.set_asset_price(&usdt, &100_000_000_000_000, &1000);
+    let contract_id = e.register(
+        xasset::WASM,
+        (
+            Address::generate(e),
+            xlm_sac.clone(),
+            datafeed.clone(),
+            datafeed.clone(),
+            Symbol::new(e, "USDT"),
+            11_000u32,
+            String::from_str(e, "United States Dollar xAsset"),
+            String::from_str(e, "xUSD"),
+            7u32,
+            0u32,
+        ),
+    );
+    (
+        xasset::Client::new(e, &contract_id),
+        data_feed::Client::new(e, &datafeed),
+    )
+}
+
+#[test]
+fn test_cdp_orders() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let xlm_sac = e
+        .register_stellar_asset_contract_v2(Address::generate(&e))
+        .address();
+    let xlm_admin = StellarAssetClient::new(&e, &xlm_sac);
+    let xlm = TokenClient::new(&e, &xlm_sac);
+    let (token, datafeed) = create_xasset(&e, &xlm_sac);
+    let orders_id = e.register(CDPOrders, (token.address.clone(),));
+    let orders = CDPOrdersClient::new(&e, &orders_id);
+
+    let alice = Address::generate(&e);
+    let keeper = Address::generate(&e);
+    xlm_admin.mint(&alice, &20_000_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    token.set_operator(&alice, &orders_id, &true);
+    assert_eq!(token.cdp(&alice).collateralization_ratio, 20_000);
+
+    // Orders need a CDP and a positive amount, and can be cancelled
+    let order = CDPOrder {
+        trigger_ratio: 18_000,
+        amount: 5_000_000_000,
+        tip: 10_000_000,
+    };
+    let result = orders.try_set_order(&keeper, &CDPOrderAction::AddCollateral, &Some(order));
+    assert_eq!(result.unwrap_err().unwrap(), Error::CDPNotFound);
+    let result = orders.try_set_order(
+        &alice,
+        &CDPOrderAction::AddCollateral,
+        &Some(CDPOrder { amount: 0, ..order }),
+    );
+    assert_eq!(result.unwrap_err().unwrap(), Error::ValueNotPositive);
+    orders.set_order(&alice, &CDPOrderAction::RepayDebt, &Some(order));
+    orders.set_order(&alice, &CDPOrderAction::RepayDebt, &None);
+    assert_eq!(orders.order(&alice, &CDPOrderAction::RepayDebt), None);
+
+    // A top-up waits for the ratio to drop below its trigger
+    orders.set_order(&alice, &CDPOrderAction::AddCollateral, &Some(order));
+    assert_eq!(
+        orders.order(&alice, &CDPOrderAction::AddCollateral),
+        Some(order)
+    );
+    xlm.approve(&alice, &orders_id, &10_000_000_000, &1000);
+    let result = orders.try_execute(&keeper, &alice, &CDPOrderAction::AddCollateral);
+    assert_eq!(result.unwrap_err().unwrap(), Error::OrderNotTriggered);
+
+    // Executing needs only the keeper's signature
+    datafeed.set_asset_price(
+        &data_feed::Asset::Other(Symbol::new(&e, "XLM")),
+        &8_000_000_000_000,
+        &1000,
+    );
+    orders
+        .mock_auths(&[MockAuth {
+            address: &keeper,
+            invoke: &MockAuthInvoke {
+                contract: &orders_id,
+                fn_name: "execute",
+                args: (&keeper, &alice, CDPOrderAction::AddCollateral).into_val(&e),
+                sub_invokes: &[],
+            },
+        }])
+        .execute(&keeper, &alice, &CDPOrderAction::AddCollateral);
+    assert_eq!(token.cdp(&alice).xlm_deposited, 15_000_000_000);
+    assert_eq!(xlm.balance(&keeper), 10_000_000);
+    assert_eq!(xlm.balance(&alice), 4_990_000_000);
+    assert_eq!(orders.order(&alice, &CDPOrderAction::AddCollateral), None);
+    e.mock_all_auths();
+    let result = orders.try_execute(&keeper, &alice, &CDPOrderAction::AddCollateral);
+    assert_eq!(result.unwrap_err().unwrap(), Error::OrderNotFound);
+
+    // A repayment draws on the owner's xAsset allowance
+    let order = CDPOrder {
+        trigger_ratio: 25_000,
+        amount: 400_000_000,
+        tip: 5_000_000,
+    };
+    orders.set_order(&alice, &CDPOrderAction::RepayDebt, &Some(order));
+    assert!(
+        orders
+            .try_execute(&keeper, &alice, &CDPOrderAction::RepayDebt)
+            .is_err()
+    );
+    token.approve(&alice, &orders_id, &500_000_000, &1000);
+    orders.execute(&keeper, &alice, &CDPOrderAction::RepayDebt);
+    assert_eq!(token.cdp(&alice).asset_lent, 100_000_000);
+    assert_eq!(token.balance(&keeper), 5_000_000);
+    assert_eq!(token.balance(&alice), 95_000_000);
+    assert_eq!(token.balance(&orders_id), 0);
+    assert_eq!(token.allowance(&alice, &orders_id), 95_000_000);
+
+    // An allowance of exactly the amount and tip is spent out
+    let order = CDPOrder {
+        trigger_ratio: 200_000,
+        amount: 50_000_000,
+        tip: 5_000_000,
+    };
+    orders.set_order(&alice, &CDPOrderAction::RepayDebt, &Some(order));
+    token.approve(&alice, &orders_id, &55_000_000, &1000);
+    orders.execute(&keeper, &alice, &CDPOrderAction::RepayDebt);
+    assert_eq!(token.cdp(&alice).asset_lent, 50_000_000);
+    assert_eq!(token.balance(&keeper), 10_000_000);
+    assert_eq!(token.allowance(&alice, &orders_id), 0);
+
+    // Interest is settled first from the owner's XLM allowance, and the rest returned
+    token.set_interest_rate(&1_000);
+    e.ledger().set_timestamp(e.ledger().timestamp() + 31_536_000);
+    let order = CDPOrder {
+        trigger_ratio: 300_000,
+        amount: 10_000_000,
+        tip: 0,
+    };
+    orders.set_order(&alice, &CDPOrderAction::RepayDebt, &Some(order));
+    token.approve(&alice, &orders_id, &10_000_000, &1000);
+    xlm.approve(&alice, &orders_id, &1_000_000_000, &1000);
+    let interest = token.get_accrued_interest(&alice);
+    assert!(interest.amount > 0);
+    let xlm_before = xlm.balance(&alice);
+    orders.execute(&keeper, &alice, &CDPOrderAction::RepayDebt);
+    assert_eq!(token.cdp(&alice).asset_lent, 40_000_000);
+    assert_eq!(token.get_accrued_interest(&alice).amount, 0);
+    assert_eq!(xlm.balance(&alice), xlm_before - interest.amount_in_xlm);
+    assert_eq!(xlm.balance(&orders_id), 0);
+}
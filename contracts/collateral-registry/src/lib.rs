@@ -0,0 +1,83 @@
+#![no_std]
+use soroban_sdk::{
+    Address, Env, Map, Symbol, contract, contracterror, contractimpl, contracttype,
+    panic_with_error, symbol_short,
+};
+
+const BASIS_POINTS: i128 = 10_000;
+
+/// Most collateral types that can be registered; xAsset stability pool updates visit each
+const MAX_COLLATERAL_TYPES: u32 = 8;
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    /// Collateral factor exceeds 100%, debt ceiling is negative, or the token is XLM
+    InvalidCollateralType = 1,
+
+    /// No more collateral types can be registered
+    TooManyCollateralTypes = 2,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Collateral CDPs may hold besides XLM, registered by its SAC token address
+pub struct CollateralType {
+    /// Oracle contract for the collateral's price feed
+    pub oracle: Address,
+    /// Oracle asset ID of the collateral
+    pub asset: Symbol,
+    /// Share of the collateral's value, in basis points, counted towards a CDP's ratio
+    pub collateral_factor: u32,
+    /// Most principal that may be lent against CDPs holding the collateral
+    pub debt_ceiling: i128,
+}
+
+const ADMIN_KEY: Symbol = symbol_short!("ADMIN");
+const NATIVE_KEY: Symbol = symbol_short!("NATIVE");
+const TYPES_KEY: Symbol = symbol_short!("TYPES");
+
+/// Collateral types the CDPs of xAsset contracts may hold besides XLM, by SAC token address. An
+/// xAsset contract values and caps its CDPs' collateral by the types of the registry its admin
+/// sets with `set_collateral_registry`.
+#[contract]
+pub struct CollateralRegistry;
+
+#[contractimpl]
+impl CollateralRegistry {
+    pub fn __constructor(env: &Env, admin: Address, native: Address) {
+        env.storage().instance().set(&ADMIN_KEY, &admin);
+        env.storage().instance().set(&NATIVE_KEY, &native);
+    }
+
+    /// Register a collateral type by its SAC token address, or update one. Types cannot be
+    /// removed; set a debt ceiling of 0 to stop lending against one. Only callable by admin.
+    pub fn set_collateral_type(env: &Env, token: Address, collateral_type: CollateralType) {
+        let admin: Address = env.storage().instance().get(&ADMIN_KEY).unwrap();
+        admin.require_auth();
+        let native: Address = env.storage().instance().get(&NATIVE_KEY).unwrap();
+        if i128::from(collateral_type.collateral_factor) > BASIS_POINTS
+            || collateral_type.debt_ceiling < 0
+            || token == native
+        {
+            panic_with_error!(env, Error::InvalidCollateralType);
+        }
+        let mut types = Self::collateral_types(env);
+        if !types.contains_key(token.clone()) && types.len() >= MAX_COLLATERAL_TYPES {
+            panic_with_error!(env, Error::TooManyCollateralTypes);
+        }
+        types.set(token, collateral_type);
+        env.storage().instance().set(&TYPES_KEY, &types);
+    }
+
+    /// Get the registered collateral types, by SAC token address
+    pub fn collateral_types(env: &Env) -> Map<Address, CollateralType> {
+        env.storage()
+            .instance()
+            .get(&TYPES_KEY)
+            .unwrap_or_else(|| Map::new(env))
+    }
+}
+
+mod test;
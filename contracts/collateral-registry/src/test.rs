@@ -0,0 +1,68 @@
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::testutils::Address as _;
+
+#[test]
+fn test_collateral_types() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let native = Address::generate(&e);
+    let registry_id = e.register(CollateralRegistry, (Address::generate(&e), native.clone()));
+    let registry = CollateralRegistryClient::new(&e, &registry_id);
+    assert!(registry.collateral_types().is_empty());
+
+    // Factors above 100%, negative ceilings and XLM itself are refused
+    let usdc = Address::generate(&e);
+    let collateral_type = CollateralType {
+        oracle: Address::generate(&e),
+        asset: Symbol::new(&e, "USDC"),
+        collateral_factor: 8000,
+        debt_ceiling: 1_500_000_000,
+    };
+    for (token, invalid) in [
+        (
+            &usdc,
+            CollateralType {
+                collateral_factor: 10_001,
+                ..collateral_type.clone()
+            },
+        ),
+        (
+            &usdc,
+            CollateralType {
+                debt_ceiling: -1,
+                ..collateral_type.clone()
+            },
+        ),
+        (&native, collateral_type.clone()),
+    ] {
+        let result = registry.try_set_collateral_type(token, &invalid);
+        assert_eq!(
+            result.unwrap_err().unwrap(),
+            Error::InvalidCollateralType.into()
+        );
+    }
+    registry.set_collateral_type(&usdc, &collateral_type);
+    assert_eq!(
+        registry.collateral_types().get(usdc.clone()),
+        Some(collateral_type.clone())
+    );
+
+    // Registered types can be updated once the registry is full, but no more added
+    for _ in 1..MAX_COLLATERAL_TYPES {
+        registry.set_collateral_type(&Address::generate(&e), &collateral_type);
+    }
+    let result = registry.try_set_collateral_type(&Address::generate(&e), &collateral_type);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        Error::TooManyCollateralTypes.into()
+    );
+    let stopped = CollateralType {
+        debt_ceiling: 0,
+        ..collateral_type
+    };
+    registry.set_collateral_type(&usdc, &stopped);
+    assert_eq!(registry.collateral_types().get(usdc), Some(stopped));
+}
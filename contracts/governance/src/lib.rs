@@ -37,6 +37,16 @@ impl GovernanceContract {
         xasset_client.set_min_collat_ratio(&new_value) 
     }
 
+    pub fn sweep_treasury(env: &Env, contract: Address) -> (i128, i128) {
+        // Only admin can execute this function
+        Self::require_admin(env);
+
+        // Sweep the xasset contract's collected fees and interest to its treasury.
+        // Requires this contract to be the xasset contract's admin
+        let xasset_client = xasset::Client::new(env, &contract);
+        xasset_client.sweep_to_treasury()
+    }
+
 
 }
 
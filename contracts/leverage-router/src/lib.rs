@@ -0,0 +1,252 @@
+#![no_std]
+use soroban_sdk::{
+    Address, Env, contract, contractclient, contracterror, contractimpl, symbol_short,
+};
+
+pub mod xasset {
+    #![allow(clippy::too_many_arguments)]
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/xasset.wasm");
+}
+
+const BASIS_POINTS: i128 = 10_000;
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    /// Target ratio must be at least the xAsset's minimum collateralization ratio
+    InvalidTarget = 1,
+
+    /// Slippage must be at most 100%
+    InvalidSlippage = 2,
+
+    /// CDP has no debt to scale
+    CDPHasNoDebt = 3,
+
+    /// Trading at the worst price allowed cannot move the ratio to the target
+    TargetUnreachable = 4,
+
+    /// The rounds a call makes left the ratio beyond the caller's bound
+    TargetNotReached = 5,
+}
+
+/// Swap venue the router trades through; adapters wrap a DEX or market maker behind it
+#[contractclient(name = "SwapClient")]
+pub trait SwapAdapter {
+    /// Swap exactly `amount_in` of `token_in` from `from` for at least `min_amount_out` of
+    /// `token_out`, sent to `from`, and return the amount sent
+    fn swap_exact_in(
+        env: Env,
+        from: Address,
+        token_in: Address,
+        token_out: Address,
+        amount_in: i128,
+        min_amount_out: i128,
+    ) -> i128;
+}
+
+const ADMIN_KEY: soroban_sdk::Symbol = symbol_short!("ADMIN");
+const XASSET_KEY: soroban_sdk::Symbol = symbol_short!("XASSET");
+const SWAP_KEY: soroban_sdk::Symbol = symbol_short!("SWAP");
+
+/// Moves an xAsset CDP towards a target collateralization ratio in one call. Leveraging borrows
+/// xAsset, swaps it for XLM and adds that as collateral; deleveraging withdraws XLM, swaps it
+/// for xAsset and repays debt. The xAsset contract cannot flash-mint, since a swap venue could
+/// not move the minted xAsset without calling back into it, so each call makes several rounds
+/// within the minimum collateralization ratio. Every swap is bounded by the oracle price less
+/// the caller's slippage allowance.
+#[contract]
+pub struct LeverageRouter;
+
+#[contractimpl]
+impl LeverageRouter {
+    pub fn __constructor(env: &Env, admin: Address, xasset: Address, swap: Address) {
+        env.storage().instance().set(&ADMIN_KEY, &admin);
+        env.storage().instance().set(&XASSET_KEY, &xasset);
+        env.storage().instance().set(&SWAP_KEY, &swap);
+    }
+
+    /// Return the xAsset contract whose CDPs this router manages
+    pub fn xasset(env: &Env) -> Address {
+        env.storage().instance().get(&XASSET_KEY).unwrap()
+    }
+
+    /// Return the swap adapter the router trades through
+    pub fn swap(env: &Env) -> Address {
+        env.storage().instance().get(&SWAP_KEY).unwrap()
+    }
+
+    /// Set the swap adapter the router trades through. Only callable by admin.
+    pub fn set_swap(env: &Env, swap: Address) {
+        let admin: Address = env.storage().instance().get(&ADMIN_KEY).unwrap();
+        admin.require_auth();
+        env.storage().instance().set(&SWAP_KEY, &swap);
+    }
+
+    /// Lower `lender`'s CDP towards `target_ratio`, in basis points, by borrowing xAsset and
+    /// swapping it for more XLM collateral, accepting up to `max_slippage` basis points below
+    /// the oracle price. The ratio approaches the target from above, in at most `max_steps`
+    /// rounds; each round costs several xAsset calls, so a transaction's resource limits allow
+    /// only a few. Targets near the minimum ratio take more rounds than one call makes; the call
+    /// fails unless it stops within rounding of the target or at or below `max_final_ratio`, so
+    /// a caller can accept partial progress and call again.
+    pub fn leverage_cdp(
+        env: &Env,
+        lender: Address,
+        target_ratio: u32,
+        max_slippage: u32,
+        max_final_ratio: u32,
+        max_steps: u32,
+    ) -> Result<xasset::CDPContract, Error> {
+        lender.require_auth();
+        let xasset = Self::client(env);
+        let kept = Self::kept_share(max_slippage)?;
+        let min_ratio = i128::from(xasset.minimum_collateralization_ratio());
+        let target = i128::from(target_ratio);
+        if target < min_ratio {
+            return Err(Error::InvalidTarget);
+        }
+        // Borrowing `d` adds at least `d * kept / BASIS_POINTS` of collateral and
+        // `d * (BASIS_POINTS + fee) / BASIS_POINTS` of debt
+        let fee = i128::from(xasset.get_borrow_fee());
+        if target * (BASIS_POINTS + fee) <= kept * BASIS_POINTS {
+            return Err(Error::TargetUnreachable);
+        }
+        let swap = SwapClient::new(env, &Self::swap(env));
+        let xlm = xasset.xlm_sac();
+        let (rate, rate_div) = Self::xlm_rate(&xasset);
+
+        for _ in 0..max_steps {
+            let cdp = xasset.cdp(&lender);
+            if cdp.asset_lent == 0 {
+                return Err(Error::CDPHasNoDebt);
+            }
+            let ratio = i128::from(cdp.collateralization_ratio);
+            if ratio <= target || ratio <= min_ratio {
+                return Ok(cdp);
+            }
+            // Borrow so the ratio reaches the target once XLM bought at the worst price allowed
+            // is added, counting the borrowing fee as debt, capped by what can be borrowed
+            // before that XLM arrives without dropping below the minimum ratio
+            let debt = cdp.asset_lent;
+            let needed = debt * (ratio - target) * BASIS_POINTS
+                / (target * (BASIS_POINTS + fee) - kept * BASIS_POINTS);
+            let headroom =
+                debt * (ratio - min_ratio - 1) * BASIS_POINTS / (min_ratio * (BASIS_POINTS + fee));
+            let amount = needed.min(headroom);
+            if amount <= 0 {
+                return Ok(cdp);
+            }
+            xasset.borrow_xasset(&lender, &amount);
+            let min_out = amount * rate / rate_div * kept / BASIS_POINTS;
+            let bought = swap.swap_exact_in(&lender, &xasset.address, &xlm, &amount, &min_out);
+            xasset.add_collateral(&lender, &bought);
+        }
+        let cdp = xasset.cdp(&lender);
+        if cdp.collateralization_ratio > max_final_ratio {
+            return Err(Error::TargetNotReached);
+        }
+        Ok(cdp)
+    }
+
+    /// Raise `lender`'s CDP towards `target_ratio`, in basis points, by withdrawing XLM
+    /// collateral and swapping it for xAsset to repay debt, accepting up to `max_slippage` basis
+    /// points below the oracle price, in at most `max_steps` rounds. Each round withdraws no more
+    /// than the minimum collateralization ratio allows; swaps beating the worst price allowed
+    /// overshoot the target. The call fails unless it stops within rounding of the target or at
+    /// or above `min_final_ratio`.
+    ///
+    /// Repaying debt settles the CDP's accrued interest first, which the xAsset contract pulls
+    /// in XLM from `lender`, so `lender` must approve it to spend that XLM, as for `repay_debt`.
+    pub fn deleverage_cdp(
+        env: &Env,
+        lender: Address,
+        target_ratio: u32,
+        max_slippage: u32,
+        min_final_ratio: u32,
+        max_steps: u32,
+    ) -> Result<xasset::CDPContract, Error> {
+        lender.require_auth();
+        let xasset = Self::client(env);
+        let kept = Self::kept_share(max_slippage)?;
+        let min_ratio = i128::from(xasset.minimum_collateralization_ratio());
+        let target = i128::from(target_ratio);
+        if target < min_ratio {
+            return Err(Error::InvalidTarget);
+        }
+        // Selling collateral worth `w` repays at least `w * kept / BASIS_POINTS` of debt
+        if target * kept <= BASIS_POINTS * BASIS_POINTS {
+            return Err(Error::TargetUnreachable);
+        }
+        let swap = SwapClient::new(env, &Self::swap(env));
+        let xlm = xasset.xlm_sac();
+        let (rate, rate_div) = Self::xlm_rate(&xasset);
+
+        for _ in 0..max_steps {
+            let cdp = xasset.cdp(&lender);
+            let ratio = i128::from(cdp.collateralization_ratio);
+            if cdp.asset_lent == 0 || ratio >= target {
+                return Ok(cdp);
+            }
+            // Collateral value, in xAsset, to sell for the target, capped by what can be
+            // withdrawn without dropping below the minimum ratio
+            let debt = cdp.asset_lent;
+            let needed = (target - ratio) * debt * BASIS_POINTS
+                / (target * kept - BASIS_POINTS * BASIS_POINTS);
+            let headroom = (ratio - min_ratio - 1) * debt / BASIS_POINTS;
+            if needed <= 0 {
+                return Ok(cdp);
+            }
+            let value = needed.min(headroom);
+            let amount = (value * rate / rate_div).min(cdp.xlm_deposited);
+            if amount <= 0 {
+                break;
+            }
+            xasset.withdraw_collateral(&lender, &amount);
+            let min_out = value * kept / BASIS_POINTS;
+            let bought = swap.swap_exact_in(&lender, &xlm, &xasset.address, &amount, &min_out);
+            xasset.repay_debt(&lender, &bought.min(debt));
+        }
+        let cdp = xasset.cdp(&lender);
+        if cdp.asset_lent > 0 && cdp.collateralization_ratio < min_final_ratio {
+            return Err(Error::TargetNotReached);
+        }
+        Ok(cdp)
+    }
+}
+
+impl LeverageRouter {
+    fn client(env: &Env) -> xasset::Client<'_> {
+        xasset::Client::new(env, &Self::xasset(env))
+    }
+
+    /// Share of the oracle price, in basis points, a swap must at least return
+    fn kept_share(max_slippage: u32) -> Result<i128, Error> {
+        let max_slippage = i128::from(max_slippage);
+        if max_slippage > BASIS_POINTS {
+            return Err(Error::InvalidSlippage);
+        }
+        Ok(BASIS_POINTS - max_slippage)
+    }
+
+    /// Oracle rate of XLM per xAsset, as a numerator and denominator
+    fn xlm_rate(xasset: &xasset::Client) -> (i128, i128) {
+        let xlm_price = xasset.lastprice_xlm().price;
+        let xlm_decimals = xasset.decimals_xlm_feed();
+        let asset_price = xasset.lastprice_asset().price;
+        let asset_decimals = xasset.decimals_asset_feed();
+        if xlm_decimals >= asset_decimals {
+            (
+                asset_price * 10i128.pow(xlm_decimals - asset_decimals),
+                xlm_price,
+            )
+        } else {
+            (
+                asset_price,
+                xlm_price * 10i128.pow(asset_decimals - xlm_decimals),
+            )
+        }
+    }
+}
+
+mod test;
@@ -0,0 +1,169 @@
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{
+    String, Symbol, Vec, contract, contractimpl,
+    testutils::Address as _,
+    token::{StellarAssetClient, TokenClient},
+};
+
+mod data_feed {
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/data_feed.wasm");
+}
+
+/// Swaps at a fixed rate of XLM per xAsset, less a fee, out of its own inventory
+#[contract]
+pub struct MockSwap;
+
+#[contractimpl]
+impl MockSwap {
+    pub fn __constructor(env: &Env, xasset: Address, xlm_per_xasset: i128, fee: i128) {
+        env.storage()
+            .instance()
+            .set(&symbol_short!("CONFIG"), &(xasset, xlm_per_xasset, fee));
+    }
+
+    pub fn swap_exact_in(
+        env: Env,
+        from: Address,
+        token_in: Address,
+        token_out: Address,
+        amount_in: i128,
+        min_amount_out: i128,
+    ) -> i128 {
+        from.require_auth();
+        let (xasset, xlm_per_xasset, fee): (Address, i128, i128) = env
+            .storage()
+            .instance()
+            .get(&symbol_short!("CONFIG"))
+            .unwrap();
+        let amount_out = if token_in == xasset {
+            amount_in * xlm_per_xasset
+        } else {
+            amount_in / xlm_per_xasset
+        } * (BASIS_POINTS - fee)
+            / BASIS_POINTS;
+        assert!(amount_out >= min_amount_out, "slippage");
+        let this = env.current_contract_address();
+        TokenClient::new(&env, &token_in).transfer(&from, &this, &amount_in);
+        TokenClient::new(&env, &token_out).transfer(&this, &from, &amount_out);
+        amount_out
+    }
+}
+
+struct Setup<'a> {
+    xasset: xasset::Client<'a>,
+    xlm: StellarAssetClient<'a>,
+}
+
+fn create_xasset<'a>(e: &Env) -> Setup<'a> {
+    let xlm_sac = e
+        .register_stellar_asset_contract_v2(Address::generate(e))
+        .address();
+    let xlm = data_feed::Asset::Other(Symbol::new(e, "XLM"));
+    let usdt = data_feed::Asset::Other(Symbol::new(e, "USDT"));
+    let datafeed = e.register(
+        data_feed::WASM,
+        (
+            Address::generate(e),
+            Vec::from_array(e, [xlm.clone(), usdt.clone()]),
+            usdt.clone(),
+            14u32,
+            300u32,
+        ),
+    );
+    let datafeed_client = data_feed::Client::new(e, &datafeed);
+    datafeed_client.set_asset_price(&xlm, &10_000_000_000_000, &1000);
+    datafeed_client.set_asset_price(&usdt, &100_000_000_000_000, &1000);
+    let contract_id = e.register(
+        xasset::WASM,
+        (
+            Address::generate(e),
+            xlm_sac.clone(),
+            datafeed.clone(),
+            datafeed,
+            Symbol::new(e, "USDT"),
+            11_000u32,
+            String::from_str(e, "United States Dollar xAsset"),
+            String::from_str(e, "xUSD"),
+            7u32,
+            0u32,
+        ),
+    );
+    Setup {
+        xasset: xasset::Client::new(e, &contract_id),
+        xlm: StellarAssetClient::new(e, &xlm_sac),
+    }
+}
+
+#[test]
+fn test_leverage_and_deleverage() {
+    let e = Env::default();
+    e.mock_all_auths();
+    // Every xasset call instantiates its wasm afresh here, so a few rounds outgrow the default
+    // memory budget
+    e.cost_estimate().budget().reset_unlimited();
+    let Setup { xasset, xlm } = create_xasset(&e);
+
+    // The swap trades at the oracle price of 10 XLM per xUSD, less 0.5%
+    let swap_id = e.register(MockSwap, (xasset.address.clone(), 10i128, 50i128));
+    xlm.mint(&swap_id, &10_000_000_000_000);
+    xasset.mint(&swap_id, &1_000_000_000_000);
+    let router_id = e.register(
+        LeverageRouter,
+        (
+            Address::generate(&e),
+            xasset.address.clone(),
+            swap_id.clone(),
+        ),
+    );
+    let router = LeverageRouterClient::new(&e, &router_id);
+
+    // 10,000 XLM back 200 xUSD at 500%
+    let alice = Address::generate(&e);
+    xlm.mint(&alice, &100_000_000_000);
+    xasset.open_cdp(&alice, &100_000_000_000, &2_000_000_000);
+    assert_eq!(xasset.cdp(&alice).collateralization_ratio, 50_000);
+
+    // Targets must respect the minimum ratio, and swaps the slippage allowance
+    let result = router.try_leverage_cdp(&alice, &10_000, &100, &u32::MAX, &4);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidTarget);
+    let result = router.try_leverage_cdp(&alice, &20_000, &10_001, &u32::MAX, &4);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidSlippage);
+    assert!(
+        router
+            .try_leverage_cdp(&alice, &20_000, &10, &u32::MAX, &4)
+            .is_err()
+    );
+
+    // Without fees or slippage, borrowing at 100% never lowers the ratio
+    xasset.set_min_collat_ratio(&10_000);
+    let result = router.try_leverage_cdp(&alice, &10_000, &0, &u32::MAX, &4);
+    assert_eq!(result.unwrap_err().unwrap(), Error::TargetUnreachable);
+    xasset.set_min_collat_ratio(&11_000);
+
+    // Leveraging close to the minimum ratio takes more rounds than a call makes
+    let result = router.try_leverage_cdp(&alice, &11_500, &100, &11_500, &4);
+    assert_eq!(result.unwrap_err().unwrap(), Error::TargetNotReached);
+
+    // A single round cannot reach 200% from 500% either
+    let result = router.try_leverage_cdp(&alice, &20_000, &100, &20_000, &1);
+    assert_eq!(result.unwrap_err().unwrap(), Error::TargetNotReached);
+
+    // Leveraging to 200% borrows more and buys XLM with it
+    let cdp = router.leverage_cdp(&alice, &20_000, &100, &20_000, &4);
+    assert!(cdp.collateralization_ratio >= 20_000 && cdp.collateralization_ratio < 21_000);
+    assert!(cdp.asset_lent > 4_000_000_000);
+    assert!(cdp.xlm_deposited > 100_000_000_000);
+    assert_eq!(xlm.balance(&alice), 0);
+
+    // Deleveraging to 300% sells XLM to repay debt
+    let result = router.try_deleverage_cdp(&alice, &30_000, &7_000, &0, &4);
+    assert_eq!(result.unwrap_err().unwrap(), Error::TargetUnreachable);
+    let leveraged = cdp;
+    let cdp = router.deleverage_cdp(&alice, &30_000, &100, &30_000, &4);
+    assert!(cdp.collateralization_ratio >= 30_000 && cdp.collateralization_ratio < 31_000);
+    assert!(cdp.asset_lent < leveraged.asset_lent);
+    assert!(cdp.xlm_deposited < leveraged.xlm_deposited);
+}
@@ -0,0 +1,33 @@
+use soroban_sdk::{Address, contractevent};
+
+// SEP-41 token events, published on every balance and allowance change
+
+#[contractevent(data_format = "single-value")]
+pub struct Transfer {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub to: Address,
+    pub amount: i128,
+}
+
+/// Transfer to a muxed address, carrying the recipient's muxed ID in the data
+#[contractevent(topics = ["transfer"], data_format = "map")]
+pub struct TransferMuxed {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub to: Address,
+    pub to_muxed_id: u64,
+    pub amount: i128,
+}
+
+#[contractevent(data_format = "vec")]
+pub struct Approve {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub spender: Address,
+    pub amount: i128,
+    pub live_until_ledger: u32,
+}
@@ -0,0 +1,211 @@
+#![no_std]
+use soroban_sdk::{
+    Address, Env, MuxedAddress, String, assert_with_error, contract, contracterror, contractimpl,
+    contracttype, panic_with_error, symbol_short, token::TokenInterface,
+};
+
+mod events;
+
+pub mod xasset {
+    #![allow(clippy::too_many_arguments)]
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/xasset.wasm");
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    /// Value must be greater than 0
+    ValueNotPositive = 1,
+
+    /// Insufficient allowance; spender must call `approve` first
+    InsufficientAllowance = 2,
+
+    /// Allowance must not expire before the current ledger
+    InvalidLedgerSequence = 3,
+
+    /// Receipts cannot be burned; withdraw from the stability pool instead
+    BurnNotSupported = 4,
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct Allowance {
+    pub amount: i128,
+    pub live_until_ledger: u32,
+}
+
+#[contracttype]
+pub enum DataKey {
+    /// Allowance of a spender, by owner and spender
+    Allowance(Address, Address),
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct ReceiptStorage {
+    /// xAsset contract whose stability pool deposits this token represents
+    xasset: Address,
+    /// Name of the token
+    name: String,
+    /// Symbol of the token
+    symbol: String,
+}
+
+const STORAGE: soroban_sdk::Symbol = symbol_short!("STORAGE");
+
+/// SEP-41 token representing stability pool deposits of an xAsset contract. A balance is the
+/// holder's compounded deposit, derived from the pool's product constant, and moving it moves the
+/// deposit's share of pending rewards too. The xAsset contract keeps all pool accounting; it must
+/// have this contract set as its pool receipt.
+#[contract]
+pub struct PoolReceiptContract;
+
+#[contractimpl]
+impl PoolReceiptContract {
+    pub fn __constructor(env: &Env, xasset: Address, name: String, symbol: String) {
+        env.storage().instance().set(
+            &STORAGE,
+            &ReceiptStorage {
+                xasset,
+                name,
+                symbol,
+            },
+        );
+    }
+
+    /// Return the xAsset contract whose stability pool deposits this token represents
+    pub fn xasset(env: &Env) -> Address {
+        Self::get_state(env).xasset
+    }
+}
+
+impl PoolReceiptContract {
+    fn get_state(env: &Env) -> ReceiptStorage {
+        env.storage().instance().get(&STORAGE).unwrap()
+    }
+
+    fn pool(env: &Env) -> xasset::Client<'_> {
+        xasset::Client::new(env, &Self::get_state(env).xasset)
+    }
+
+    fn spend_allowance(env: &Env, from: Address, spender: Address, amount: i128) {
+        let allowance = Self::allowance(env.clone(), from.clone(), spender.clone());
+        assert_with_error!(env, allowance >= amount, Error::InsufficientAllowance);
+        let key = DataKey::Allowance(from, spender);
+        let mut stored: Allowance = env.storage().persistent().get(&key).unwrap();
+        stored.amount -= amount;
+        env.storage().persistent().set(&key, &stored);
+    }
+}
+
+#[contractimpl]
+impl TokenInterface for PoolReceiptContract {
+    /// Return the allowance for `spender` to transfer from `from`.
+    fn allowance(env: Env, from: Address, spender: Address) -> i128 {
+        let allowance: Option<Allowance> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Allowance(from, spender));
+        match allowance {
+            Some(a) if env.ledger().sequence() <= a.live_until_ledger => a.amount,
+            _ => 0,
+        }
+    }
+
+    /// Set the allowance by `amount` for `spender` to transfer from `from`
+    fn approve(env: Env, from: Address, spender: Address, amount: i128, live_until_ledger: u32) {
+        from.require_auth();
+        assert_with_error!(env, amount >= 0, Error::ValueNotPositive);
+        assert_with_error!(
+            env,
+            live_until_ledger >= env.ledger().sequence(),
+            Error::InvalidLedgerSequence
+        );
+        let key = DataKey::Allowance(from.clone(), spender.clone());
+        env.storage().persistent().set(
+            &key,
+            &Allowance {
+                amount,
+                live_until_ledger,
+            },
+        );
+        let max_ttl = env.storage().max_ttl();
+        env.storage()
+            .persistent()
+            .extend_ttl(&key, max_ttl, max_ttl);
+        events::Approve {
+            from,
+            spender,
+            amount,
+            live_until_ledger,
+        }
+        .publish(&env);
+    }
+
+    /// Return the compounded stability pool deposit of `id`
+    fn balance(env: Env, id: Address) -> i128 {
+        match Self::pool(&env).try_get_staker_deposit_amount(&id) {
+            Ok(Ok(amount)) => amount,
+            _ => 0,
+        }
+    }
+
+    /// Transfer `amount` of `from`'s deposit to `to`, with its share of pending rewards
+    fn transfer(env: Env, from: Address, to: MuxedAddress, amount: i128) {
+        from.require_auth();
+        let to_address = to.address();
+        Self::pool(&env).transfer_stake(&from, &to_address, &amount);
+        match to.id() {
+            Some(to_muxed_id) => events::TransferMuxed {
+                from,
+                to: to_address,
+                to_muxed_id,
+                amount,
+            }
+            .publish(&env),
+            None => events::Transfer {
+                from,
+                to: to_address,
+                amount,
+            }
+            .publish(&env),
+        }
+    }
+
+    /// Transfer `amount` of `from`'s deposit to `to`, consuming the allowance of `spender`
+    fn transfer_from(env: Env, spender: Address, from: Address, to: Address, amount: i128) {
+        spender.require_auth();
+        assert_with_error!(env, amount > 0, Error::ValueNotPositive);
+        Self::spend_allowance(&env, from.clone(), spender, amount);
+        Self::pool(&env).transfer_stake(&from, &to, &amount);
+        events::Transfer { from, to, amount }.publish(&env);
+    }
+
+    /// Not supported; withdraw from the stability pool instead
+    fn burn(env: Env, _from: Address, _amount: i128) {
+        panic_with_error!(env, Error::BurnNotSupported);
+    }
+
+    /// Not supported; withdraw from the stability pool instead
+    fn burn_from(env: Env, _spender: Address, _from: Address, _amount: i128) {
+        panic_with_error!(env, Error::BurnNotSupported);
+    }
+
+    /// Return the number of decimals, the same as the xAsset's
+    fn decimals(env: Env) -> u32 {
+        Self::pool(&env).decimals()
+    }
+
+    /// Return the name for this token
+    fn name(env: Env) -> String {
+        Self::get_state(&env).name
+    }
+
+    /// Return the symbol for this token
+    fn symbol(env: Env) -> String {
+        Self::get_state(&env).symbol
+    }
+}
+
+mod test;
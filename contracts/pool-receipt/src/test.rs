@@ -0,0 +1,191 @@
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{
+    IntoVal, Symbol, Val, Vec, map,
+    testutils::{Address as _, Events, Ledger, MuxedAddress as _},
+    token::{StellarAssetClient, TokenClient},
+    vec,
+};
+
+mod data_feed {
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/data_feed.wasm");
+}
+
+fn create_xasset<'a>(e: &Env, xlm_sac: &Address) -> xasset::Client<'a> {
+    let xlm = data_feed::Asset::Other(Symbol::new(e, "XLM"));
+    let usdt = data_feed::Asset::Other(Symbol::new(e, "USDT"));
+    let datafeed = e.register(
+        data_feed::WASM,
+        (
+            Address::generate(e),
+            Vec::from_array(e, [xlm.clone(), usdt.clone()]),
+            usdt.clone(),
+            14u32,
+            300u32,
+        ),
+    );
+    let datafeed_client = data_feed::Client::new(e, &datafeed);
+    datafeed_client.set_asset_price(&xlm, &10_000_000_000_000, &1000);
+    datafeed_client.set_asset_price(&usdt, &100_000_000_000_000, &1000);
+    let contract_id = e.register(
+        xasset::WASM,
+        (
+            Address::generate(e),
+            xlm_sac.clone(),
+            datafeed.clone(),
+            datafeed,
+            Symbol::new(e, "USDT"),
+            11_000u32,
+            String::from_str(e, "United States Dollar xAsset"),
+            String::from_str(e, "xUSD"),
+            7u32,
+            0u32,
+        ),
+    );
+    xasset::Client::new(e, &contract_id)
+}
+
+#[test]
+fn test_receipts_carry_rewards() {
+    let e = Env::default();
+    e.mock_all_auths();
+    e.ledger().set_sequence_number(100);
+    let xlm_sac = e
+        .register_stellar_asset_contract_v2(Address::generate(&e))
+        .address();
+    let xlm = StellarAssetClient::new(&e, &xlm_sac);
+    let pool = create_xasset(&e, &xlm_sac);
+    let receipt_id = e.register(
+        PoolReceiptContract,
+        (
+            pool.address.clone(),
+            String::from_str(&e, "Stability Pool xUSD"),
+            String::from_str(&e, "spxUSD"),
+        ),
+    );
+    let receipt = PoolReceiptContractClient::new(&e, &receipt_id);
+    assert_eq!(receipt.decimals(), 7);
+
+    // Receipts only move deposits once the xAsset contract knows about them
+    let alice = Address::generate(&e);
+    let carol = Address::generate(&e);
+    xlm.mint(&alice, &100_0000000);
+    xlm.mint(&carol, &100_0000000);
+    pool.mint(&alice, &2_000_0000000);
+    pool.stake(&alice, &2_000_0000000);
+    assert!(receipt.try_transfer(&alice, &carol, &1).is_err());
+    pool.set_pool_receipt(&Some(receipt_id.clone()));
+    assert_eq!(receipt.balance(&alice), 2_000_0000000);
+    assert_eq!(receipt.balance(&carol), 0);
+
+    // A liquidation halves Alice's deposit and rewards her with its collateral
+    let bob = Address::generate(&e);
+    xlm.mint(&bob, &20_000_0000000);
+    pool.open_cdp(&bob, &20_000_0000000, &1_000_0000000);
+    pool.set_min_collat_ratio(&1_000_000);
+    pool.freeze_cdp(&bob);
+    pool.liquidate_cdp(&bob);
+    assert_eq!(receipt.balance(&alice), 1_000_0000000);
+    let rewards = pool.get_available_assets(&alice).available_rewards;
+    assert_eq!(rewards, 20_000_0000000);
+
+    // Receipts sent to a new staker take their share of pending rewards along
+    receipt.transfer(&alice, &carol, &400_0000000);
+    let events = e.events().all();
+    assert!(
+        events.contains((
+            pool.address.clone(),
+            (
+                Symbol::new(&e, "stake_transfer"),
+                alice.clone(),
+                carol.clone()
+            )
+                .into_val(&e),
+            map![
+                &e,
+                (
+                    Symbol::new(&e, "amount"),
+                    IntoVal::<Env, Val>::into_val(&400_0000000i128, &e)
+                ),
+            ]
+            .into_val(&e),
+        ))
+    );
+    assert!(events.contains((
+        receipt_id.clone(),
+        (symbol_short!("transfer"), alice.clone(), carol.clone()).into_val(&e),
+        400_0000000i128.into_val(&e),
+    )));
+    assert_eq!(receipt.balance(&alice), 600_0000000);
+    assert_eq!(receipt.balance(&carol), 400_0000000);
+    assert_eq!(
+        pool.get_available_assets(&alice).available_rewards,
+        rewards * 3 / 5
+    );
+    assert_eq!(
+        pool.get_available_assets(&carol).available_rewards,
+        rewards * 2 / 5
+    );
+
+    // Sent to an existing staker, both its own and the moved rewards are paid out
+    let spender = Address::generate(&e);
+    receipt.approve(&carol, &spender, &100_0000000, &200);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                receipt_id.clone(),
+                (Symbol::new(&e, "approve"), carol.clone(), spender.clone()).into_val(&e),
+                (100_0000000i128, 200u32).into_val(&e),
+            ),
+        ]
+    );
+    receipt.transfer_from(&spender, &carol, &alice, &100_0000000);
+    assert_eq!(receipt.allowance(&carol, &spender), 0);
+    assert_eq!(receipt.balance(&alice), 700_0000000);
+    assert_eq!(receipt.balance(&carol), 300_0000000);
+    assert_eq!(pool.get_available_assets(&alice).available_rewards, 0);
+    assert_eq!(
+        pool.get_available_assets(&carol).available_rewards,
+        rewards * 3 / 10
+    );
+    assert_eq!(
+        TokenClient::new(&e, &xlm_sac).balance(&alice),
+        100_0000000 - pool.get_pool_fees().stake_fee + rewards * 7 / 10
+    );
+
+    // Transfers to a muxed address carry its ID
+    let muxed = MuxedAddress::generate(&e);
+    let dave = muxed.address();
+    receipt.transfer(&carol, &muxed, &100_0000000);
+    let events = e.events().all();
+    assert_eq!(
+        events.slice(events.len() - 1..),
+        vec![
+            &e,
+            (
+                receipt_id.clone(),
+                (symbol_short!("transfer"), carol.clone(), dave.clone()).into_val(&e),
+                map![
+                    &e,
+                    (
+                        Symbol::new(&e, "amount"),
+                        IntoVal::<Env, Val>::into_val(&100_0000000i128, &e)
+                    ),
+                    (
+                        Symbol::new(&e, "to_muxed_id"),
+                        IntoVal::<Env, Val>::into_val(&muxed.id().unwrap(), &e)
+                    ),
+                ]
+                .into_val(&e),
+            )
+        ]
+    );
+    assert_eq!(receipt.balance(&dave), 100_0000000);
+
+    assert!(receipt.try_transfer(&carol, &alice, &200_0000001).is_err());
+    assert!(receipt.try_burn(&alice, &1).is_err());
+}
@@ -1,7 +1,9 @@
-use soroban_sdk::{Address, Env, String, Symbol, Vec, contracttype};
+use soroban_sdk::{Address, Env, Map, String, Symbol, Vec, contractclient, contracttype};
 
 use crate::{
     Error, PriceData,
+    rate_model::{RateInputs, RateModel},
+    stability_pool::PoolFees,
     storage::{Interest, InterestDetail},
 };
 
@@ -31,6 +33,71 @@ pub struct CDPContract {
     pub collateralization_ratio: u32,
     pub accrued_interest: Interest,
     pub last_interest_time: u64,
+    /// Annual interest rate chosen by the owner; `None` follows the protocol's rate
+    pub interest_rate: Option<u32>,
+    pub rate_adjusted_time: u64,
+    /// Collateral held besides XLM, by SAC token address
+    pub collateral: Map<Address, i128>,
+}
+
+#[contracttype(export = false)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Collateral CDPs may hold besides XLM, registered by its SAC token address
+pub struct CollateralType {
+    /// Oracle contract for the collateral's price feed
+    pub oracle: Address,
+    /// Oracle asset ID of the collateral
+    pub asset: Symbol,
+    /// Share of the collateral's value, in basis points, counted towards a CDP's ratio
+    pub collateral_factor: u32,
+    /// Most principal that may be lent against CDPs holding the collateral
+    pub debt_ceiling: i128,
+}
+
+/// Contract registering the collateral types CDPs may hold besides XLM; only its client is used
+#[allow(dead_code)]
+#[contractclient(name = "CollateralRegistryClient")]
+pub trait CollateralRegistry {
+    /// Get the registered collateral types, by SAC token address
+    fn collateral_types(env: Env) -> Map<Address, CollateralType>;
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+/// Totals of a registered collateral type
+pub struct CollateralTotals {
+    /// Collateral held across all CDPs
+    pub deposited: i128,
+    /// Principal of all CDPs holding the collateral, counted against its debt ceiling
+    pub debt: i128,
+    /// Collateral liquidated to stability pool stakers and not yet claimed
+    pub pool_rewards: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// How far a CDP is from the minimum collateralization ratio, at current prices
+pub struct CDPHealth {
+    /// XLM price, in the XLM oracle's decimals, below which the CDP drops under the minimum
+    /// ratio; `None` if it has no debt, no XLM net of interest, or other collateral enough alone
+    pub liquidation_price: Option<i128>,
+    /// Most xAsset that can be borrowed, leaving room for the borrowing fee
+    pub max_borrow: i128,
+    /// Most XLM collateral that can be withdrawn
+    pub max_withdraw: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Asset CDP interest is paid in, and where it goes
+pub enum InterestPayment {
+    /// Interest is converted to XLM at oracle prices and transferred to this contract
+    Xlm,
+    /// Interest is paid in xAsset, which is burned
+    Burn,
+    /// Interest is paid in xAsset, which is distributed to stability pool stakers.
+    /// While the pool is empty, it is burned instead.
+    StabilityPool,
 }
 
 // TODO was a subcontract
@@ -73,9 +140,32 @@ pub trait IsCollateralized {
         asset_lent: i128,
     ) -> Result<(), Error>;
 
+    /// Open a new CDP holding a registered collateral type instead of XLM (see
+    /// [`IsCDPAdmin::set_collateral_registry`]). Its ratio counts the collateral's value weighted by
+    /// the type's collateral factor, and its debt counts against the type's debt ceiling.
+    fn open_cdp_in(
+        env: &Env,
+        lender: Address,
+        collateral_type: Address,
+        collateral: i128,
+        asset_lent: i128,
+    ) -> Result<(), Error>;
+
     /// Retrieves the CDP information for a specific lender
     fn cdp(env: &Env, lender: Address) -> Result<CDPContract, Error>;
 
+    /// Get a CDP's liquidation price and how much it can borrow or withdraw
+    fn cdp_health(env: &Env, lender: Address) -> Result<CDPHealth, Error>;
+
+    /// Get a CDP's collateralization ratio after depositing `xlm` and borrowing `asset_lent`,
+    /// including the borrowing fee. Negative amounts withdraw and repay.
+    fn projected_cdp_ratio(
+        env: &Env,
+        lender: Address,
+        xlm: i128,
+        asset_lent: i128,
+    ) -> Result<u32, Error>;
+
     /// Freeze a CDP if its Collateralization Ratio (CR) is below the xAsset's Minimum Collateralization Ratio (MCR).
     /// A frozen CDP is no longer usable or interactable by its former owner.
     fn freeze_cdp(env: &Env, lender: Address) -> Result<(), Error>;
@@ -83,10 +173,36 @@ pub trait IsCollateralized {
     /// Increase the Collateralization Ratio (CR) by depositing more collateral to an existing CDP.
     fn add_collateral(env: &Env, lender: Address, amount: i128) -> Result<(), Error>;
 
+    /// Add collateral to a CDP as one of its owner's operators (see `set_operator`), paying from
+    /// the operator's balance.
+    fn add_collateral_for(
+        env: &Env,
+        operator: Address,
+        lender: Address,
+        amount: i128,
+    ) -> Result<(), Error>;
+
+    /// Add a registered collateral type to an existing CDP. A CDP may hold several types, and
+    /// XLM, at once; once it holds a type, all its debt counts against the type's debt ceiling.
+    fn add_collateral_in(
+        env: &Env,
+        lender: Address,
+        collateral_type: Address,
+        amount: i128,
+    ) -> Result<(), Error>;
+
     /// Lower the Collateralization Ratio (CR) by withdrawing part or all of the collateral from a CDP.
     /// Collateral cannot be withdrawn if it brings CR below the xAsset's MCR.
     fn withdraw_collateral(env: &Env, lender: Address, amount: i128) -> Result<(), Error>;
 
+    /// Withdraw part or all of a registered collateral type from a CDP, like `withdraw_collateral`
+    fn withdraw_collateral_in(
+        env: &Env,
+        lender: Address,
+        collateral_type: Address,
+        amount: i128,
+    ) -> Result<(), Error>;
+
     /// Lowers the Collateralization Ratio (CR) by minting additional xAsset against existing collateral.
     /// More xAsset cannot be minted if it brings CR below the xAsset's MCR.
     fn borrow_xasset(env: &Env, lender: Address, amount: i128) -> Result<(), Error>;
@@ -95,7 +211,10 @@ pub trait IsCollateralized {
     /// When the debt is repaid, the xAsset is burned (i.e., destroyed).
     /// More xAsset cannot be burned than debt owed by the CDP.
     ///
-    /// **Repayment Workflow:**
+    /// If interest is paid in xAsset (see [`IsCDPAdmin::set_interest_payment`]), accrued interest
+    /// is taken from the lender's xAsset balance on top of `amount`, and no approval is needed.
+    ///
+    /// **Repayment Workflow when interest is paid in XLM:**
     /// 1. Call [`get_accrued_interest`] to get the latest accrued interest, including `approval_amount`.
     /// 2. Call the XLM SAC's `approve` function to approve spending the required XLM:
     ///    ```
@@ -116,6 +235,15 @@ pub trait IsCollateralized {
     /// This ensures the proper interest payment is authorized and prevents race conditions.
     fn repay_debt(env: &Env, lender: Address, amount: i128) -> Result<(), Error>;
 
+    /// Repay a CDP's debt as one of its owner's operators (see `set_operator`), paying the debt
+    /// and accrued interest from the operator's balances, as [`repay_debt`] would from the owner's.
+    fn repay_debt_for(
+        env: &Env,
+        operator: Address,
+        lender: Address,
+        amount: i128,
+    ) -> Result<(), Error>;
+
     /// Liquidates a frozen CDP. Upon liquidation, CDP debt is repaid by withdrawing xAsset from a Stability Pool.
     /// As debt is repaid, collateral is withdrawn from the CDP.
     /// If all debt is repaid, then all collateral is withdrawn, and the CDP is closed.
@@ -123,12 +251,30 @@ pub trait IsCollateralized {
 
     /// Merge two or more frozen CDPs into one CDP.
     /// Upon merging, all but one of the CDPs are closed, and their debt and collateral are transferred into a single CDP.
+    /// All CDPs must share the same interest rate, which the merged CDP keeps.
     fn merge_cdps(env: &Env, lenders: Vec<Address>) -> Result<(), Error>;
 
     /// Close a CDP when its Collateralization Ratio (CR) value is zero, having no collateral or debt.
     /// A CDP is closed after all its debt is repaid and its collateral is withdrawn.
     fn close_cdp(env: &Env, lender: Address) -> Result<(), Error>;
 
+    /// Move `xlm`, `collateral` of registered types and `asset_lent` of debt out of `from`'s CDP
+    /// into a new CDP owned by `to`, who must not have one yet. Only callable by the CDP manager
+    /// contract (see [`IsCDPAdmin::set_cdp_manager`]), which must have the consent of both owners.
+    /// Frozen CDPs cannot move.
+    ///
+    /// Moving all of a CDP transfers it as it stands. Moving part of it splits an open CDP in two:
+    /// both must keep some collateral and meet the xAsset's MCR, and accrued interest is divided
+    /// in proportion to the debt.
+    fn move_cdp(
+        env: &Env,
+        from: Address,
+        to: Address,
+        xlm: i128,
+        collateral: Map<Address, i128>,
+        asset_lent: i128,
+    ) -> Result<(), Error>;
+
     /// Update and returns the accrued interest on a CDP.
     ///
     /// Returns an [`InterestDetail`] struct, including:
@@ -141,7 +287,9 @@ pub trait IsCollateralized {
 
     /// Pay the accrued interest (but not principal) on a CDP.
     ///
-    /// - Interest is paid in XLM, not in the principal token.
+    /// - `amount` is denominated in the principal token.
+    /// - Interest is paid in XLM or in the principal token, as set by
+    ///   [`IsCDPAdmin::set_interest_payment`].
     /// - To determine the current interest due (in both principal token and XLM),
     ///   call [`get_accrued_interest`], which returns both values.
     /// - Use the `amount_in_xlm` and/or `approval_amount` from that result when
@@ -149,6 +297,21 @@ pub trait IsCollateralized {
     ///
     /// Note: This function is for paying only the interest; to repay principal, use [`repay_debt`].
     fn pay_interest(env: &Env, lender: Address, amount: i128) -> Result<CDPContract, Error>;
+
+    /// Choose the annual interest rate, in basis points, charged on this CDP in place of the
+    /// protocol's rate, or pass `None` to follow the protocol's rate again. The rate must be
+    /// within the bounds set by the admin.
+    ///
+    /// Changing the rate again within the adjustment cooldown (default: 7 days) is charged as
+    /// accrued interest: the new rate's interest on the CDP's debt over the whole cooldown.
+    ///
+    /// Note: redemptions, which would let borrowers price their redemption risk through this
+    /// rate, are not implemented yet.
+    fn set_cdp_interest_rate(
+        env: &Env,
+        lender: Address,
+        rate: Option<u32>,
+    ) -> Result<CDPContract, Error>;
 }
 
 /// Interface-only subcontract for a contract that implements an asset which can have
@@ -169,7 +332,9 @@ pub trait IsCDPAdmin {
     /// Set minimum collateralization ration. Only callable by admin.
     fn set_min_collat_ratio(env: &Env, to: u32) -> u32;
 
-    /// Set annual interest rate
+    /// Set annual interest rate. Interest accrued so far is settled at the previous rate,
+    /// so the new rate only applies going forward. Under a rate model other than
+    /// [`RateModel::Fixed`], the rate is re-derived from the model at the next accrual.
     fn set_interest_rate(env: &Env, new_rate: u32) -> u32;
 
     /// Get annual interest rate
@@ -178,6 +343,115 @@ pub trait IsCDPAdmin {
     /// Get total interest collected
     fn get_total_interest_collected(env: &Env) -> i128;
 
+    /// Get the cumulative interest index, scaled by 10^12. Debt grows by the ratio between
+    /// the index now and the index when it was last stored.
+    fn get_interest_index(env: &Env) -> i128;
+
+    /// Set the range of annual rates, in basis points, borrowers may choose for their CDPs.
+    /// Default: 0% to 250%. Only callable by admin.
+    fn set_cdp_rate_bounds(env: &Env, min: u32, max: u32);
+
+    /// Set the cooldown, in seconds, after a CDP's rate change during which changing it again
+    /// is charged a fee; at most a year. Only callable by admin.
+    fn set_rate_adjustment_cooldown(env: &Env, seconds: u64) -> u64;
+
+    /// Set how the annual interest rate is determined. Under any model other than
+    /// [`RateModel::Fixed`], the rate is re-derived from the model each time interest accrues.
+    /// Only callable by admin.
+    fn set_rate_model(env: &Env, model: RateModel);
+
+    /// Get the interest rate model
+    fn get_rate_model(env: &Env) -> RateModel;
+
+    /// Get the interest rate model's current utilization and the rate it gives
+    fn get_rate_inputs(env: &Env) -> Result<RateInputs, Error>;
+
+    /// Get total outstanding interest across all open and insolvent CDPs, in xAsset, including
+    /// those with a borrower-chosen rate
+    fn get_total_outstanding_interest(env: &Env) -> i128;
+
+    /// Set the one-time borrowing fee floor and cap, in basis points, charged whenever
+    /// `open_cdp` or `borrow_xasset` mints xAsset. A cap of 0 disables the fee. Only callable by admin.
+    fn set_borrow_fee(env: &Env, floor: u32, cap: u32);
+
+    /// Set the base rate, in basis points, added on top of the borrowing fee floor. It decays
+    /// by half every `base_rate_half_life` seconds. Only callable by admin.
+    ///
+    /// Redemptions, which raise the base rate in Liquity, are not implemented yet; until they are,
+    /// the base rate can only be raised here.
+    fn set_base_rate(env: &Env, rate: u32) -> u32;
+
+    /// Set the half-life, in seconds, of the base rate's decay. Default: 12 hours. Only callable by admin.
+    fn set_base_rate_half_life(env: &Env, seconds: u64) -> u64;
+
+    /// Set the recipient of borrowing fees. If `None`, fees are distributed to stability pool
+    /// stakers, or waived while the pool is empty. Only callable by admin.
+    fn set_fee_recipient(env: &Env, to: Option<Address>);
+
+    /// Get the current borrowing fee in basis points, including the decayed base rate
+    fn get_borrow_fee(env: &Env) -> u32;
+
+    /// Set the asset CDP interest is paid in, and where xAsset interest goes. Default:
+    /// [`InterestPayment::Xlm`]. Only callable by admin.
+    fn set_interest_payment(env: &Env, payment: InterestPayment);
+
+    /// Get the asset CDP interest is paid in
+    fn get_interest_payment(env: &Env) -> InterestPayment;
+
+    /// Set the share, in basis points, of interest collected in XLM that is distributed to
+    /// stability pool stakers; the rest is retained for the treasury. Default: 0. Only callable by admin.
+    fn set_interest_share(env: &Env, share: u32) -> u32;
+
+    /// Get the share, in basis points, of interest distributed to stability pool stakers
+    fn get_interest_share(env: &Env) -> u32;
+
+    /// Get interest, in XLM, retained for the treasury
+    fn get_treasury_interest(env: &Env) -> i128;
+
+    /// Set the treasury that collected fees and interest are swept to. Only callable by admin.
+    fn set_treasury(env: &Env, to: Address);
+
+    /// Get the treasury, if set
+    fn get_treasury(env: &Env) -> Option<Address>;
+
+    /// Get the XLM and xAsset that [`sweep_to_treasury`](IsCDPAdmin::sweep_to_treasury) would
+    /// transfer now
+    fn get_sweepable(env: &Env) -> (i128, i128);
+
+    /// Transfer collected stability pool fees and the treasury's share of interest to the
+    /// treasury, returning the XLM and xAsset transferred. Never transfers XLM backing CDP
+    /// collateral, stability pool rewards, or unstake returns owed to stakers. Only callable by
+    /// admin, which may be a governance contract.
+    fn sweep_to_treasury(env: &Env) -> Result<(i128, i128), Error>;
+
+    /// Set the stability pool fees, in XLM. The unstake return must not exceed the stake fee;
+    /// it applies to positions opened from now on. Only callable by admin.
+    fn set_pool_fees(env: &Env, fees: PoolFees);
+
+    /// Set the pool receipt contract, a SEP-41 token representing stability pool deposits, which
+    /// is the only way deposits move between stakers. If `None`, they cannot. Only callable by admin.
+    fn set_pool_receipt(env: &Env, receipt: Option<Address>);
+
+    /// Get the pool receipt contract, if set
+    fn get_pool_receipt(env: &Env) -> Option<Address>;
+
+    /// Set the CDP manager contract, the only way CDPs move between owners. If `None`, they
+    /// cannot. Only callable by admin.
+    fn set_cdp_manager(env: &Env, manager: Option<Address>);
+
+    /// Get the CDP manager contract, if set
+    fn get_cdp_manager(env: &Env) -> Option<Address>;
+
+    /// Set the collateral registry contract, whose types CDPs may hold besides XLM. If `None`,
+    /// CDPs may only hold XLM. Only callable by admin.
+    fn set_collateral_registry(env: &Env, registry: Option<Address>);
+
+    /// Get the collateral registry contract, if set
+    fn get_collateral_registry(env: &Env) -> Option<Address>;
+
+    /// Get the totals of a registered collateral type
+    fn get_collateral_totals(env: &Env, token: Address) -> CollateralTotals;
+
     /// Report the version of this contract
     fn version(env: &Env) -> String;
 }
@@ -31,7 +31,8 @@ pub enum Error {
     /// Cannot close CDP with outstanding debt
     OutstandingDebt = 9,
 
-    /// "At least two CDPs are required for merging" or "All CDPs must be frozen to merge"
+    /// "At least two CDPs are required for merging", "All CDPs must be frozen to merge" or "All CDPs
+    /// must share an interest rate to merge"
     InvalidMerge = 10,
 
     /// "CDP must be frozen to be liquidated" or "Debt and collateral must be positive"
@@ -99,4 +100,56 @@ pub enum Error {
 
     // Cannot transfer to self
     CannotTransferToSelf = 32,
+
+    /// Borrowing fee floor must not exceed its cap, rates must not exceed 100%, and the half-life must be positive
+    InvalidBorrowFee = 33,
+
+    /// Rate model's minimum rate must not exceed its maximum, and its kink must not exceed 100%
+    InvalidRateModel = 34,
+
+    /// Interest rate is outside the allowed bounds
+    InvalidInterestRate = 35,
+
+    /// Interest share must not exceed 100%
+    InvalidInterestShare = 36,
+
+    /// Treasury must be set before sweeping to it
+    TreasuryNotSet = 37,
+
+    /// Stability pool fees must not be negative, and the unstake return must not exceed the stake fee
+    InvalidPoolFees = 38,
+
+    /// Stability pool deposits can only be transferred once a pool receipt contract is set
+    PoolReceiptNotSet = 39,
+
+    /// Position has not opted in to compounding its rewards
+    AutoCompoundDisabled = 40,
+
+    /// Caller is not an operator of the owner; see `set_operator`
+    NotOperator = 41,
+
+    /// Stability pool withdrawals are blocked for a day after CDPs are frozen, while they await
+    /// liquidation
+    LiquidationsPending = 42,
+
+    /// Account is not authorized under the contract's compliance mode
+    NotAuthorized = 43,
+
+    /// Permit is for another contract or network, an owner without a permit key, or a used nonce
+    InvalidPermit = 44,
+
+    /// Collateral type is not registered with the collateral registry; see `set_collateral_registry`
+    UnknownCollateralType = 45,
+
+    /// Lending this much would exceed a collateral type's debt ceiling
+    DebtCeilingExceeded = 46,
+
+    /// Failed to transfer a registered collateral type
+    CollateralTransferFailed = 47,
+
+    /// CDPs can only move between owners once a CDP manager contract is set
+    CDPManagerNotSet = 51,
+
+    /// Rate adjustment cooldown must not exceed a year
+    InvalidRateAdjustmentCooldown = 52,
 }
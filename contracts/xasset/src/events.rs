@@ -0,0 +1,80 @@
+use soroban_sdk::{Address, contractevent};
+
+// SEP-41 token events, published on every balance and allowance change
+
+#[contractevent(data_format = "single-value")]
+pub struct Transfer {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub to: Address,
+    pub amount: i128,
+}
+
+/// Transfer to a muxed address, carrying the recipient's muxed ID in the data
+#[contractevent(topics = ["transfer"], data_format = "map")]
+pub struct TransferMuxed {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub to: Address,
+    pub to_muxed_id: u64,
+    pub amount: i128,
+}
+
+#[contractevent(data_format = "single-value")]
+pub struct Mint {
+    #[topic]
+    pub to: Address,
+    pub amount: i128,
+}
+
+#[contractevent(data_format = "single-value")]
+pub struct Burn {
+    #[topic]
+    pub from: Address,
+    pub amount: i128,
+}
+
+#[contractevent(data_format = "single-value")]
+pub struct Clawback {
+    #[topic]
+    pub from: Address,
+    pub amount: i128,
+}
+
+// CDP events
+
+/// Part or all of a CDP moved to a new owner: the collateral and debt that moved, or for a whole
+/// CDP, its state at the time
+#[contractevent(topics = ["cdp_transfer"])]
+pub struct CDPTransfer {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub to: Address,
+    pub xlm_deposited: i128,
+    pub asset_lent: i128,
+}
+
+#[contractevent(data_format = "vec")]
+pub struct Approve {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub spender: Address,
+    pub amount: i128,
+    pub live_until_ledger: u32,
+}
+
+// Stability pool events
+
+/// Part of a stability pool deposit moved to a new owner, with its share of pending rewards
+#[contractevent(topics = ["stake_transfer"])]
+pub struct StakeTransfer {
+    #[topic]
+    pub from: Address,
+    #[topic]
+    pub to: Address,
+    pub amount: i128,
+}
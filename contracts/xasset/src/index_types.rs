@@ -20,8 +20,10 @@ pub struct StakePosition {
     pub xasset_deposit: i128,
     pub product_constant: i128,
     pub compounded_constant: i128,
+    pub interest_constant: i128,
     pub rewards_claimed: i128,
     pub epoch: u64,
+    pub scale: u64,
     pub ledger: u32,
     pub timestamp: u64,
 }
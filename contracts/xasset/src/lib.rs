@@ -3,7 +3,10 @@ use soroban_sdk::{self, contracttype};
 
 mod collateralized;
 mod error;
+mod events;
 mod index_types;
+mod migration;
+pub mod rate_model;
 mod stability_pool;
 mod storage;
 pub mod token;
@@ -0,0 +1,28 @@
+//! Storage layouts of contracts deployed before storage was versioned. After `upgrade`, the admin
+//! calls `migrate` to convert the contract's state. Each account is then converted the first time
+//! it is used, its balance counting towards the supply, so none is ever read in its legacy layout.
+//! The admin calls `migrate_accounts` for every holder, CDP owner and staker, and then
+//! `finish_migration`; accounts already converted are left as they are. Legacy CDPs accrue
+//! interest up to then at the protocol's rate, as they did before, and the XLM they paid in
+//! interest is kept as it was under its own key. Entries whose current layout only adds fields
+//! are extended in place.
+
+use soroban_sdk::contracttype;
+
+use crate::{collateralized::CDPStatus, storage::Interest};
+
+/// A CDP accruing simple interest on its principal since `last_interest_time`, unless frozen
+#[contracttype(export = false)]
+pub struct LegacyCDP {
+    pub xlm_deposited: i128,
+    pub asset_lent: i128,
+    pub status: CDPStatus,
+    pub last_interest_time: u64,
+    pub accrued_interest: Interest,
+}
+
+#[contracttype(export = false)]
+pub enum LegacyDataKey {
+    /// Stability pool compound records, by epoch
+    CompoundRecord(u64),
+}
@@ -0,0 +1,77 @@
+use core::cmp;
+
+use soroban_sdk::{Address, Symbol, contracttype};
+
+const BASIS_POINTS: u64 = 10_000;
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Oracle price feed for a single asset
+pub struct PriceFeed {
+    /// Oracle contract ID
+    pub contract: Address,
+    /// Which asset from the Oracle to read
+    pub asset: Symbol,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Interest rate curve that steepens past a kink, all values in basis points.
+/// Slopes are the rate added per 100% of utilization.
+pub struct KinkedCurve {
+    /// Rate at zero utilization
+    pub min_rate: u32,
+    /// Upper bound on the rate
+    pub max_rate: u32,
+    /// Utilization at which the curve switches from `slope_low` to `slope_high`
+    pub kink: u32,
+    /// Slope below the kink
+    pub slope_low: u32,
+    /// Slope above the kink
+    pub slope_high: u32,
+}
+
+impl KinkedCurve {
+    /// Annual interest rate, in basis points, at `utilization` basis points
+    #[must_use]
+    pub fn rate(&self, utilization: u32) -> u32 {
+        let utilization = u64::from(utilization);
+        let kink = u64::from(self.kink);
+        let below = cmp::min(utilization, kink);
+        let above = utilization.saturating_sub(kink);
+        let rate = u64::from(self.min_rate)
+            + u64::from(self.slope_low) * below / BASIS_POINTS
+            + u64::from(self.slope_high) * above / BASIS_POINTS;
+        cmp::min(rate, u64::from(self.max_rate)) as u32
+    }
+
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.min_rate <= self.max_rate && u64::from(self.kink) <= BASIS_POINTS
+    }
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// How the annual interest rate charged on CDPs is set
+pub enum RateModel {
+    /// The admin-set `interest_rate`, regardless of market conditions
+    Fixed,
+    /// Curve driven by how far below its peg the xAsset trades, as reported by the given feed.
+    /// Utilization is the discount to the peg, in basis points.
+    PegDeviation(KinkedCurve, PriceFeed),
+    /// Curve driven by how little of the outstanding CDP debt the stability pool could absorb.
+    /// Utilization is the uncovered share of debt, in basis points.
+    PoolCoverage(KinkedCurve),
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Inputs and output of the interest rate model, as of now
+pub struct RateInputs {
+    pub model: RateModel,
+    /// Utilization fed into the model's curve, in basis points; 0 for `Fixed`
+    pub utilization: u32,
+    /// Rate the model gives for `utilization`, applied from the next accrual on
+    pub rate: u32,
+}
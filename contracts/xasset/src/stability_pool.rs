@@ -1,7 +1,6 @@
-use soroban_sdk::{Address, Env, contracttype};
+use soroban_sdk::{Address, Env, Map, contracttype};
 
 use crate::{Error, collateralized::CDPStatus};
-const PRODUCT_CONSTANT: i128 = 1_000_000_000;
 
 #[contracttype]
 #[derive(Clone)]
@@ -10,34 +9,87 @@ pub struct StakerPosition {
     pub product_constant: i128,
     pub compounded_constant: i128,
     pub epoch: u64,
+    /// Scale within the epoch; the product constant is scaled up each time it changes
+    pub scale: u64,
+    /// Interest reward accumulator, tracked like `compounded_constant`
+    pub interest_constant: i128,
+    /// XLM returned upon unstaking; 0 if the stake fee was paid in xAsset
+    pub unstake_return: i128,
+    /// Collateralization ratio, in basis points, at which rewards are compounded into the
+    /// deposit through the staker's CDP; if `None`, rewards are paid out
+    pub compound_collat_ratio: Option<u32>,
+    /// Reward accumulators of liquidated non-XLM collateral, by SAC token address, tracked like
+    /// `compounded_constant`; missing ones are 0
+    pub collateral_constants: Map<Address, i128>,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Stability pool fees, in XLM
+pub struct PoolFees {
+    /// Fee for each deposit into an existing position
+    pub deposit_fee: i128,
+    /// Fee for opening a position
+    pub stake_fee: i128,
+    /// Part of the stake fee returned upon unstaking
+    pub unstake_return: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Asset a stability pool fee is paid in
+pub enum FeeAsset {
+    /// The fee is paid in XLM
+    Xlm,
+    /// The fee's value in XLM is paid in xAsset, at oracle prices. A stake fee paid in xAsset
+    /// is charged without the unstake return, which is then not paid back upon unstaking.
+    XAsset,
 }
 
 #[contracttype]
 pub struct AvailableAssets {
     pub available_xasset: i128,
+    /// XLM gained from liquidations
     pub available_rewards: i128,
-}
-
-impl Default for StakerPosition {
-    fn default() -> Self {
-        StakerPosition {
-            xasset_deposit: 0,
-            product_constant: PRODUCT_CONSTANT, // Using 1_000_000 to represent 1.0 for better precision
-            compounded_constant: 0,
-            epoch: 0,
-        }
-    }
+    /// XLM earned from CDP interest
+    pub available_interest: i128,
+    /// Non-XLM collateral gained from liquidations, by SAC token address
+    pub available_collateral: Map<Address, i128>,
 }
 
 pub trait IsStabilityPool {
     /// Deposit xasset tokens into the Stability Pool
     fn deposit(env: &Env, from: Address, amount: i128) -> Result<(), Error>;
-    /// Withdraw xasset tokens from the Stability Pool
+    /// Deposit xasset tokens into the Stability Pool, paying the deposit fee in the given asset
+    fn deposit_paying_fee_in(
+        env: &Env,
+        from: Address,
+        amount: i128,
+        fee_asset: FeeAsset,
+    ) -> Result<(), Error>;
+    /// Withdraw xasset tokens from the Stability Pool. Blocked while any frozen CDP awaits
+    /// liquidation, so stakers cannot withdraw ahead of it and avoid their share of the loss.
     fn withdraw(env: &Env, to: Address, amount: i128) -> Result<(), Error>;
     /// Process a liquidation event for a CDP
     fn liquidate(env: &Env, cdp_owner: Address) -> Result<(i128, i128, CDPStatus), Error>;
-    /// Claim a user's share of collateral rewards
+    /// Claim a user's share of collateral and interest rewards, paid to its rewards recipient
     fn claim_rewards(env: &Env, to: Address) -> Result<i128, Error>;
+    /// Claim a staker's rewards as one of its operators (see `set_operator`). They are still
+    /// paid to the staker's rewards recipient.
+    fn claim_rewards_for(env: &Env, operator: Address, staker: Address) -> Result<i128, Error>;
+    /// Opt a position in to compounding its rewards, or out of it with `None`. Rewards are then
+    /// added to the staker's CDP as collateral, opening one if needed, and xAsset minted against
+    /// them at `collat_ratio`, in basis points, is added to the deposit. This happens whenever the
+    /// deposit changes, or on `compound`, and the change fails if compounding does; opt out to
+    /// have rewards paid out instead.
+    fn set_auto_compound(
+        env: &Env,
+        staker: Address,
+        collat_ratio: Option<u32>,
+    ) -> Result<(), Error>;
+    /// Compound a position's pending rewards into its deposit, returning the xAsset added. Only
+    /// callable by the staker, for a position that opted in.
+    fn compound(env: &Env, staker: Address) -> Result<i128, Error>;
     /// Retrieve the current deposit amount for a given address
     fn get_staker_deposit_amount(env: &Env, address: Address) -> Result<i128, Error>;
     /// Retrieve the total amount of xasset tokens in the Stability Pool
@@ -46,6 +98,17 @@ pub trait IsStabilityPool {
     fn get_total_collateral(env: &Env) -> i128;
     /// Add a stake to the pool
     fn stake(env: &Env, from: Address, amount: i128) -> Result<(), Error>;
+    /// Add a stake to the pool, paying the stake fee in the given asset
+    fn stake_paying_fee_in(
+        env: &Env,
+        from: Address,
+        amount: i128,
+        fee_asset: FeeAsset,
+    ) -> Result<(), Error>;
+    /// Move `amount` of `from`'s deposit to `to`, along with its share of pending rewards. If `to`
+    /// has no position, the moved rewards stay pending in its new one; otherwise they are paid
+    /// out to it with its own. Only callable by the pool receipt contract.
+    fn transfer_stake(env: &Env, from: Address, to: Address, amount: i128) -> Result<(), Error>;
     /// Remove a user's stake from the pool
     fn unstake(env: &Env, staker: Address) -> Result<(), Error>;
     /// View a user's available xasset and rewards
@@ -54,4 +117,10 @@ pub trait IsStabilityPool {
     fn get_position(env: &Env, staker: Address) -> Result<StakerPosition, Error>;
     /// View the stability pool's current constants
     fn get_constants(env: &Env) -> StakerPosition;
+    /// View the stability pool's fees, in XLM
+    fn get_pool_fees(env: &Env) -> PoolFees;
+    /// View the number of frozen CDPs awaiting liquidation, not counting any left without debt or
+    /// collateral to liquidate. Withdrawals and unstaking are blocked while it is above 0, for up
+    /// to a day after it last rose above 0.
+    fn get_frozen_cdps(env: &Env) -> u32;
 }
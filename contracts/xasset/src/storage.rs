@@ -1,20 +1,25 @@
-use soroban_sdk::{Address, contracttype};
+use core::cmp;
 
-use crate::collateralized::CDPStatus;
+use soroban_sdk::{Address, BytesN, Env, Map, contracttype};
 
-#[contracttype]
+use crate::{
+    collateralized::CDPStatus,
+    token::{INTEREST_PRECISION, NORMALIZED_DEBT_PRECISION},
+};
+
+#[contracttype(export = false)]
 #[derive(Clone)]
 pub struct Txn(pub Address, pub Address);
 
-#[contracttype]
+#[contracttype(export = false)]
 #[derive(Clone)]
 pub struct Allowance {
     pub amount: i128,
     pub live_until_ledger: u32,
 }
 
-#[contracttype]
-#[derive(Clone, Copy)]
+/// A CDP as the contract works with it, with interest accrued up to when it was loaded
+#[derive(Clone)]
 pub struct CDPInternal {
     pub xlm_deposited: i128,
     pub asset_lent: i128,
@@ -22,26 +27,207 @@ pub struct CDPInternal {
     /// Last time (in seconds) interest was calculated for each CDP
     pub last_interest_time: u64,
     pub accrued_interest: Interest,
+    /// Global interest index as of `accrued_interest`. Open and insolvent CDPs accrue interest
+    /// on principal and interest by the ratio between the current index and this one.
+    pub interest_index: i128,
+    /// Annual interest rate in basis points chosen by the CDP's owner; if `None`, the CDP
+    /// accrues interest through the global interest index at the protocol's rate
+    pub interest_rate: Option<u32>,
+    /// Unix timestamp of the owner's last change to `interest_rate`
+    pub rate_adjusted_time: u64,
+    /// Collateral held besides XLM, by SAC token address
+    pub collateral: Map<Address, i128>,
+}
+
+/// Debt of all CDPs at one borrower-chosen rate, accrued up to `time`
+#[contracttype(export = false)]
+#[derive(Clone)]
+pub struct RateDebt {
+    pub debt: i128,
+    /// Unix timestamp `debt` was last accrued to
+    pub time: u64,
+}
+
+/// A CDP as stored, keyed by `DataKey::CDP`
+#[contracttype(export = false)]
+#[derive(Clone)]
+pub struct StoredCDP {
+    pub xlm_deposited: i128,
+    pub asset_lent: i128,
+    pub status: CDPStatus,
+    /// Last time (in seconds) the CDP was stored while accruing interest
+    pub last_interest_time: u64,
+    /// Principal plus unpaid interest, divided by the global interest index and scaled by
+    /// `NORMALIZED_DEBT_PRECISION` if the CDP accrues at the protocol's rate; otherwise as of
+    /// `last_interest_time`
+    pub normalized_debt: i128,
+    /// Amount of interest paid, in xAsset
+    pub interest_paid: i128,
+    pub interest_rate: Option<u32>,
+    pub rate_adjusted_time: u64,
+    pub collateral: Map<Address, i128>,
+}
+
+#[contracttype(export = false)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// One-time borrowing fee charged on newly minted xAsset, all rates in basis points
+pub struct BorrowFee {
+    /// Minimum fee
+    pub floor: u32,
+    /// Maximum fee
+    pub cap: u32,
+    /// Base rate added on top of the floor; decays over time
+    pub base_rate: u32,
+    /// Unix timestamp of the last base rate update
+    pub base_rate_time: u64,
+    /// Seconds it takes the base rate to decay to half its value
+    pub base_rate_half_life: u64,
+    /// Recipient of borrowing fees; if unset, fees are distributed to the stability pool
+    pub recipient: Option<Address>,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+/// Which accounts may transfer xAsset, open CDPs and join the stability pool
+pub enum ComplianceMode {
+    /// Every account may, regardless of its authorized flag
+    #[default]
+    Open,
+    /// Every account may unless the admin has deauthorized it
+    Denylist,
+    /// Only accounts the admin has authorized may
+    Allowlist,
 }
 
 #[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Allowance signed off-chain by the owner's permit key (see `set_permit_key`); the signed
+/// message is this struct's XDR
+pub struct Permit {
+    /// Network the permit is valid on
+    pub network_id: BytesN<32>,
+    /// xAsset contract the permit is valid for
+    pub contract: Address,
+    pub owner: Address,
+    pub spender: Address,
+    pub amount: i128,
+    /// Ledger the allowance expires after; the permit cannot be used after it either
+    pub live_until_ledger: u32,
+    /// Must match the owner's next permit nonce; each nonce can be used once
+    pub nonce: u64,
+}
+
+#[contracttype(export = false)]
 pub struct AllowanceValue {
     pub amount: i128,
     pub expiration_ledger: u32,
 }
 
-#[contracttype]
+#[contracttype(export = false)]
 pub struct Balance(Address);
 
 impl CDPInternal {
     #[must_use]
-    pub fn new(xlm_deposited: i128, asset_lent: i128, timestamp: u64) -> Self {
+    pub fn new(
+        env: &Env,
+        xlm_deposited: i128,
+        asset_lent: i128,
+        timestamp: u64,
+        interest_index: i128,
+    ) -> Self {
         CDPInternal {
             xlm_deposited,
             asset_lent,
             status: CDPStatus::Open,
             accrued_interest: Interest::default(),
             last_interest_time: timestamp,
+            interest_index,
+            interest_rate: None,
+            rate_adjusted_time: 0,
+            collateral: Map::new(env),
+        }
+    }
+
+    /// Whether interest accrues on this CDP; it stops once the CDP is frozen
+    #[must_use]
+    pub fn accrues_interest(&self) -> bool {
+        matches!(self.status, CDPStatus::Open | CDPStatus::Insolvent)
+    }
+
+    /// Whether the stability pool can liquidate this CDP: it is frozen, and has debt and
+    /// collateral left
+    #[must_use]
+    pub fn is_liquidatable(&self) -> bool {
+        matches!(self.status, CDPStatus::Frozen)
+            && self.asset_lent > 0
+            && (self.xlm_deposited > 0 || !self.collateral.is_empty())
+    }
+
+    /// The CDP as stored, with its debt normalized by `interest_index`
+    #[must_use]
+    pub fn to_stored(&self) -> StoredCDP {
+        let debt = self.asset_lent + self.accrued_interest.amount;
+        StoredCDP {
+            xlm_deposited: self.xlm_deposited,
+            asset_lent: self.asset_lent,
+            status: self.status,
+            last_interest_time: self.last_interest_time,
+            normalized_debt: if self.accrues_interest() && self.interest_rate.is_none() {
+                debt * NORMALIZED_DEBT_PRECISION / self.interest_index
+            } else {
+                debt
+            },
+            interest_paid: self.accrued_interest.paid,
+            interest_rate: self.interest_rate,
+            rate_adjusted_time: self.rate_adjusted_time,
+            collateral: self.collateral.clone(),
+        }
+    }
+}
+
+impl StoredCDP {
+    /// Whether interest accrues on this CDP; it stops once the CDP is frozen
+    #[must_use]
+    pub fn accrues_interest(&self) -> bool {
+        matches!(self.status, CDPStatus::Open | CDPStatus::Insolvent)
+    }
+
+    /// Whether the stability pool can liquidate this CDP: it is frozen, and has debt and
+    /// collateral left
+    #[must_use]
+    pub fn is_liquidatable(&self) -> bool {
+        matches!(self.status, CDPStatus::Frozen)
+            && self.asset_lent > 0
+            && (self.xlm_deposited > 0 || !self.collateral.is_empty())
+    }
+
+    /// The CDP with its debt as of `interest_index`, the global interest index now. Interest
+    /// at a borrower-chosen rate is left as of `last_interest_time`.
+    #[must_use]
+    pub fn to_internal(&self, interest_index: i128) -> CDPInternal {
+        let (debt, interest_index) = if self.accrues_interest() && self.interest_rate.is_none() {
+            // Rounded up, so that a CDP stored and loaded at the same index keeps its debt
+            (
+                (self.normalized_debt * interest_index + NORMALIZED_DEBT_PRECISION - 1)
+                    / NORMALIZED_DEBT_PRECISION,
+                interest_index,
+            )
+        } else {
+            (self.normalized_debt, INTEREST_PRECISION)
+        };
+        CDPInternal {
+            xlm_deposited: self.xlm_deposited,
+            asset_lent: self.asset_lent,
+            status: self.status,
+            last_interest_time: self.last_interest_time,
+            accrued_interest: Interest {
+                amount: cmp::max(0, debt - self.asset_lent),
+                paid: self.interest_paid,
+            },
+            interest_index,
+            interest_rate: self.interest_rate,
+            rate_adjusted_time: self.rate_adjusted_time,
+            collateral: self.collateral.clone(),
         }
     }
 }
@@ -51,7 +237,7 @@ impl CDPInternal {
 pub struct Interest {
     /// Amount of interest accrued
     pub amount: i128,
-    /// Amount of interest paid
+    /// Amount of interest paid, in xAsset, whatever it was paid in
     pub paid: i128,
 }
 
@@ -60,7 +246,7 @@ pub struct Interest {
 pub struct InterestDetail {
     /// Amount of interest accrued
     pub amount: i128,
-    /// Amount of interest paid
+    /// Amount of interest paid, in xAsset, whatever it was paid in
     pub paid: i128,
     /// Amount of interest accrued in XLM
     pub amount_in_xlm: i128,
@@ -1,18 +1,31 @@
 #![cfg(test)]
 extern crate std;
 
-use crate::collateralized::CDPStatus;
+use crate::collateralized::{CDPStatus, InterestPayment};
 use crate::data_feed;
 use crate::error::Error;
-use crate::token::{TokenContract, TokenContractClient};
+use crate::migration::{LegacyCDP, LegacyDataKey};
+use crate::rate_model::{KinkedCurve, PriceFeed, RateModel};
+use crate::stability_pool::{FeeAsset, PoolFees};
+use crate::storage::{ComplianceMode, Interest, Permit};
+use crate::token::{DataKey, TokenContract, TokenContractClient};
 use data_feed::Asset;
-use soroban_sdk::testutils::Ledger;
+use ed25519_dalek::SigningKey;
+use soroban_sdk::testutils::{EnvTestConfig, Events, Ledger, MuxedAddress as _, ed25519::Sign};
 use soroban_sdk::{
-    Address, Env, String, Symbol, Vec,
+    Address, BytesN, Env, IntoVal, Map, MuxedAddress, String, Symbol, Val, Vec, contract,
+    contracttype, map, symbol_short,
     testutils::Address as _,
     token::{self, Client as TokenClient, StellarAssetClient},
+    vec,
 };
 
+mod collateral_registry {
+    soroban_sdk::contractimport!(
+        file = "../../target/wasm32v1-none/release/collateral_registry.wasm"
+    );
+}
+
 fn create_sac_token_clients<'a>(
     e: &Env,
     admin: &Address,
@@ -67,6 +80,18 @@ fn create_token_contract<'a>(
     TokenContractClient::new(e, &contract_id)
 }
 
+/// Check that every xAsset in circulation was either lent against one of `lenders`' CDPs or minted
+/// by the admin. Only holds while no borrowing fees, xAsset interest or holder burns move the
+/// supply independently of CDP principal.
+fn assert_supply_invariant(token: &TokenContractClient, lenders: &[&Address], admin_minted: i128) {
+    let principal: i128 = lenders
+        .iter()
+        .filter_map(|lender| token.try_cdp(lender).ok())
+        .map(|cdp| cdp.unwrap().asset_lent)
+        .sum();
+    assert_eq!(token.total_supply(), principal + admin_minted);
+}
+
 #[test]
 fn test_token_initialization() {
     let e = Env::default();
@@ -156,13 +181,19 @@ fn test_cannot_cause_overflow() {
 
     // Mint some tokens to Alice
     token.mint(&alice, &1000_0000000);
-    // Mint maximum tokens to Bob
-    token.mint(&bob, &i128::MAX);
-
-    // Try to transfer from Bob to Alice that would cause overflow
-    let result = token.try_transfer(&bob, &alice, &i128::MAX);
+    // Minting maximum tokens to Bob would overflow the total supply
+    let result = token.try_mint(&bob, &i128::MAX);
     assert!(result.is_err());
     assert_eq!(result.unwrap_err().unwrap(), Error::ArithmeticError.into());
+    assert_eq!(token.total_supply(), 1000_0000000);
+
+    // With the supply capped, no balance can grow large enough for a transfer to overflow it
+    token.mint(&bob, &(i128::MAX - 1000_0000000));
+    let result = token.try_transfer(&bob, &alice, &i128::MAX);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        Error::InsufficientBalance.into()
+    );
 }
 
 #[test]
@@ -193,6 +224,260 @@ fn test_token_transfers() {
     assert_eq!(token.balance(&bob), 500_0000000);
 }
 
+#[test]
+fn test_token_events() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+    let id = token.address.clone();
+
+    let alice = Address::generate(&e);
+    let bob = MuxedAddress::generate(&e);
+    let carol = Address::generate(&e);
+
+    token.mint(&alice, &1_000_000_000);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                id.clone(),
+                (Symbol::new(&e, "mint"), alice.clone()).into_val(&e),
+                1_000_000_000i128.into_val(&e),
+            )
+        ]
+    );
+
+    // Transfers to muxed addresses carry the muxed ID in the event data
+    token.transfer(&alice, &bob, &100_000_000);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                id.clone(),
+                (Symbol::new(&e, "transfer"), alice.clone(), bob.address()).into_val(&e),
+                map![
+                    &e,
+                    (
+                        Symbol::new(&e, "amount"),
+                        IntoVal::<Env, Val>::into_val(&100_000_000i128, &e)
+                    ),
+                    (
+                        Symbol::new(&e, "to_muxed_id"),
+                        bob.id().unwrap().into_val(&e)
+                    ),
+                ]
+                .into_val(&e),
+            )
+        ]
+    );
+    token.transfer(&alice, &carol, &100_000_000);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                id.clone(),
+                (Symbol::new(&e, "transfer"), alice.clone(), carol.clone()).into_val(&e),
+                100_000_000i128.into_val(&e),
+            )
+        ]
+    );
+
+    token.approve(&alice, &carol, &200_000_000, &1_000);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                id.clone(),
+                (Symbol::new(&e, "approve"), alice.clone(), carol.clone()).into_val(&e),
+                (200_000_000i128, 1_000u32).into_val(&e),
+            )
+        ]
+    );
+
+    token.burn(&alice, &100_000_000);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                id.clone(),
+                (Symbol::new(&e, "burn"), alice.clone()).into_val(&e),
+                100_000_000i128.into_val(&e),
+            )
+        ]
+    );
+
+    // Internal balance changes, like minting against a CDP, are published too
+    let client = data_feed::Client::new(&e, &token.xlm_contract());
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+    xlm_admin.mint(&carol, &10_000_000_000);
+    token.open_cdp(&carol, &10_000_000_000, &100_000_000);
+    let mint: (Address, Vec<Val>, Val) = (
+        id.clone(),
+        (Symbol::new(&e, "mint"), carol.clone()).into_val(&e),
+        100_000_000i128.into_val(&e),
+    );
+    assert!(e.events().all().contains(mint));
+}
+
+#[test]
+fn test_compliance_mode() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+    let client = data_feed::Client::new(&e, &token.xlm_contract());
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    xlm_admin.mint(&alice, &20_000_000_000);
+    xlm_admin.mint(&bob, &20_000_000_000);
+    token.mint(&alice, &1_000_000_000);
+
+    // Open by default, whatever the flags say
+    assert_eq!(token.compliance_mode(), ComplianceMode::Open);
+    token.set_authorized(&bob, &false);
+    assert!(!token.authorized(&bob));
+    assert!(token.is_permitted(&bob));
+    token.transfer(&alice, &bob, &100_000_000);
+
+    // A denylist rejects only deauthorized accounts, and freezes their balances
+    token.set_compliance_mode(&ComplianceMode::Denylist);
+    assert!(!token.authorized(&alice));
+    assert!(token.is_permitted(&alice));
+    assert!(!token.is_permitted(&bob));
+    assert_eq!(token.spendable_balance(&bob), 0);
+    assert_eq!(token.spendable_balance(&alice), 900_000_000);
+    let result = token.try_transfer(&alice, &bob, &100_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotAuthorized.into());
+    let result = token.try_transfer(&bob, &alice, &100_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotAuthorized.into());
+    let result = token.try_stake(&bob, &100_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotAuthorized);
+    token.stake(&alice, &100_000_000);
+    token.set_pool_receipt(&Some(Address::generate(&e)));
+    let result = token.try_transfer_stake(&alice, &bob, &50_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotAuthorized);
+
+    // An allowlist rejects every account the admin has not authorized
+    token.set_compliance_mode(&ComplianceMode::Allowlist);
+    assert!(!token.is_permitted(&alice));
+    let result = token.try_open_cdp(&alice, &10_000_000_000, &100_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotAuthorized);
+    let result = token.try_deposit(&alice, &100_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotAuthorized);
+    token.set_authorized(&alice, &true);
+    token.open_cdp(&alice, &10_000_000_000, &100_000_000);
+    token.deposit(&alice, &100_000_000);
+
+    let spender = Address::generate(&e);
+    token.approve(&alice, &spender, &200_000_000, &1000);
+    let result = token.try_transfer_from(&spender, &alice, &bob, &100_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotAuthorized.into());
+    token.set_authorized(&bob, &true);
+    token.transfer_from(&spender, &alice, &bob, &100_000_000);
+    assert_eq!(token.balance(&bob), 200_000_000);
+}
+
+#[test]
+fn test_permit() {
+    let e = Env::default();
+    e.mock_all_auths();
+    e.ledger().set_sequence_number(100);
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let signing_key = SigningKey::from_bytes(&[7; 32]);
+    let owner_key = BytesN::from_array(&e, &signing_key.verifying_key().to_bytes());
+    let owner = Address::generate(&e);
+    let router = Address::generate(&e);
+    token.mint(&owner, &1_000_000_000);
+
+    let mut permit = Permit {
+        network_id: e.ledger().network_id(),
+        contract: token.address.clone(),
+        owner: owner.clone(),
+        spender: router.clone(),
+        amount: 500_000_000,
+        live_until_ledger: 200,
+        nonce: 0,
+    };
+    let sign = |permit: &Permit| BytesN::from_array(&e, &signing_key.sign(permit).unwrap());
+
+    // Permits are off until the owner sets a key for them
+    let result = token.try_permit(&permit, &sign(&permit));
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidPermit);
+    token.set_permit_key(&owner, &Some(owner_key.clone()));
+    assert_eq!(token.permit_key(&owner), Some(owner_key.clone()));
+
+    // Anyone can submit the owner's signed permit; the spender can then act on it
+    token.permit(&permit, &sign(&permit));
+    assert_eq!(token.allowance(&owner, &router), 500_000_000);
+    assert_eq!(token.permit_nonce(&owner), 1);
+    token.transfer_from(&router, &owner, &router, &200_000_000);
+    assert_eq!(token.balance(&router), 200_000_000);
+
+    // A permit can't be replayed, or signed with another key
+    let result = token.try_permit(&permit, &sign(&permit));
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidPermit);
+    permit.nonce = 1;
+    let other_key = SigningKey::from_bytes(&[9; 32]);
+    let signature = BytesN::from_array(&e, &other_key.sign(&permit).unwrap());
+    assert!(token.try_permit(&permit, &signature).is_err());
+
+    // Nor can its terms be changed after signing
+    let signature = sign(&permit);
+    permit.amount = 1_000_000_000;
+    assert!(token.try_permit(&permit, &signature).is_err());
+    token.permit(&permit, &sign(&permit));
+    assert_eq!(token.allowance(&owner, &router), 1_000_000_000);
+
+    // Clearing the key turns permits off again
+    token.set_permit_key(&owner, &None);
+    permit.nonce = 2;
+    let result = token.try_permit(&permit, &sign(&permit));
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidPermit);
+}
+
 #[test]
 fn test_allowances() {
     let e = Env::default();
@@ -314,8 +599,19 @@ fn test_liquidation() {
     let alice_cdp = token.cdp(&alice);
     assert_eq!(alice_cdp.status, CDPStatus::Insolvent);
 
-    // Freeze the CDP
+    // Freeze the CDP; stakers can no longer withdraw ahead of its liquidation
     token.freeze_cdp(&alice);
+    assert_eq!(token.get_frozen_cdps(), 1);
+    let result = token.try_withdraw(&staker, &100_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::LiquidationsPending);
+
+    // but only for a day, however long liquidation takes
+    let frozen_at = e.ledger().timestamp();
+    Ledger::set_timestamp(&e.ledger(), frozen_at + 86_399);
+    let result = token.try_withdraw(&staker, &100_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::LiquidationsPending);
+    Ledger::set_timestamp(&e.ledger(), frozen_at + 86_400);
+    token.withdraw(&staker, &100_000_000);
 
     // Liquidate the CDP
     token.liquidate_cdp(&alice);
@@ -324,6 +620,175 @@ fn test_liquidation() {
     let alice_cdp = token.cdp(&alice);
     assert!(alice_cdp.xlm_deposited < 10_000_000_000);
     assert!(alice_cdp.asset_lent < 700_000_000);
+    assert_supply_invariant(&token, &[&alice], 1000_0000000);
+
+    // The pool only covered part of the debt, so withdrawals stay blocked until the rest is
+    token.claim_rewards(&staker);
+    token.deposit(&staker, &1_000_000_000);
+    assert_eq!(token.get_frozen_cdps(), 1);
+    token.liquidate_cdp(&alice);
+    assert_eq!(token.get_frozen_cdps(), 0);
+    assert_supply_invariant(&token, &[&alice], 1000_0000000);
+    token.withdraw(&staker, &100_000_000);
+}
+
+#[test]
+fn test_liquidation_interest_capped_at_collateral() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (xlm_token, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_admin.address.clone());
+    let client = data_feed::Client::new(&e, &token.xlm_contract());
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    let staker = Address::generate(&e);
+    xlm_admin.mint(&alice, &10_000_000_000);
+    xlm_admin.mint(&bob, &100_000_000_000);
+    xlm_admin.mint(&staker, &1_000_000_000);
+    token.mint(&staker, &2_000_000_000);
+    token.stake(&staker, &2_000_000_000);
+    token.set_interest_rate(&100_00);
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &700_000_000);
+    token.open_cdp(&bob, &100_000_000_000, &100_000_000);
+
+    // A year of interest and a price drop leave Alice's interest worth more than her collateral
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000 + 31_536_000);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &3_000_000_000_000,
+        &1000,
+    );
+    token.freeze_cdp(&alice);
+    assert!(token.get_accrued_interest(&alice).amount_in_xlm > 10_000_000_000);
+
+    // Liquidation takes no more XLM for interest than the CDP holds, leaving Bob's backed
+    token.liquidate_cdp(&alice);
+    assert!(token.try_cdp(&alice).is_err());
+    assert_eq!(token.get_treasury_interest(), 10_000_000_000);
+    assert_eq!(
+        xlm_token.balance(&token.address) - token.get_treasury_interest(),
+        100_070_000_000
+    );
+    assert_eq!(token.cdp(&bob).xlm_deposited, 100_000_000_000);
+}
+
+#[test]
+fn test_stranded_frozen_cdp() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_admin.address.clone());
+    let client = data_feed::Client::new(&e, &token.xlm_contract());
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    let alice = Address::generate(&e);
+    let staker = Address::generate(&e);
+    xlm_admin.mint(&alice, &10_000_000_000);
+    xlm_admin.mint(&staker, &1_000_000_000);
+    token.mint(&staker, &500_000_000);
+    token.stake(&staker, &500_000_000);
+    token.set_interest_rate(&100_00);
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &700_000_000);
+
+    // A year of interest and a price drop leave the interest worth more than the collateral
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000 + 31_536_000);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &3_000_000_000_000,
+        &1000,
+    );
+    token.freeze_cdp(&alice);
+    assert_eq!(token.get_frozen_cdps(), 1);
+
+    // Liquidation takes all its XLM for interest, and has nothing left to take for its debt
+    token.liquidate_cdp(&alice);
+    let cdp = token.cdp(&alice);
+    assert_eq!(cdp.status, CDPStatus::Frozen);
+    assert_eq!(cdp.xlm_deposited, 0);
+    assert_eq!(cdp.asset_lent, 700_000_000);
+    let result = token.try_liquidate_cdp(&alice);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidLiquidation);
+
+    // So it no longer holds up withdrawals
+    assert_eq!(token.get_frozen_cdps(), 0);
+    token.withdraw(&staker, &100_000_000);
+}
+
+#[test]
+fn test_total_supply() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+    let client = data_feed::Client::new(&e, &token.xlm_contract());
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    xlm_admin.mint(&alice, &20_000_000_000);
+    xlm_admin.mint(&bob, &20_000_000_000);
+    assert_eq!(token.total_supply(), 0);
+
+    token.mint(&alice, &500_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &700_000_000);
+    token.open_cdp(&bob, &10_000_000_000, &300_000_000);
+    token.borrow_xasset(&bob, &100_000_000);
+    assert_eq!(token.total_supply(), 1_600_000_000);
+    assert_supply_invariant(&token, &[&alice, &bob], 500_000_000);
+
+    // Transfers and stability pool deposits move xAsset without changing the supply
+    token.transfer(&alice, &bob, &200_000_000);
+    token.stake(&bob, &300_000_000);
+    assert_supply_invariant(&token, &[&alice, &bob], 500_000_000);
+
+    token.repay_debt(&bob, &300_000_000);
+    token.clawback(&alice, &100_000_000);
+    assert_eq!(token.total_supply(), 1_200_000_000);
+    assert_supply_invariant(&token, &[&alice, &bob], 400_000_000);
 }
 
 #[test]
@@ -413,9 +878,14 @@ fn test_cdp_operations_with_interest() {
     // Check interest has accrued (11% annual rate)
     let cdp_after_year = token.cdp(&alice);
     assert!(cdp_after_year.accrued_interest.amount > 0);
-    // With 11% interest rate, expect ~55_000_000 interest (500_000_000 * 0.11)
+    // With 11% interest rate, expect ~58_139_035 interest (500_000_000 * (e^0.11 - 1))
     assert!(cdp_after_year.accrued_interest.amount >= 54_000_000); // Allow for some rounding
 
+    // The approval amount only adds the next five minutes of interest
+    let interest = token.get_accrued_interest(&alice);
+    assert!(interest.approval_amount > interest.amount_in_xlm);
+    assert!(interest.approval_amount < interest.amount_in_xlm * 101 / 100);
+
     // Advance another 6 months
     Ledger::set_timestamp(&e.ledger(), initial_time + 47304000);
 
@@ -527,3 +997,1352 @@ fn test_token_transfers_self() {
     // Balance should remain unchanged
     assert_eq!(token.balance(&alice), 1000_0000000);
 }
+
+#[test]
+fn test_borrow_fee() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let alice = Address::generate(&e);
+    let treasury = Address::generate(&e);
+    let staker = Address::generate(&e);
+    xlm_admin.mint(&alice, &2_000_000_000_000);
+    xlm_admin.mint(&staker, &2_000_000_000_000);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    // Fees are disabled by default
+    assert_eq!(token.get_borrow_fee(), 0);
+
+    // Floor may not exceed cap
+    let result = token.try_set_borrow_fee(&600, &500);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidBorrowFee.into());
+
+    // 0.5% fee, capped at 5%, paid to a fee recipient
+    token.set_borrow_fee(&50, &500);
+    token.set_fee_recipient(&Some(treasury.clone()));
+    assert_eq!(token.get_borrow_fee(), 50);
+
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    assert_eq!(token.balance(&alice), 500_000_000);
+    assert_eq!(token.balance(&treasury), 2_500_000);
+    assert_eq!(token.cdp(&alice).asset_lent, 502_500_000);
+
+    // Base rate is added to the floor, then halves every half-life
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000);
+    token.set_base_rate(&200);
+    assert_eq!(token.get_borrow_fee(), 250);
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000 + 43_200);
+    assert_eq!(token.get_borrow_fee(), 150);
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000 + 43_200 + 21_600);
+    assert_eq!(token.get_borrow_fee(), 125);
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000 + 86_400);
+    assert_eq!(token.get_borrow_fee(), 100);
+
+    // Without a recipient, fees grow stability pool deposits
+    token.set_fee_recipient(&None);
+    token.mint(&staker, &100_000_000);
+    token.stake(&staker, &100_000_000);
+    token.borrow_xasset(&alice, &100_000_000);
+    assert_eq!(token.balance(&alice), 600_000_000);
+    assert_eq!(token.get_total_xasset(), 101_000_000);
+    assert_eq!(token.get_staker_deposit_amount(&staker), 101_000_000);
+}
+
+#[test]
+fn test_borrow_fee_settings() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_admin.address.clone());
+    let client = data_feed::Client::new(&e, &token.xlm_contract());
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+    let alice = Address::generate(&e);
+    let treasury = Address::generate(&e);
+    xlm_admin.mint(&alice, &20_000_000_000);
+
+    // Each setter changes only its own part of the fee
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000);
+    token.set_borrow_fee(&50, &500);
+    token.set_fee_recipient(&Some(treasury.clone()));
+    token.set_base_rate(&200);
+    assert_eq!(token.get_borrow_fee(), 250);
+
+    // A new half-life keeps the decay so far, and the floor, cap and recipient
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000 + 43_200);
+    token.set_base_rate_half_life(&86_400);
+    assert_eq!(token.get_borrow_fee(), 150);
+    Ledger::set_timestamp(&e.ledger(), 1_700_000_000 + 43_200 + 86_400);
+    assert_eq!(token.get_borrow_fee(), 100);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    assert_eq!(token.balance(&treasury), 5_000_000);
+    token.set_base_rate(&10_000);
+    assert_eq!(token.get_borrow_fee(), 500);
+}
+
+#[test]
+fn test_interest_index() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let initial_time = 1700000000;
+    Ledger::set_timestamp(&e.ledger(), initial_time);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    xlm_admin.mint(&alice, &2_000_000_000_000);
+    xlm_admin.mint(&bob, &2_000_000_000_000);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    token.open_cdp(&bob, &10_000_000_000, &200_000_000);
+    assert_eq!(token.get_total_outstanding_interest(), 0);
+
+    // One year at 11%
+    Ledger::set_timestamp(&e.ledger(), initial_time + 31_536_000);
+    assert_eq!(token.cdp(&alice).accrued_interest.amount, 58_139_036);
+    assert_eq!(token.cdp(&bob).accrued_interest.amount, 23_255_615);
+    assert_eq!(token.get_total_outstanding_interest(), 81_394_649);
+
+    // Dropping the rate does not touch interest already accrued
+    token.set_interest_rate(&0);
+    Ledger::set_timestamp(&e.ledger(), initial_time + 2 * 31_536_000);
+    assert_eq!(token.cdp(&alice).accrued_interest.amount, 58_139_036);
+    assert_eq!(token.get_total_outstanding_interest(), 81_394_649);
+
+    // Raising it again compounds on principal plus interest, going forward only
+    token.set_interest_rate(&10_00);
+    Ledger::set_timestamp(&e.ledger(), initial_time + 3 * 31_536_000);
+    assert_eq!(token.cdp(&alice).accrued_interest.amount, 116_839_030);
+    assert_eq!(token.cdp(&bob).accrued_interest.amount, 46_735_612);
+    assert_eq!(token.get_total_outstanding_interest(), 163_574_641);
+
+    // Frozen CDPs stop accruing and drop out of the total
+    token.set_min_collat_ratio(&50_000);
+    token.freeze_cdp(&bob);
+    assert_eq!(token.get_total_outstanding_interest(), 116_839_029);
+    Ledger::set_timestamp(&e.ledger(), initial_time + 4 * 31_536_000);
+    assert_eq!(token.cdp(&bob).accrued_interest.amount, 46_735_612);
+}
+
+#[test]
+fn test_rate_model() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let alice = Address::generate(&e);
+    let staker = Address::generate(&e);
+    xlm_admin.mint(&alice, &2_000_000_000_000);
+    xlm_admin.mint(&staker, &2_000_000_000_000);
+
+    // Mock prices, including a market price for xUSD 3% below its peg
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+    let xusd = Asset::Other(Symbol::new(&e, "xUSD"));
+    client.add_assets(&Vec::from_array(&e, [xusd.clone()]));
+    client.set_asset_price(&xusd, &97_000_000_000_000, &1000);
+
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+
+    let curve = KinkedCurve {
+        min_rate: 1_00,
+        max_rate: 50_00,
+        kink: 50_00,
+        slope_low: 10_00,
+        slope_high: 60_00,
+    };
+
+    // Curve must be well-formed
+    let result = token.try_set_rate_model(&RateModel::PoolCoverage(KinkedCurve {
+        min_rate: 60_00,
+        ..curve
+    }));
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidRateModel.into());
+
+    // With nothing in the stability pool, all debt is uncovered
+    token.set_rate_model(&RateModel::PoolCoverage(curve));
+    let inputs = token.get_rate_inputs();
+    assert_eq!(inputs.utilization, 100_00);
+    assert_eq!(inputs.rate, 36_00);
+    assert_eq!(token.get_interest_rate(), 36_00);
+
+    // Staking half the debt halves utilization, and the rate follows
+    token.mint(&staker, &250_000_000);
+    token.stake(&staker, &250_000_000);
+    assert_eq!(token.get_rate_inputs().utilization, 50_00);
+    assert_eq!(token.get_interest_rate(), 6_00);
+
+    // Trading 3% below peg, past a 2% kink
+    token.set_rate_model(&RateModel::PegDeviation(
+        KinkedCurve {
+            min_rate: 0,
+            max_rate: 100_00,
+            kink: 2_00,
+            slope_low: 100_00,
+            slope_high: 500_00,
+        },
+        PriceFeed {
+            contract: xlm_contract.clone(),
+            asset: Symbol::new(&e, "xUSD"),
+        },
+    ));
+    assert_eq!(token.get_rate_inputs().utilization, 3_00);
+    assert_eq!(token.get_interest_rate(), 7_00);
+
+    // Back to a fixed rate
+    token.set_rate_model(&RateModel::Fixed);
+    token.set_interest_rate(&11_00);
+    assert_eq!(token.get_interest_rate(), 11_00);
+    assert_eq!(token.get_rate_inputs().utilization, 0);
+}
+
+#[test]
+fn test_borrower_chosen_interest_rate() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let initial_time = 1700000000;
+    Ledger::set_timestamp(&e.ledger(), initial_time);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    xlm_admin.mint(&alice, &2_000_000_000_000);
+    xlm_admin.mint(&bob, &2_000_000_000_000);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    token.open_cdp(&bob, &10_000_000_000, &200_000_000);
+
+    // Rates outside the bounds are rejected
+    token.set_cdp_rate_bounds(&1_00, &20_00);
+    let result = token.try_set_cdp_interest_rate(&alice, &Some(50));
+    assert_eq!(
+        result.err().unwrap().unwrap(),
+        Error::InvalidInterestRate.into()
+    );
+
+    // Alice picks 5%, Bob stays on the protocol's 11%
+    let alice_cdp = token.set_cdp_interest_rate(&alice, &Some(5_00));
+    assert_eq!(alice_cdp.interest_rate, Some(5_00));
+    assert_eq!(alice_cdp.accrued_interest.amount, 0);
+
+    Ledger::set_timestamp(&e.ledger(), initial_time + 31_536_000);
+    assert_eq!(token.cdp(&alice).accrued_interest.amount, 25_635_548);
+    assert_eq!(token.cdp(&bob).accrued_interest.amount, 23_255_615);
+    assert_eq!(token.get_total_outstanding_interest(), 48_891_162);
+
+    // Once the cooldown has passed, changing the rate is free
+    let alice_cdp = token.set_cdp_interest_rate(&alice, &Some(3_00));
+    assert_eq!(alice_cdp.accrued_interest.amount, 25_635_548);
+
+    // Changing it again within the cooldown costs 7 days of interest at the new rate
+    let alice_cdp = token.set_cdp_interest_rate(&alice, &Some(4_00));
+    assert_eq!(alice_cdp.accrued_interest.amount, 26_038_775);
+    assert_eq!(token.get_total_outstanding_interest(), 49_294_389);
+
+    // Going back to the protocol's rate
+    let alice_cdp = token.set_cdp_interest_rate(&alice, &None);
+    assert_eq!(alice_cdp.interest_rate, None);
+
+    // The cooldown is at most a year
+    let result = token.try_set_rate_adjustment_cooldown(&31_536_001);
+    assert_eq!(
+        result.err().unwrap().unwrap(),
+        Error::InvalidRateAdjustmentCooldown.into()
+    );
+    assert_eq!(token.set_rate_adjustment_cooldown(&31_536_000), 31_536_000);
+}
+
+#[test]
+fn test_custom_rates_compound_like_the_index() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let initial_time = 1700000000;
+    Ledger::set_timestamp(&e.ledger(), initial_time);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    // Alice and Bob choose the protocol's 11% for themselves; Carol follows the protocol
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    let carol = Address::generate(&e);
+    for lender in [&alice, &bob, &carol] {
+        xlm_admin.mint(lender, &20_000_000_000);
+        token.open_cdp(lender, &10_000_000_000, &500_000_000);
+    }
+    token.set_cdp_interest_rate(&alice, &Some(11_00));
+    token.set_cdp_interest_rate(&bob, &Some(11_00));
+
+    // Bob's CDP is stored every month, Alice's not at all; both owe what the index charges
+    for month in 1..=12 {
+        Ledger::set_timestamp(&e.ledger(), initial_time + month * 31_536_000 / 12);
+        token.add_collateral(&bob, &1);
+    }
+    let interest = token.cdp(&carol).accrued_interest.amount;
+    assert_eq!(interest, 58_139_036);
+    assert!((token.cdp(&alice).accrued_interest.amount - interest).abs() <= 1);
+    assert!((token.cdp(&bob).accrued_interest.amount - interest).abs() <= 12);
+
+    // A decade on, the totals have compounded along with the CDPs they sum
+    token.set_cdp_interest_rate(&carol, &Some(20_00));
+    Ledger::set_timestamp(&e.ledger(), initial_time + 11 * 31_536_000);
+    let owed: i128 = [&alice, &bob, &carol]
+        .iter()
+        .map(|lender| token.cdp(lender).accrued_interest.amount)
+        .sum();
+    assert!(owed > 3 * 500_000_000);
+    assert!((token.get_total_outstanding_interest() - owed).abs() <= 3);
+}
+
+#[test]
+fn test_interest_paid_in_xasset() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (xlm_token, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let initial_time = 1700000000;
+    Ledger::set_timestamp(&e.ledger(), initial_time);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let alice = Address::generate(&e);
+    let staker = Address::generate(&e);
+    xlm_admin.mint(&alice, &2_000_000_000_000);
+    xlm_admin.mint(&staker, &2_000_000_000_000);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    assert_eq!(token.get_interest_payment(), InterestPayment::Xlm);
+    token.set_interest_payment(&InterestPayment::Burn);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    let xlm_balance = xlm_token.balance(&alice);
+
+    // One year at 11%; repaying takes the interest in xAsset and needs no XLM approval
+    Ledger::set_timestamp(&e.ledger(), initial_time + 31_536_000);
+    assert_eq!(token.cdp(&alice).accrued_interest.amount, 58_139_036);
+    token.repay_debt(&alice, &400_000_000);
+    let cdp = token.cdp(&alice);
+    assert_eq!(cdp.accrued_interest.amount, 0);
+    assert_eq!(cdp.accrued_interest.paid, 58_139_036);
+    assert_eq!(cdp.asset_lent, 100_000_000);
+    assert_eq!(token.balance(&alice), 41_860_964);
+    assert_eq!(xlm_token.balance(&alice), xlm_balance);
+    assert_eq!(token.get_total_interest_collected(), 0);
+
+    // Routed to the stability pool, interest grows stakers' deposits
+    token.set_interest_payment(&InterestPayment::StabilityPool);
+    token.mint(&staker, &100_000_000);
+    token.stake(&staker, &100_000_000);
+    Ledger::set_timestamp(&e.ledger(), initial_time + 2 * 31_536_000);
+    assert_eq!(token.cdp(&alice).accrued_interest.amount, 11_627_808);
+    token.pay_interest(&alice, &11_627_808);
+    assert_eq!(token.balance(&alice), 30_233_156);
+    assert_eq!(token.get_total_xasset(), 111_627_808);
+    assert_eq!(token.get_staker_deposit_amount(&staker), 111_627_808);
+    assert_eq!(token.cdp(&alice).accrued_interest.paid, 69_766_844);
+
+    // With no XLM moving, repaying needs no prices
+    e.register_at(&xlm_contract, NoPrices, ());
+    Ledger::set_timestamp(&e.ledger(), initial_time + 3 * 31_536_000);
+    token.repay_debt(&alice, &10_000_000);
+    assert_eq!(token.balance(&alice), 8_605_348);
+}
+
+/// Stands in for a data feed that can't be reached
+#[contract]
+struct NoPrices;
+
+#[test]
+fn test_interest_to_stability_pool() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (xlm_token, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let initial_time = 1700000000;
+    Ledger::set_timestamp(&e.ledger(), initial_time);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let alice = Address::generate(&e);
+    let staker = Address::generate(&e);
+    xlm_admin.mint(&alice, &2_000_000_000_000);
+    xlm_admin.mint(&staker, &2_000_000_000_000);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    let result = token.try_set_interest_share(&100_01);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        Error::InvalidInterestShare.into()
+    );
+    token.set_interest_share(&80_00);
+
+    token.mint(&staker, &500_000_000);
+    token.stake(&staker, &500_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+
+    // One year at 11%, paid in XLM: 55_000_000 xAsset is worth 550_000_000 XLM
+    Ledger::set_timestamp(&e.ledger(), initial_time + 31_536_000);
+    token.pay_interest(&alice, &55_000_000);
+    assert_eq!(token.get_total_interest_collected(), 550_000_000);
+    assert_eq!(token.get_treasury_interest(), 110_000_000);
+
+    // Stakers earn their share without any liquidation
+    let available = token.get_available_assets(&staker);
+    assert_eq!(available.available_xasset, 500_000_000);
+    assert_eq!(available.available_rewards, 0);
+    assert_eq!(available.available_interest, 440_000_000);
+
+    // Withdrawing pays out pending interest rewards
+    let xlm_balance = xlm_token.balance(&staker);
+    token.withdraw(&staker, &100_000_000);
+    assert_eq!(xlm_token.balance(&staker), xlm_balance + 440_000_000);
+    assert_eq!(token.get_available_assets(&staker).available_interest, 0);
+    assert_eq!(token.get_staker_deposit_amount(&staker), 400_000_000);
+}
+
+#[test]
+fn test_sweep_to_treasury() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (xlm_token, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let initial_time = 1700000000;
+    Ledger::set_timestamp(&e.ledger(), initial_time);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let alice = Address::generate(&e);
+    let staker = Address::generate(&e);
+    let treasury = Address::generate(&e);
+    xlm_admin.mint(&alice, &2_000_000_000_000);
+    xlm_admin.mint(&staker, &2_000_000_000_000);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    token.set_interest_share(&50_00);
+    token.mint(&staker, &500_000_000);
+    token.stake(&staker, &500_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+
+    // Half of 581_390_360 XLM of interest goes to the treasury
+    Ledger::set_timestamp(&e.ledger(), initial_time + 31_536_000);
+    token.pay_interest(&alice, &58_139_036);
+    assert_eq!(token.get_treasury_interest(), 290_695_180);
+
+    let result = token.try_sweep_to_treasury();
+    assert_eq!(result.unwrap_err().unwrap(), Error::TreasuryNotSet.into());
+
+    // The stake fee is swept, less the unstake return still owed to the staker
+    token.set_treasury(&treasury);
+    assert_eq!(token.get_sweepable(), (340_695_180, 0));
+    assert_eq!(token.sweep_to_treasury(), (340_695_180, 0));
+    assert_eq!(xlm_token.balance(&treasury), 340_695_180);
+    assert_eq!(token.get_treasury_interest(), 0);
+    assert_eq!(token.sweep_to_treasury(), (0, 0));
+
+    // What backs collateral, rewards and unstake returns is still there
+    token.claim_rewards(&staker);
+    let xlm_balance = xlm_token.balance(&staker);
+    token.unstake(&staker);
+    assert_eq!(xlm_token.balance(&staker), xlm_balance + 20_000_000);
+    token.repay_debt(&alice, &500_000_000);
+    token.withdraw_collateral(&alice, &10_000_000_000);
+    assert_eq!(xlm_token.balance(&token.address), 0);
+}
+
+#[test]
+fn test_pool_fees() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (xlm_token, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    let treasury = Address::generate(&e);
+    xlm_admin.mint(&alice, &2_000_000_000);
+
+    // Mock prices: 1 xAsset is worth 10 XLM
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    assert_eq!(
+        token.get_pool_fees(),
+        PoolFees {
+            deposit_fee: 10_000_000,
+            stake_fee: 70_000_000,
+            unstake_return: 20_000_000,
+        }
+    );
+
+    // The return may not exceed the stake fee
+    let fees = PoolFees {
+        deposit_fee: 20_000_000,
+        stake_fee: 100_000_000,
+        unstake_return: 40_000_000,
+    };
+    let result = token.try_set_pool_fees(&PoolFees {
+        unstake_return: 100_000_001,
+        ..fees
+    });
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidPoolFees.into());
+    token.set_pool_fees(&fees);
+    assert_eq!(token.get_pool_fees(), fees);
+
+    // Alice pays in XLM and gets the return back upon unstaking
+    token.mint(&alice, &100_000_000);
+    token.stake(&alice, &50_000_000);
+    token.deposit(&alice, &50_000_000);
+    assert_eq!(xlm_token.balance(&alice), 1_880_000_000);
+
+    // Bob has no XLM, and pays in xAsset without the return: 6 + 2 xAsset
+    token.mint(&bob, &108_000_000);
+    token.stake_paying_fee_in(&bob, &50_000_000, &FeeAsset::XAsset);
+    token.deposit_paying_fee_in(&bob, &50_000_000, &FeeAsset::XAsset);
+    assert_eq!(token.balance(&bob), 0);
+    assert_eq!(token.get_staker_deposit_amount(&bob), 100_000_000);
+
+    token.unstake(&alice);
+    assert_eq!(xlm_token.balance(&alice), 1_920_000_000);
+    token.unstake(&bob);
+    assert_eq!(xlm_token.balance(&bob), 0);
+    assert_eq!(token.balance(&bob), 100_000_000);
+
+    token.set_treasury(&treasury);
+    assert_eq!(token.sweep_to_treasury(), (80_000_000, 8_000_000));
+    assert_eq!(token.balance(&treasury), 8_000_000);
+}
+
+#[test]
+fn test_auto_compound() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (xlm_token, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    // Mock prices: 1 xAsset is worth 10 XLM
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+    token.set_pool_fees(&PoolFees {
+        deposit_fee: 0,
+        stake_fee: 0,
+        unstake_return: 0,
+    });
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    let carol = Address::generate(&e);
+    token.mint(&alice, &1_500_000_000);
+    token.stake(&alice, &1_000_000_000);
+    token.mint(&carol, &1_000_000_000);
+    token.stake(&carol, &1_000_000_000);
+
+    // Alice compounds at 200%; a ratio below the minimum would never succeed
+    let result = token.try_set_auto_compound(&alice, &Some(10_999));
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        Error::InsufficientCollateralization.into()
+    );
+    token.set_auto_compound(&alice, &Some(20_000));
+    let result = token.try_compound(&carol);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        Error::AutoCompoundDisabled.into()
+    );
+
+    // Bob's liquidation leaves each staker 500_000_000 xAsset and 10_000_000_000 XLM
+    xlm_admin.mint(&bob, &20_000_000_000);
+    token.open_cdp(&bob, &20_000_000_000, &1_000_000_000);
+    token.set_min_collat_ratio(&1_000_000);
+    token.freeze_cdp(&bob);
+    token.set_min_collat_ratio(&11_000);
+    token.liquidate_cdp(&bob);
+
+    // While compounding fails, so do changes to the deposit
+    token.set_min_collat_ratio(&25_000);
+    let result = token.try_deposit(&alice, &500_000_000);
+    assert_eq!(
+        result.unwrap_err().unwrap(),
+        Error::InsufficientCollateralization
+    );
+    token.set_min_collat_ratio(&11_000);
+
+    // Depositing compounds Alice's rewards: 1_000_000_000 xAsset worth of XLM backs half as much
+    token.deposit(&alice, &500_000_000);
+    assert_eq!(token.get_staker_deposit_amount(&alice), 1_500_000_000);
+    let cdp = token.cdp(&alice);
+    assert_eq!(cdp.xlm_deposited, 10_000_000_000);
+    assert_eq!(cdp.asset_lent, 500_000_000);
+    assert_eq!(cdp.collateralization_ratio, 20_000);
+    assert_eq!(token.get_available_assets(&alice).available_rewards, 0);
+
+    // Carol didn't opt in, and withdrawing pays her rewards out
+    token.withdraw(&carol, &100_000_000);
+    assert_eq!(xlm_token.balance(&carol), 10_000_000_000);
+    assert_eq!(token.get_staker_deposit_amount(&carol), 400_000_000);
+    assert_eq!(token.get_total_xasset(), 1_900_000_000);
+}
+
+#[test]
+fn test_operators() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (xlm_token, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    // Mock prices: 1 xAsset is worth 10 XLM
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    // Alice keeps her keys cold, and lets a hot wallet manage her positions
+    let alice = Address::generate(&e);
+    let hot = Address::generate(&e);
+    let treasury = Address::generate(&e);
+    let bob = Address::generate(&e);
+    xlm_admin.mint(&alice, &20_000_000_000);
+    xlm_admin.mint(&hot, &1_000_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+    token.stake(&alice, &500_000_000);
+    token.set_rewards_recipient(&alice, &Some(treasury.clone()));
+    assert_eq!(token.rewards_recipient(&alice), treasury);
+
+    let result = token.try_claim_rewards_for(&hot, &alice);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotOperator);
+    token.set_operator(&alice, &hot, &true);
+    assert!(token.is_operator(&alice, &hot));
+
+    // Bob's liquidation earns Alice 2_000_000_000 XLM, which the hot wallet sends to the treasury
+    xlm_admin.mint(&bob, &2_000_000_000);
+    token.open_cdp(&bob, &2_000_000_000, &100_000_000);
+    token.set_min_collat_ratio(&1_000_000);
+    token.freeze_cdp(&bob);
+    token.set_min_collat_ratio(&11_000);
+    token.liquidate_cdp(&bob);
+    assert_eq!(token.claim_rewards_for(&hot, &alice), 2_000_000_000);
+    assert_eq!(xlm_token.balance(&treasury), 2_000_000_000);
+    assert_eq!(xlm_token.balance(&hot), 1_000_000_000);
+
+    // The hot wallet tops up collateral and repays debt from its own balances
+    token.add_collateral_for(&hot, &alice, &1_000_000_000);
+    token.mint(&hot, &100_000_000);
+    token.repay_debt_for(&hot, &alice, &100_000_000);
+    let cdp = token.cdp(&alice);
+    assert_eq!(cdp.xlm_deposited, 11_000_000_000);
+    assert_eq!(cdp.asset_lent, 400_000_000);
+    assert_eq!(xlm_token.balance(&hot), 0);
+    assert_eq!(token.balance(&hot), 0);
+    assert_eq!(token.balance(&alice), 0);
+
+    token.set_operator(&alice, &hot, &false);
+    let result = token.try_add_collateral_for(&hot, &alice, &1);
+    assert_eq!(result.unwrap_err().unwrap(), Error::NotOperator);
+}
+
+#[test]
+fn test_many_liquidations() {
+    liquidate_repeatedly(100);
+}
+
+// Takes several minutes; run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn test_thousands_of_liquidations() {
+    liquidate_repeatedly(2_000);
+}
+
+fn liquidate_repeatedly(rounds: u64) {
+    // Thousands of CDPs make the snapshot too large to keep
+    let e = Env::new_with_config(EnvTestConfig {
+        capture_snapshot_at_drop: false,
+    });
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+    token.set_pool_fees(&PoolFees {
+        deposit_fee: 0,
+        stake_fee: 0,
+        unstake_return: 0,
+    });
+
+    let debt = 1_000_0000000;
+    let collateral = 20_000_0000000;
+    let early = Address::generate(&e);
+    let bob = Address::generate(&e);
+    token.mint(&early, &10_0000000);
+    token.stake(&early, &10_0000000);
+    token.mint(&bob, &(debt + debt / 10));
+    token.stake(&bob, &(debt + debt / 10));
+    xlm_admin.mint(&bob, &(2 * collateral));
+
+    // Each liquidation takes about 90% of the pool, shrinking the product constant tenfold; Bob
+    // tops the pool back up with the xAsset he borrowed
+    let liquidate = || {
+        token.open_cdp(&bob, &collateral, &debt);
+        token.set_min_collat_ratio(&1_000_000);
+        token.freeze_cdp(&bob);
+        token.set_min_collat_ratio(&11_000);
+        token.liquidate_cdp(&bob);
+    };
+    // Mocked auth leaves a nonce entry behind for every authorized call, and the test host loads
+    // all of them in each invocation, so its costs grow with the rounds run so far
+    e.cost_estimate().budget().reset_unlimited();
+    for _ in 0..rounds {
+        liquidate();
+        token.claim_rewards(&bob);
+        token.deposit(&bob, &debt);
+    }
+
+    // The product constant kept its precision by changing scale along the way, and Bob now holds
+    // the whole pool
+    let constants = token.get_constants();
+    assert_eq!(constants.epoch, 0);
+    assert!(constants.scale >= rounds / 10);
+    assert!(constants.product_constant >= 1_000_000_000);
+    assert_eq!(token.get_staker_deposit_amount(&early), 0);
+    let bob_deposit = token.get_staker_deposit_amount(&bob);
+    assert!(token.get_total_xasset() - bob_deposit <= 10);
+
+    // A staker joining now is tracked as precisely as on day one
+    let carol = Address::generate(&e);
+    token.mint(&carol, &bob_deposit);
+    token.stake(&carol, &bob_deposit);
+    liquidate();
+    assert_eq!(
+        token.get_staker_deposit_amount(&carol),
+        bob_deposit - debt / 2
+    );
+    let available = token.get_available_assets(&carol);
+    assert_eq!(available.available_rewards, collateral / 2);
+}
+
+#[test]
+fn test_multi_collateral() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let (usdc, usdc_admin) = create_sac_token_clients(&e, &Address::generate(&e));
+    let datafeed = create_data_feed(&e);
+    datafeed.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    datafeed.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+    let oracle = datafeed.address.clone();
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin.clone(), datafeed, xlm_admin.address.clone());
+
+    // USDC counts at 80% of its $1 price, and at most 150 xUSD may be lent against it
+    let registry = collateral_registry::Client::new(
+        &e,
+        &e.register(
+            collateral_registry::WASM,
+            (admin, xlm_admin.address.clone()),
+        ),
+    );
+    registry.set_collateral_type(
+        &usdc.address,
+        &collateral_registry::CollateralType {
+            oracle,
+            asset: Symbol::new(&e, "USDT"),
+            collateral_factor: 8000,
+            debt_ceiling: 1_500_000_000,
+        },
+    );
+    token.set_collateral_registry(&Some(registry.address.clone()));
+    assert_eq!(token.get_collateral_registry(), Some(registry.address));
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    usdc_admin.mint(&alice, &10_000_000_000);
+    usdc_admin.mint(&bob, &10_000_000_000);
+    xlm_admin.mint(&alice, &10_000_000_000);
+    xlm_admin.mint(&bob, &10_000_000_000);
+    let result = token.try_open_cdp_in(&alice, &Address::generate(&e), &1, &1);
+    assert_eq!(result.unwrap_err().unwrap(), Error::UnknownCollateralType);
+
+    // 200 USDC back 100 xUSD at 160%
+    token.open_cdp_in(&alice, &usdc.address, &2_000_000_000, &1_000_000_000);
+    let cdp = token.cdp(&alice);
+    assert_eq!(cdp.xlm_deposited, 0);
+    assert_eq!(
+        cdp.collateral.get(usdc.address.clone()),
+        Some(2_000_000_000)
+    );
+    assert_eq!(cdp.collateralization_ratio, 16_000);
+    assert_eq!(usdc.balance(&alice), 8_000_000_000);
+    let result = token.try_open_cdp_in(&bob, &usdc.address, &2_000_000_000, &600_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::DebtCeilingExceeded);
+
+    // XLM and USDC count together; withdrawing USDC cannot take the ratio below the minimum
+    token.add_collateral(&alice, &1_000_000_000);
+    assert_eq!(token.cdp(&alice).collateralization_ratio, 17_000);
+    let result = token.try_withdraw_collateral_in(&alice, &usdc.address, &1_500_000_000);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidWithdrawal);
+    token.withdraw_collateral_in(&alice, &usdc.address, &500_000_000);
+    assert_eq!(token.cdp(&alice).collateralization_ratio, 13_000);
+    let totals = token.get_collateral_totals(&usdc.address);
+    assert_eq!(totals.deposited, 1_500_000_000);
+    assert_eq!(totals.debt, 1_000_000_000);
+
+    // Liquidation hands both collaterals to stakers
+    token.mint(&bob, &2_000_000_000);
+    token.stake(&bob, &2_000_000_000);
+    token.set_min_collat_ratio(&1_000_000);
+    token.freeze_cdp(&alice);
+    token.liquidate_cdp(&alice);
+    assert!(token.try_cdp(&alice).is_err());
+    let available = token.get_available_assets(&bob);
+    assert_eq!(available.available_rewards, 1_000_000_000);
+    assert_eq!(
+        available.available_collateral.get(usdc.address.clone()),
+        Some(1_500_000_000)
+    );
+    let totals = token.get_collateral_totals(&usdc.address);
+    assert_eq!((totals.deposited, totals.debt), (0, 0));
+    assert_eq!(totals.pool_rewards, 1_500_000_000);
+
+    token.claim_rewards(&bob);
+    assert_eq!(usdc.balance(&bob), 11_500_000_000);
+    assert!(
+        token
+            .get_available_assets(&bob)
+            .available_collateral
+            .is_empty()
+    );
+    assert_eq!(token.get_collateral_totals(&usdc.address).pool_rewards, 0);
+}
+
+#[test]
+fn test_cdp_health() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let datafeed = create_data_feed(&e);
+    datafeed.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    datafeed.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_admin.address.clone());
+    let alice = Address::generate(&e);
+    xlm_admin.mint(&alice, &20_000_000_000);
+    token.open_cdp(&alice, &10_000_000_000, &500_000_000);
+
+    // Withdrawing or borrowing the reported maximum lands on the minimum ratio, and any more
+    // drops below it
+    let min_ratio = token.minimum_collateralization_ratio();
+    let assert_headroom = || {
+        let health = token.cdp_health(&alice);
+        let ratio =
+            |xlm: i128, asset_lent: i128| token.projected_cdp_ratio(&alice, &xlm, &asset_lent);
+        assert_eq!(ratio(-health.max_withdraw, 0), min_ratio);
+        assert!(ratio(-health.max_withdraw - 1, 0) < min_ratio);
+        assert_eq!(ratio(0, health.max_borrow), min_ratio);
+        assert!(ratio(0, health.max_borrow + 1) < min_ratio);
+    };
+
+    // At 200% and a 110% minimum, XLM can fall to 0.055 before the CDP is insolvent
+    let health = token.cdp_health(&alice);
+    assert_eq!(health.liquidation_price, Some(5_500_000_000_000));
+    assert_eq!(health.max_withdraw, 4_500_000_000);
+    assert!(health.max_borrow > 0 && health.max_borrow <= 409_090_909);
+    assert_headroom();
+
+    // Projections match the ratio the change would produce
+    assert_eq!(
+        token.projected_cdp_ratio(&alice, &10_000_000_000, &0),
+        40_000
+    );
+    let result = token.try_projected_cdp_ratio(&alice, &-10_000_000_001, &0);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InsufficientCollateral);
+    let result = token.try_projected_cdp_ratio(&alice, &0, &-500_000_001);
+    assert_eq!(result.unwrap_err().unwrap(), Error::RepaymentExceedsDebt);
+    let projected = token.projected_cdp_ratio(&alice, &0, &100_000_000);
+    token.borrow_xasset(&alice, &100_000_000);
+    assert_eq!(token.cdp(&alice).collateralization_ratio, projected);
+
+    // The headroom reported can be used in full, and no more
+    let health = token.cdp_health(&alice);
+    token.withdraw_collateral(&alice, &health.max_withdraw);
+    assert_eq!(token.cdp_health(&alice).max_withdraw, 0);
+    let result = token.try_withdraw_collateral(&alice, &1);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidWithdrawal);
+    token.add_collateral(&alice, &5_000_000_000);
+    let health = token.cdp_health(&alice);
+    token.borrow_xasset(&alice, &health.max_borrow);
+    let cdp = token.cdp(&alice);
+    assert!(cdp.collateralization_ratio >= token.minimum_collateralization_ratio());
+    assert!(token.cdp_health(&alice).max_borrow <= 1);
+
+    // Accrued interest counts towards the debt the headroom allows for
+    token.add_collateral(&alice, &4_000_000_000);
+    token.set_interest_rate(&5_00);
+    Ledger::set_timestamp(&e.ledger(), e.ledger().timestamp() + 31_536_000);
+    assert!(token.get_accrued_interest(&alice).amount > 0);
+    assert_headroom();
+}
+
+/// Contract state as stored before storage was versioned
+#[contracttype]
+struct BaselineTokenStorage {
+    name: String,
+    symbol: String,
+    decimals: u32,
+    xlm_sac: Address,
+    xlm_contract: Address,
+    asset_contract: Address,
+    pegged_asset: Symbol,
+    min_collat_ratio: u32,
+    total_xasset: i128,
+    total_collateral: i128,
+    product_constant: i128,
+    compounded_constant: i128,
+    epoch: u64,
+    fees_collected: i128,
+    deposit_fee: i128,
+    stake_fee: i128,
+    unstake_return: i128,
+    interest_rate: u32,
+    interest_collected: i128,
+}
+
+/// Stability pool position as stored before storage was versioned
+#[contracttype]
+struct BaselineStakerPosition {
+    xasset_deposit: i128,
+    product_constant: i128,
+    compounded_constant: i128,
+    epoch: u64,
+}
+
+#[test]
+fn test_migrate_from_unversioned_storage() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let initial_time = 1700000000;
+    Ledger::set_timestamp(&e.ledger(), initial_time);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address.clone());
+
+    // Mock prices; an xAsset is worth 10 XLM
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    // Replace the state with what a contract deployed before storage was versioned stores
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    token.mint(&alice, &500_000_000);
+    token.mint(&bob, &1000_0000000);
+    xlm_admin.mint(&token.address, &10_000_000_000);
+    e.as_contract(&token.address, || {
+        let instance = e.storage().instance();
+        instance.remove(&symbol_short!("VERSION"));
+        instance.remove(&symbol_short!("SUPPLY"));
+        instance.set(
+            &symbol_short!("STORAGE"),
+            &BaselineTokenStorage {
+                name: String::from_str(&e, "United States Dollar xAsset"),
+                symbol: String::from_str(&e, "xUSD"),
+                decimals: 7,
+                xlm_sac: xlm_token_address.clone(),
+                xlm_contract: xlm_contract.clone(),
+                asset_contract: xlm_contract.clone(),
+                pegged_asset: Symbol::new(&e, "USDT"),
+                min_collat_ratio: 11000,
+                total_xasset: 1000_0000000,
+                total_collateral: 0,
+                product_constant: 1_000_000_000,
+                compounded_constant: 0,
+                epoch: 1,
+                fees_collected: 3,
+                deposit_fee: 1_000_000,
+                stake_fee: 7_000_000,
+                unstake_return: 2_000_000,
+                interest_rate: 11_00,
+                interest_collected: 9,
+            },
+        );
+        let persistent = e.storage().persistent();
+        persistent.set(
+            &DataKey::CDP(alice.clone()),
+            &LegacyCDP {
+                xlm_deposited: 10_000_000_000,
+                asset_lent: 500_000_000,
+                status: CDPStatus::Open,
+                last_interest_time: initial_time,
+                accrued_interest: Interest {
+                    amount: 1_000_000,
+                    paid: 2_000_000,
+                },
+            },
+        );
+        persistent.set(
+            &DataKey::StakerPosition(bob.clone()),
+            &BaselineStakerPosition {
+                xasset_deposit: 1000_0000000,
+                product_constant: 1_000_000_000,
+                compounded_constant: 0,
+                epoch: 1,
+            },
+        );
+        persistent.set(&LegacyDataKey::CompoundRecord(0), &5_i128);
+    });
+
+    // One year on, the upgraded contract converts it
+    Ledger::set_timestamp(&e.ledger(), initial_time + 31_536_000);
+    assert_eq!(token.total_supply(), 0);
+    token.migrate();
+
+    // An account is converted when first used, counting its balance towards the supply
+    assert_eq!(token.cdp(&alice).asset_lent, 500_000_000);
+    assert_eq!(token.total_supply(), 500_000_000);
+    token.migrate_accounts(&vec![&e, alice.clone(), bob.clone()]);
+    assert_eq!(token.total_supply(), 1050_0000000);
+    assert_eq!(token.get_total_xasset(), 1000_0000000);
+    assert_eq!(
+        token.get_pool_fees(),
+        PoolFees {
+            deposit_fee: 1_000_000,
+            stake_fee: 7_000_000,
+            unstake_return: 2_000_000,
+        }
+    );
+    assert_eq!(token.get_total_interest_collected(), 9);
+    assert_eq!(token.get_treasury_interest(), 9);
+
+    // The CDP's simple interest is brought up to date, and the XLM it paid kept apart
+    let cdp = token.cdp(&alice);
+    assert_eq!(cdp.asset_lent, 500_000_000);
+    assert_eq!(cdp.accrued_interest.amount, 56_000_000);
+    assert_eq!(cdp.accrued_interest.paid, 0);
+    assert_eq!(token.legacy_interest_paid(&alice), 2_000_000);
+    assert_eq!(token.legacy_interest_paid(&bob), 0);
+    assert_eq!(token.get_total_outstanding_interest(), 56_000_000);
+    assert_eq!(token.get_staker_deposit_amount(&bob), 1000_0000000);
+    e.as_contract(&token.address, || {
+        let persistent = e.storage().persistent();
+        assert!(!persistent.has(&LegacyDataKey::CompoundRecord(0)));
+        assert_eq!(persistent.get(&DataKey::CompoundRecord(0, 0)), Some(5_i128));
+    });
+
+    // Converted state is left as it is
+    token.migrate();
+    assert_eq!(token.total_supply(), 1050_0000000);
+    token.migrate_accounts(&vec![&e, alice.clone(), bob.clone()]);
+    assert_eq!(token.cdp(&alice).accrued_interest.amount, 56_000_000);
+    assert_eq!(token.get_total_outstanding_interest(), 56_000_000);
+
+    // and keeps working
+    xlm_admin.mint(&alice, &1_000_000_000);
+    token.stake(&alice, &100_000_000);
+    token.pay_interest(&alice, &56_000_000);
+    assert_eq!(token.cdp(&alice).accrued_interest.paid, 56_000_000);
+    assert_eq!(token.get_total_outstanding_interest(), 0);
+    token.finish_migration();
+    token.mint(&bob, &1);
+    assert_eq!(token.total_supply(), 1050_0000001);
+}
+
+#[test]
+fn test_merge_cdps_with_interest_rates() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let xlm_admin_address = Address::generate(&e);
+    let (_, xlm_admin) = create_sac_token_clients(&e, &xlm_admin_address);
+    let xlm_token_address = xlm_admin.address.clone();
+    let datafeed = create_data_feed(&e);
+    let admin: Address = Address::generate(&e);
+    let token = create_token_contract(&e, admin, datafeed, xlm_token_address);
+
+    // Mock prices
+    let xlm_contract = token.xlm_contract();
+    let client = data_feed::Client::new(&e, &xlm_contract);
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &10_000_000_000_000,
+        &1000,
+    );
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "USDT")),
+        &100_000_000_000_000,
+        &1000,
+    );
+
+    let alice = Address::generate(&e);
+    let bob = Address::generate(&e);
+    let carol = Address::generate(&e);
+    for lender in [&alice, &bob, &carol] {
+        xlm_admin.mint(lender, &2_000_000_000_000);
+        token.open_cdp(lender, &10_000_000_000, &700_000_000);
+    }
+    token.set_cdp_rate_bounds(&1_00, &20_00);
+    token.set_cdp_interest_rate(&alice, &Some(5_00));
+    token.set_cdp_interest_rate(&bob, &Some(5_00));
+    token.set_cdp_interest_rate(&carol, &Some(10_00));
+
+    // Halve the XLM price and freeze all three
+    client.set_asset_price(
+        &Asset::Other(Symbol::new(&e, "XLM")),
+        &5_000_000_000_000,
+        &1000,
+    );
+    for lender in [&alice, &bob, &carol] {
+        token.freeze_cdp(lender);
+    }
+
+    // CDPs at different rates cannot be merged
+    let result = token.try_merge_cdps(&vec![&e, alice.clone(), carol.clone()]);
+    assert_eq!(result.unwrap_err().unwrap(), Error::InvalidMerge);
+
+    // and the merged CDP keeps the rate they share
+    token.merge_cdps(&vec![&e, alice.clone(), bob.clone()]);
+    let merged = token.cdp(&alice);
+    assert_eq!(merged.asset_lent, 1_400_000_000);
+    assert_eq!(merged.interest_rate, Some(5_00));
+    assert!(token.try_cdp(&bob).is_err());
+}
@@ -1,17 +1,26 @@
 use core::cmp;
 
 use soroban_sdk::{
-    self, Address, BytesN, Env, MuxedAddress, String, Symbol, Vec, assert_with_error, contract,
-    contractimpl, contracttype, panic_with_error, symbol_short,
+    self, Address, BytesN, Env, IntoVal, Map, MuxedAddress, String, Symbol, Val, Vec,
+    assert_with_error, contract, contractimpl, contracttype, panic_with_error, symbol_short,
     token::{TokenClient, TokenInterface},
+    xdr::ToXdr,
 };
 
 use crate::{
     Error, PriceData,
-    collateralized::{CDPContract, CDPStatus, IsCDPAdmin, IsCollateralized},
-    data_feed,
-    stability_pool::{AvailableAssets, IsStabilityPool, StakerPosition},
-    storage::{Allowance, CDPInternal, Interest, InterestDetail, Txn},
+    collateralized::{
+        CDPContract, CDPHealth, CDPStatus, CollateralRegistryClient, CollateralTotals,
+        CollateralType, InterestPayment, IsCDPAdmin, IsCollateralized,
+    },
+    data_feed, events,
+    migration::{LegacyCDP, LegacyDataKey},
+    rate_model::{RateInputs, RateModel},
+    stability_pool::{AvailableAssets, FeeAsset, IsStabilityPool, PoolFees, StakerPosition},
+    storage::{
+        Allowance, BorrowFee, CDPInternal, ComplianceMode, Interest, InterestDetail, Permit,
+        RateDebt, StoredCDP, Txn,
+    },
 };
 const VERSION_STRING: &str = concat!(
     env!("CARGO_PKG_VERSION_MAJOR"),
@@ -21,14 +30,23 @@ const VERSION_STRING: &str = concat!(
     env!("CARGO_PKG_VERSION_PATCH")
 );
 const BASIS_POINTS: i128 = 10_000;
-const PRODUCT_CONSTANT: i128 = 1_000_000_000;
+const PRODUCT_CONSTANT: i128 = 1_000_000_000_000_000_000;
+// The product constant is scaled up by this factor whenever it would drop below it
+const SCALE_FACTOR: i128 = 1_000_000_000;
 const DEPOSIT_FEE: i128 = 10_000_000;
 const STAKE_FEE: i128 = 70_000_000;
 const UNSTAKE_RETURN: i128 = 20_000_000;
 // Constants for interest calculation
 const SECONDS_PER_YEAR: u64 = 31_536_000; // 365 days
-const INTEREST_PRECISION: i128 = 1_000_000_000; // 9 decimal places for precision
+pub(crate) const INTEREST_PRECISION: i128 = 1_000_000_000_000; // 12 decimal places for the interest index
+// Debt divided by the interest index keeps 3 more decimal places than the debt itself
+pub(crate) const NORMALIZED_DEBT_PRECISION: i128 = 1_000 * INTEREST_PRECISION;
 const DEFAULT_PRECISION: i128 = 10_000_000; // 7 decimal places for precision
+const MAX_CDP_INTEREST_RATE: u32 = 25_000; // 250%
+const RATE_ADJUSTMENT_COOLDOWN: u64 = 604_800; // 7 days
+const LIQUIDATION_WINDOW: u64 = 86_400; // 1 day
+// Constants for the borrowing fee
+const BASE_RATE_HALF_LIFE: u64 = 43_200; // 12 hours
 
 fn assert_positive(env: &Env, value: i128) {
     assert_with_error!(env, value > 0, Error::ValueNotPositive);
@@ -59,6 +77,17 @@ fn bankers_round(value: i128, precision: i128) -> i128 {
     }
 }
 
+/// Divide non-negative values, rounding half to even like `bankers_round`
+fn bankers_div(numerator: i128, denominator: i128) -> i128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    match (2 * remainder).cmp(&denominator) {
+        cmp::Ordering::Less => quotient,
+        cmp::Ordering::Greater => quotient + 1,
+        cmp::Ordering::Equal => quotient + quotient % 2,
+    }
+}
+
 fn calculate_collateralization_ratio(
     asset_lent: i128,
     xasset_price: i128,
@@ -81,14 +110,31 @@ fn calculate_collateralization_ratio(
     } else {
         // Include accrued interest in the calculation: (a - i)b / (mp)
         let effective_xlm = xlm_deposited.saturating_sub(accrued_interest);
+        // Ratios too high to represent saturate, so the ratio only ever falls as debt grows
         (BASIS_POINTS * effective_xlm * xlm_price * 10i128.pow(numer_decimals)
-            / (asset_lent * 10i128.pow(denom_decimals) * xasset_price)) as u32
+            / (asset_lent * 10i128.pow(denom_decimals) * xasset_price))
+            .try_into()
+            .unwrap_or(u32::MAX)
     };
     collateralization_ratio
 }
 
+/// Largest value in `lo..=hi` for which `ok` holds, given that it holds for every value up to
+/// some point and for none past it; `lo` if it holds for none
+fn max_where(mut lo: i128, mut hi: i128, ok: impl Fn(i128) -> bool) -> i128 {
+    while lo < hi {
+        let mid = lo + (hi - lo + 1) / 2;
+        if ok(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    lo
+}
+
 // Persistent storage keys
-#[contracttype]
+#[contracttype(export = false)]
 pub enum DataKey {
     /// Mapping of account addresses to their token balances
     Balance(Address),
@@ -101,18 +147,69 @@ pub enum DataKey {
     /* Stability pool fields */
     /// Stability pool deposits
     StakerPosition(Address), // deposits: PersistentMap<Address, StakerPosition>,
-    /// Stability pool compound records
-    CompoundRecord(u64), // compound_record: PersistentMap<u64, i128>,
+    /// Stability pool compound records, by epoch and scale
+    CompoundRecord(u64, u64), // compound_record: PersistentMap<(u64, u64), i128>,
     /// Stability pool interest collected records
     InterestRecord(u64), // interest_record: PersistentMap<u64, i128>,
+    /// Stability pool interest constant records, kept like compound records
+    InterestConstantRecord(u64, u64),
+    /// Operators allowed to act on behalf of an owner, by owner and operator
+    Operator(Address, Address),
+    /// Recipient of an owner's stability pool rewards, if not the owner
+    RewardsRecipient(Address),
+    /// Next permit nonce of an owner
+    PermitNonce(Address),
+    /// Key an owner signs permits with
+    PermitKey(Address),
+    /// Totals of a registered collateral type, by SAC token address
+    CollateralTotals(Address),
+    /// Current stability pool reward constant of a collateral type, kept like the compounded
+    /// constant
+    CollateralConstant(Address),
+    /// Stability pool reward constant records of a collateral type, by epoch and scale
+    CollateralRecord(Address, u64, u64),
+    /// Accounts converted from the layout stored before storage was versioned
+    Migrated(Address),
+    /// XLM a CDP paid in interest before storage was versioned, by owner
+    LegacyInterestPaid(Address),
 }
 
 const ADMIN_KEY: Symbol = symbol_short!("ADMIN");
 
+// Instance storage for the pool receipt contract, if set
+const RECEIPT_KEY: Symbol = symbol_short!("RECEIPT");
+
+// Instance storage for the CDP manager contract, if set
+const MANAGER_KEY: Symbol = symbol_short!("MANAGER");
+
+// Instance storage for the total xAsset supply
+const SUPPLY_KEY: Symbol = symbol_short!("SUPPLY");
+
+// Instance storage for the compliance mode, if not open
+const COMPLIANCE_KEY: Symbol = symbol_short!("COMPLY");
+
+// Instance storage for the collateral registry contract, if set
+const COLLATERAL_KEY: Symbol = symbol_short!("COLLAT");
+
+// Instance storage for the debt of CDPs with borrower-chosen rates, by rate
+const RATE_DEBT_KEY: Symbol = symbol_short!("RATEDEBT");
+
+// Instance storage for when the count of frozen CDPs awaiting liquidation last rose above 0
+const FROZEN_SINCE_KEY: Symbol = symbol_short!("FROZEN");
+
+// Instance storage flag, set by `migrate` until `finish_migration`; while set, accounts may still
+// be stored in the layout from before storage was versioned
+const MIGRATING_KEY: Symbol = symbol_short!("MIGRATING");
+
+// Instance storage for the layout version of all storage; contracts deployed before it was
+// introduced store none, and are converted by `migrate`
+const STORAGE_VERSION_KEY: Symbol = symbol_short!("VERSION");
+const STORAGE_VERSION: u32 = 1;
+
 // Instance storage
 const STORAGE: Symbol = symbol_short!("STORAGE");
 
-#[contracttype]
+#[contracttype(export = false)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct TokenStorage {
     /// Name of the token
@@ -141,21 +238,123 @@ pub struct TokenStorage {
     compounded_constant: i128,
     /// current epoch of the stability pool
     epoch: u64,
+    /// current scale of the stability pool within its epoch
+    scale: u64,
+    /// current interest constant of the stability pool
+    interest_constant: i128,
     /// current total of collected fees for stability pool
     fees_collected: i128,
-    /// stability pool deposit fee
-    deposit_fee: i128,
-    /// stability pool stake fee
-    stake_fee: i128,
-    /// stability pool fee amount returned upon unstaking
-    unstake_return: i128,
+    /// current total of stability pool fees collected in xAsset
+    xasset_fees_collected: i128,
+    /// stability pool fees
+    pool_fees: PoolFees,
+    /// total XLM owed to open stability pool positions upon unstaking
+    unstake_returns_owed: i128,
+    /// Total XLM deposited as collateral across all CDPs
+    total_cdp_collateral: i128,
+    /// Number of frozen CDPs; stability pool withdrawals are blocked while any awaits liquidation
+    frozen_cdps: u32,
+    /// Recipient of swept fees and the treasury's share of interest
+    treasury: Option<Address>,
     /// Annual interest rate in basis points (e.g., 500 = 5%)
     interest_rate: u32,
     /// Total interest collected (in XLM) by the protocol
     interest_collected: i128,
+    /// Share of interest collected in XLM, in basis points, distributed to stability pool stakers
+    interest_share: u32,
+    /// Interest (in XLM) distributed to stability pool stakers and not yet claimed
+    interest_rewards: i128,
+    /// Interest (in XLM) retained for the protocol treasury
+    treasury_interest: i128,
+    /// How `interest_rate` is set; re-derived from the model on every accrual unless `Fixed`
+    rate_model: RateModel,
+    /// Cumulative borrow index, compounded at `interest_rate`; starts at `INTEREST_PRECISION`
+    interest_index: i128,
+    /// Unix timestamp of the last `interest_index` update
+    interest_index_time: u64,
+    /// Sum of the normalized debt of all CDPs accruing interest
+    total_normalized_debt: i128,
+    /// Sum of the principal of all CDPs accruing interest
+    total_principal: i128,
+    /// Lowest annual rate, in basis points, a borrower may choose for their CDP
+    min_cdp_rate: u32,
+    /// Highest annual rate, in basis points, a borrower may choose for their CDP
+    max_cdp_rate: u32,
+    /// Seconds after changing a CDP's rate during which another change is charged a fee
+    rate_adjustment_cooldown: u64,
+    /// One-time borrowing fee charged on newly minted xAsset
+    borrow_fee: BorrowFee,
+    /// Asset CDP interest is paid in, and where xAsset interest goes
+    interest_payment: InterestPayment,
 }
 
 impl TokenStorage {
+    /// State of a newly deployed contract
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        env: &Env,
+        xlm_sac: Address,
+        xlm_contract: Address,
+        asset_contract: Address,
+        pegged_asset: Symbol,
+        min_collat_ratio: u32,
+        name: String,
+        symbol: String,
+        decimals: u32,
+        annual_interest_rate: u32,
+    ) -> TokenStorage {
+        TokenStorage {
+            name,
+            symbol,
+            decimals,
+            xlm_sac,
+            xlm_contract,
+            asset_contract,
+            pegged_asset,
+            min_collat_ratio,
+            total_xasset: 0,
+            total_collateral: 0,
+            product_constant: PRODUCT_CONSTANT,
+            compounded_constant: 0,
+            epoch: 0,
+            scale: 0,
+            interest_constant: 0,
+            fees_collected: 0,
+            xasset_fees_collected: 0,
+            pool_fees: PoolFees {
+                deposit_fee: DEPOSIT_FEE,
+                stake_fee: STAKE_FEE,
+                unstake_return: UNSTAKE_RETURN,
+            },
+            unstake_returns_owed: 0,
+            total_cdp_collateral: 0,
+            frozen_cdps: 0,
+            treasury: None,
+            interest_rate: annual_interest_rate,
+            interest_collected: 0,
+            interest_share: 0,
+            interest_rewards: 0,
+            treasury_interest: 0,
+            rate_model: RateModel::Fixed,
+            interest_index: INTEREST_PRECISION,
+            interest_index_time: env.ledger().timestamp(),
+            total_normalized_debt: 0,
+            total_principal: 0,
+            min_cdp_rate: 0,
+            max_cdp_rate: MAX_CDP_INTEREST_RATE,
+            rate_adjustment_cooldown: RATE_ADJUSTMENT_COOLDOWN,
+            borrow_fee: BorrowFee {
+                floor: 0,
+                cap: 0,
+                base_rate: 0,
+                base_rate_time: env.ledger().timestamp(),
+                base_rate_half_life: BASE_RATE_HALF_LIFE,
+                recipient: None,
+            },
+            interest_payment: InterestPayment::Xlm,
+        }
+    }
+
     /// Get current state of the contract
     fn get_state(env: &Env) -> TokenStorage {
         env.storage().instance().get(&STORAGE).unwrap()
@@ -165,14 +364,154 @@ impl TokenStorage {
         env.storage().instance().set(&STORAGE, &storage);
     }
 
-    // Get internal CDP for a given lender
+    // Get internal CDP for a given lender, with interest accrued up to now
     fn get_cdp(env: &Env, lender: Address) -> Option<CDPInternal> {
-        env.storage()
+        TokenContract::migrate_account(env, &lender);
+        let stored: StoredCDP = env
+            .storage()
             .persistent()
-            .get(&DataKey::CDP(lender.clone()))
+            .get(&DataKey::CDP(lender.clone()))?;
+        Some(TokenStorage::load_cdp(env, &stored))
+    }
+
+    /// A stored CDP with interest accrued up to now
+    fn load_cdp(env: &Env, stored: &StoredCDP) -> CDPInternal {
+        let mut cdp = stored.to_internal(TokenContract::interest_index_at(
+            env,
+            env.ledger().timestamp(),
+        ));
+        let (interest, last_interest_time) = TokenContract::get_updated_accrued_interest(env, &cdp);
+        cdp.accrued_interest = interest;
+        cdp.last_interest_time = last_interest_time;
+        cdp
+    }
+
+    /// Add (or, if `sign` is -1, remove) a stored CDP's contribution to the global debt totals
+    fn add_to_debt_totals(env: &Env, cdp: &StoredCDP, sign: i128) {
+        if !cdp.accrues_interest() {
+            return;
+        }
+        let mut state = TokenStorage::get_state(env);
+        let Some(total_principal) = state.total_principal.checked_add(sign * cdp.asset_lent) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        state.total_principal = total_principal;
+        match cdp.interest_rate {
+            None => {
+                let Some(total_normalized_debt) = state
+                    .total_normalized_debt
+                    .checked_add(sign * cdp.normalized_debt)
+                else {
+                    panic_with_error!(env, Error::ArithmeticError);
+                };
+                state.total_normalized_debt = total_normalized_debt;
+            }
+            Some(rate) => {
+                // The rate's total has been compounding this CDP's debt since it was stored
+                let now = env.ledger().timestamp();
+                let current_debt = cdp.normalized_debt
+                    * TokenContract::compound_factor(
+                        rate,
+                        now.saturating_sub(cdp.last_interest_time),
+                    )
+                    / INTEREST_PRECISION;
+                let mut rate_debts = TokenContract::rate_debts(env);
+                let total = rate_debts
+                    .get(rate)
+                    .map_or(0, |total| TokenContract::rate_debt_at(&total, rate, now));
+                let Some(total) = total.checked_add(sign * current_debt) else {
+                    panic_with_error!(env, Error::ArithmeticError);
+                };
+                // Rounding may leave dust once the rate's last CDP is removed
+                if total > 0 {
+                    rate_debts.set(
+                        rate,
+                        RateDebt {
+                            debt: total,
+                            time: now,
+                        },
+                    );
+                } else {
+                    rate_debts.remove(rate);
+                }
+                env.storage().instance().set(&RATE_DEBT_KEY, &rate_debts);
+            }
+        }
+        TokenStorage::set_state(env, &state);
+    }
+
+    fn add_cdp_collateral(env: &Env, amount: i128) {
+        let mut state = TokenStorage::get_state(env);
+        let Some(new_total) = state.total_cdp_collateral.checked_add(amount) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        state.total_cdp_collateral = new_total;
+        TokenStorage::set_state(env, &state);
+    }
+
+    /// Add (or, if `sign` is -1, remove) a stored CDP's contribution to the totals of each
+    /// collateral type it holds
+    fn add_collateral_totals(env: &Env, cdp: &StoredCDP, sign: i128) {
+        for (token, amount) in cdp.collateral.iter() {
+            let mut totals = TokenContract::collateral_totals(env, &token);
+            let (Some(deposited), Some(debt)) = (
+                totals.deposited.checked_add(sign * amount),
+                totals.debt.checked_add(sign * cdp.asset_lent),
+            ) else {
+                panic_with_error!(env, Error::ArithmeticError);
+            };
+            totals.deposited = deposited;
+            totals.debt = debt;
+            TokenContract::set_collateral_totals(env, &token, &totals);
+        }
+    }
+
+    /// Count (or, if `sign` is -1, stop counting) a stored CDP if it can be liquidated
+    fn add_frozen_cdp(env: &Env, cdp: &StoredCDP, sign: i32) {
+        if !cdp.is_liquidatable() {
+            return;
+        }
+        let mut state = TokenStorage::get_state(env);
+        let Some(frozen_cdps) = state.frozen_cdps.checked_add_signed(sign) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        if frozen_cdps == 0 {
+            env.storage().instance().remove(&FROZEN_SINCE_KEY);
+        } else if state.frozen_cdps == 0 {
+            let now = env.ledger().timestamp();
+            env.storage().instance().set(&FROZEN_SINCE_KEY, &now);
+        }
+        state.frozen_cdps = frozen_cdps;
+        TokenStorage::set_state(env, &state);
     }
 
-    fn set_cdp(env: &Env, lender: Address, cdp: CDPInternal) {
+    /// Store a CDP whose `accrued_interest` is current, normalizing its debt by the interest index
+    fn set_cdp(env: &Env, lender: Address, mut cdp: CDPInternal) {
+        TokenContract::migrate_account(env, &lender);
+        cdp.interest_index = TokenContract::accrue_interest_index(env);
+        cdp.last_interest_time = env.ledger().timestamp();
+        let previous = env
+            .storage()
+            .persistent()
+            .get::<_, StoredCDP>(&DataKey::CDP(lender.clone()));
+        let previous_cdp = previous
+            .as_ref()
+            .map(|previous| TokenStorage::load_cdp(env, previous));
+        if let Err(error) = TokenContract::check_debt_ceilings(env, previous_cdp.as_ref(), &cdp) {
+            panic_with_error!(env, error);
+        }
+        if let Some(previous) = previous {
+            TokenStorage::add_to_debt_totals(env, &previous, -1);
+            TokenStorage::add_cdp_collateral(env, -previous.xlm_deposited);
+            TokenStorage::add_collateral_totals(env, &previous, -1);
+            TokenStorage::add_frozen_cdp(env, &previous, -1);
+        }
+        let stored = cdp.to_stored();
+        TokenStorage::add_to_debt_totals(env, &stored, 1);
+        TokenStorage::add_cdp_collateral(env, stored.xlm_deposited);
+        TokenStorage::add_collateral_totals(env, &stored, 1);
+        TokenStorage::add_frozen_cdp(env, &stored, 1);
+
         env.events().publish(
             (Symbol::new(env, "CDP"), lender.clone()),
             crate::index_types::CDP {
@@ -191,25 +530,33 @@ impl TokenStorage {
         // Set CDP
         env.storage()
             .persistent()
-            .set(&DataKey::CDP(lender.clone()), &cdp);
+            .set(&DataKey::CDP(lender.clone()), &stored);
 
         // Extend TTL
         let ttl = env.storage().max_ttl();
         env.storage()
             .persistent()
             .extend_ttl(&DataKey::CDP(lender.clone()), ttl, ttl);
+
+        TokenContract::update_interest_rate(env);
     }
 
     fn remove_cdp(env: &Env, lender: Address) {
+        TokenContract::migrate_account(env, &lender);
+        if let Some(previous) = env
+            .storage()
+            .persistent()
+            .get::<_, StoredCDP>(&DataKey::CDP(lender.clone()))
+        {
+            TokenStorage::add_to_debt_totals(env, &previous, -1);
+            TokenStorage::add_cdp_collateral(env, -previous.xlm_deposited);
+            TokenStorage::add_collateral_totals(env, &previous, -1);
+            TokenStorage::add_frozen_cdp(env, &previous, -1);
+        }
         env.storage()
             .persistent()
             .remove(&DataKey::CDP(lender.clone()));
-    }
-
-    fn set_interest_collected(env: &Env, amount: i128) {
-        let mut state = TokenStorage::get_state(env);
-        state.interest_collected = amount;
-        TokenStorage::set_state(env, &state);
+        TokenContract::update_interest_rate(env);
     }
 }
 
@@ -233,28 +580,22 @@ impl TokenContract {
         annual_interest_rate: u32,
     ) {
         Self::set_admin(env, &admin);
-        let token = TokenStorage {
-            name,
-            symbol,
-            decimals,
+        let token = TokenStorage::new(
+            env,
             xlm_sac,
             xlm_contract,
             asset_contract,
             pegged_asset,
             min_collat_ratio,
-            total_xasset: 0,
-            total_collateral: 0,
-            product_constant: PRODUCT_CONSTANT,
-            compounded_constant: 0,
-            epoch: 0,
-            fees_collected: 0,
-            deposit_fee: DEPOSIT_FEE,
-            stake_fee: STAKE_FEE,
-            unstake_return: UNSTAKE_RETURN,
-            interest_rate: annual_interest_rate,
-            interest_collected: 0,
-        };
+            name,
+            symbol,
+            decimals,
+            annual_interest_rate,
+        );
         TokenStorage::set_state(env, &token);
+        env.storage()
+            .instance()
+            .set(&STORAGE_VERSION_KEY, &STORAGE_VERSION);
     }
 
     /// Upgrade the contract to new wasm. Admin-only.
@@ -263,6 +604,135 @@ impl TokenContract {
         env.deployer().update_current_contract_wasm(new_wasm_hash);
     }
 
+    /// Convert state stored before storage was versioned, once. Accounts are converted as they
+    /// are first used, or by `migrate_accounts`. Admin-only.
+    pub fn migrate(env: &Env) {
+        Self::require_admin(env);
+        if env.storage().instance().has(&STORAGE_VERSION_KEY) {
+            return;
+        }
+        env.storage().instance().set(&MIGRATING_KEY, &true);
+        // A contract type is stored as a map of its fields, so the legacy state overwrites every
+        // field it shares with the current layout, including the placeholders passed here
+        let legacy: Map<Symbol, Val> = env.storage().instance().get(&STORAGE).unwrap();
+        let contract = env.current_contract_address();
+        let name = String::from_str(env, "");
+        let state: Val = TokenStorage::new(
+            env,
+            contract.clone(),
+            contract.clone(),
+            contract,
+            symbol_short!(""),
+            0,
+            name.clone(),
+            name,
+            0,
+            0,
+        )
+        .into_val(env);
+        let mut state: Map<Symbol, Val> = state.into_val(env);
+        for (key, value) in legacy.iter() {
+            if state.contains_key(key.clone()) {
+                state.set(key, value);
+            }
+        }
+        let field = |name| legacy.get_unchecked(name);
+        let pool_fees = PoolFees {
+            deposit_fee: field(Symbol::new(env, "deposit_fee")).into_val(env),
+            stake_fee: field(symbol_short!("stake_fee")).into_val(env),
+            unstake_return: field(Symbol::new(env, "unstake_return")).into_val(env),
+        };
+        state.set(symbol_short!("pool_fees"), pool_fees.into_val(env));
+        // Interest collected so far was all retained by the protocol
+        state.set(
+            Symbol::new(env, "treasury_interest"),
+            field(Symbol::new(env, "interest_collected")),
+        );
+        env.storage().instance().set(&STORAGE, &state);
+
+        // Each past epoch recorded its compounded constant once, now kept as its first scale's
+        let epoch: u64 = field(symbol_short!("epoch")).into_val(env);
+        for epoch in 0..epoch {
+            let key = LegacyDataKey::CompoundRecord(epoch);
+            if let Some(record) = env.storage().persistent().get::<_, i128>(&key) {
+                env.storage().persistent().remove(&key);
+                Self::set_compound_record(env, epoch, 0, &record);
+            }
+        }
+        env.storage()
+            .instance()
+            .set(&STORAGE_VERSION_KEY, &STORAGE_VERSION);
+    }
+
+    /// Convert the balances, CDPs and stability pool positions of `accounts` stored before
+    /// storage was versioned, counting their balances towards the supply. Admin-only.
+    pub fn migrate_accounts(env: &Env, accounts: Vec<Address>) {
+        Self::require_admin(env);
+        for account in accounts.iter() {
+            Self::migrate_account(env, &account);
+        }
+    }
+
+    /// Stop converting accounts once all have been, so using them no longer checks. Admin-only.
+    pub fn finish_migration(env: &Env) {
+        Self::require_admin(env);
+        env.storage().instance().remove(&MIGRATING_KEY);
+    }
+
+    /// XLM `lender`'s CDP paid in interest before storage was versioned, when interest paid was
+    /// counted in XLM
+    pub fn legacy_interest_paid(env: &Env, lender: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::LegacyInterestPaid(lender))
+            .unwrap_or(0)
+    }
+
+    /// Allow or disallow `operator` to claim `owner`'s stability pool rewards, add collateral to
+    /// their CDP and repay its debt. Operators pay from their own balances, and rewards are
+    /// still paid to the owner's rewards recipient.
+    pub fn set_operator(env: &Env, owner: Address, operator: Address, allowed: bool) {
+        owner.require_auth();
+        let key = DataKey::Operator(owner, operator);
+        if allowed {
+            env.storage().persistent().set(&key, &true);
+            let ttl = env.storage().max_ttl();
+            env.storage().persistent().extend_ttl(&key, ttl, ttl);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+    }
+
+    /// Whether `operator` may act on behalf of `owner`
+    pub fn is_operator(env: &Env, owner: Address, operator: Address) -> bool {
+        env.storage()
+            .persistent()
+            .has(&DataKey::Operator(owner, operator))
+    }
+
+    /// Set where `owner`'s stability pool rewards are paid, whoever claims them. If `None`, they
+    /// are paid to `owner`.
+    pub fn set_rewards_recipient(env: &Env, owner: Address, recipient: Option<Address>) {
+        owner.require_auth();
+        let key = DataKey::RewardsRecipient(owner);
+        match recipient {
+            Some(recipient) => {
+                env.storage().persistent().set(&key, &recipient);
+                let ttl = env.storage().max_ttl();
+                env.storage().persistent().extend_ttl(&key, ttl, ttl);
+            }
+            None => env.storage().persistent().remove(&key),
+        }
+    }
+
+    /// Get where `owner`'s stability pool rewards are paid
+    pub fn rewards_recipient(env: &Env, owner: Address) -> Address {
+        env.storage()
+            .persistent()
+            .get(&DataKey::RewardsRecipient(owner.clone()))
+            .unwrap_or(owner)
+    }
+
     /// Get the admin address
     fn admin(env: &Env) -> Option<Address> {
         env.storage().instance().get(&ADMIN_KEY)
@@ -282,6 +752,24 @@ impl TokenContract {
         admin.require_auth();
     }
 
+    /// Fail with `NotAuthorized` unless `id` is permitted under the current compliance mode
+    fn require_authorized(env: &Env, id: &Address) -> Result<(), Error> {
+        if Self::is_permitted(env, id.clone()) {
+            Ok(())
+        } else {
+            Err(Error::NotAuthorized)
+        }
+    }
+
+    fn require_operator(env: &Env, owner: &Address, operator: &Address) {
+        operator.require_auth();
+        assert_with_error!(
+            env,
+            Self::is_operator(env, owner.clone(), operator.clone()),
+            Error::NotOperator
+        );
+    }
+
     // Fungible implementation. Implemented in a second impl block to reduce code diff in loam-migration
     fn set_and_extend_allowance(
         env: &Env,
@@ -305,9 +793,79 @@ impl TokenContract {
                 live_until_ledger,
             },
         );
+        env.storage().persistent().extend_ttl(
+            &Txn(from.clone(), spender.clone()),
+            max_ttl,
+            max_ttl,
+        );
+        events::Approve {
+            from,
+            spender,
+            amount,
+            live_until_ledger,
+        }
+        .publish(env);
+    }
+
+    /// Set an allowance from a permit signed off-chain with the owner's permit key, so that an
+    /// integrator can approve and act in a single call. Anyone may submit the permit.
+    pub fn permit(env: &Env, permit: Permit, signature: BytesN<64>) -> Result<(), Error> {
+        let Some(owner_key) = Self::permit_key(env, permit.owner.clone()) else {
+            return Err(Error::InvalidPermit);
+        };
+        if permit.network_id != env.ledger().network_id()
+            || permit.contract != env.current_contract_address()
+            || permit.nonce != Self::permit_nonce(env, permit.owner.clone())
+        {
+            return Err(Error::InvalidPermit);
+        }
+        env.crypto()
+            .ed25519_verify(&owner_key, &permit.clone().to_xdr(env), &signature);
+
+        let key = DataKey::PermitNonce(permit.owner.clone());
+        env.storage().persistent().set(&key, &(permit.nonce + 1));
+        let max_ttl = env.storage().max_ttl();
+        env.storage()
+            .persistent()
+            .extend_ttl(&key, max_ttl, max_ttl);
+        Self::set_and_extend_allowance(
+            env,
+            permit.owner,
+            permit.spender,
+            permit.amount,
+            permit.live_until_ledger,
+        );
+        Ok(())
+    }
+
+    /// Set (or, with `None`, clear) the ed25519 key `owner` signs permits with. Permits are off
+    /// until an owner sets one; they are checked against this key alone, not the account's own
+    /// signers or thresholds, so rotating or removing an account signer does not revoke it.
+    pub fn set_permit_key(env: &Env, owner: Address, key: Option<BytesN<32>>) {
+        owner.require_auth();
+        let data_key = DataKey::PermitKey(owner);
+        let Some(key) = key else {
+            env.storage().persistent().remove(&data_key);
+            return;
+        };
+        env.storage().persistent().set(&data_key, &key);
+        let max_ttl = env.storage().max_ttl();
+        env.storage()
+            .persistent()
+            .extend_ttl(&data_key, max_ttl, max_ttl);
+    }
+
+    /// Return the key `owner` signs permits with, if permits are on
+    pub fn permit_key(env: &Env, owner: Address) -> Option<BytesN<32>> {
+        env.storage().persistent().get(&DataKey::PermitKey(owner))
+    }
+
+    /// Return the nonce the next permit signed by `owner` must carry
+    pub fn permit_nonce(env: &Env, owner: Address) -> u64 {
         env.storage()
             .persistent()
-            .extend_ttl(&Txn(from, spender), max_ttl, max_ttl);
+            .get(&DataKey::PermitNonce(owner))
+            .unwrap_or(0)
     }
 
     /// Increase the allowance that one address can spend on behalf of another address.
@@ -331,17 +889,7 @@ impl TokenContract {
     /// Decrease the allowance that one address can spend on behalf of another address.
     pub fn decrease_allowance(env: &Env, from: Address, spender: Address, amount: i128) {
         from.require_auth();
-        assert_positive(env, amount);
-        let current_allowance = Self::allowance(env.clone(), from.clone(), spender.clone());
-        let new_amount = current_allowance.checked_sub(amount).unwrap_or(0);
-        let current_ledger = env.ledger().sequence();
-        Self::set_and_extend_allowance(
-            env,
-            from,
-            spender,
-            new_amount,
-            current_ledger + 1000, // Example: set to expire after 1000 ledgers
-        );
+        Self::decrease_allowance_internal(env, from, spender, amount);
     }
 
     /// Internal form of decrease_allowance that does not require auth
@@ -349,6 +897,11 @@ impl TokenContract {
         assert_positive(env, amount);
         let current_allowance = Self::allowance(env.clone(), from.clone(), spender.clone());
         let new_amount = current_allowance.checked_sub(amount).unwrap_or(0);
+        if new_amount <= 0 {
+            // Allowances are stored positive, so a spent-out one is removed instead
+            env.storage().persistent().remove(&Txn(from, spender));
+            return;
+        }
         let current_ledger = env.ledger().sequence();
         Self::set_and_extend_allowance(
             env,
@@ -359,8 +912,11 @@ impl TokenContract {
         );
     }
 
-    /// Return the spendable balance of tokens for a specific address
+    /// Return the spendable balance of tokens for a specific address; zero if it is not authorized
     pub fn spendable_balance(env: &Env, id: Address) -> i128 {
+        if !Self::is_permitted(env, id.clone()) {
+            return 0;
+        }
         Self::balance(env.clone(), id)
     }
 
@@ -372,6 +928,33 @@ impl TokenContract {
             .unwrap_or_default()
     }
 
+    /// Check if a specific address may transfer xAsset, open CDPs and join the stability pool
+    /// under the current compliance mode, judged by its `authorized` flag
+    pub fn is_permitted(env: &Env, id: Address) -> bool {
+        let flag: Option<bool> = env.storage().persistent().get(&DataKey::Authorized(id));
+        match Self::compliance_mode(env) {
+            ComplianceMode::Open => true,
+            ComplianceMode::Denylist => flag.unwrap_or(true),
+            ComplianceMode::Allowlist => flag.unwrap_or(false),
+        }
+    }
+
+    /// Set which accounts may transfer xAsset, open CDPs and join the stability pool, judged by
+    /// the flags set with `set_authorized`. Default: [`ComplianceMode::Open`]. Only callable by
+    /// admin.
+    pub fn set_compliance_mode(env: &Env, mode: ComplianceMode) {
+        Self::require_admin(env);
+        env.storage().instance().set(&COMPLIANCE_KEY, &mode);
+    }
+
+    /// Get the current compliance mode
+    pub fn compliance_mode(env: &Env) -> ComplianceMode {
+        env.storage()
+            .instance()
+            .get(&COMPLIANCE_KEY)
+            .unwrap_or_default()
+    }
+
     fn set_and_extend_authorized(env: &Env, id: Address, authorize: bool) {
         let max_ttl = env.storage().max_ttl();
         env.storage()
@@ -390,6 +973,7 @@ impl TokenContract {
     pub fn clawback(env: &Env, from: Address, amount: i128) {
         assert_positive(env, amount);
         Self::require_admin(env);
+        Self::migrate_account(env, &from);
         let Some(new_balance) = Self::balance(env.clone(), from.clone()).checked_sub(amount) else {
             panic_with_error!(env, Error::ArithmeticError);
         };
@@ -399,11 +983,14 @@ impl TokenContract {
         let ttl = env.storage().max_ttl();
         env.storage()
             .persistent()
-            .extend_ttl(&DataKey::Balance(from), ttl, ttl);
+            .extend_ttl(&DataKey::Balance(from.clone()), ttl, ttl);
+        Self::add_supply(env, -amount);
+        events::Clawback { from, amount }.publish(env);
     }
 
     // From second impl block
     /// Decorate a CDPInternal with the collateralization ratio. Also check if the CDP is insolvent.
+    /// Collateral held besides XLM counts at its risk-weighted value in XLM.
     fn decorate(
         env: &Env,
         cdp: CDPInternal,
@@ -412,27 +999,31 @@ impl TokenContract {
         xlm_decimals: u32,
         xasset_price: i128,
         xasset_decimals: u32,
-    ) -> CDPContract {
-        // Update accrued interest first
-        let (interest, last_interest_time) =
-            Self::get_updated_accrued_interest(env, &cdp).unwrap_or_default();
-
+    ) -> Result<CDPContract, Error> {
+        let interest = cdp.accrued_interest;
+        let weighted_collateral =
+            Self::weighted_collateral_xlm(env, &cdp.collateral, xlm_price, xlm_decimals)?;
+        let Some(collateral) = cdp.xlm_deposited.checked_add(weighted_collateral) else {
+            return Err(Error::ArithmeticError);
+        };
         let collateralization_ratio = calculate_collateralization_ratio(
             cdp.asset_lent,
             xasset_price,
-            cdp.xlm_deposited,
+            collateral,
             xlm_price,
             xlm_decimals,
             xasset_decimals,
             interest.amount,
         );
 
-        CDPContract {
+        Ok(CDPContract {
             lender,
             xlm_deposited: cdp.xlm_deposited,
             asset_lent: cdp.asset_lent,
             accrued_interest: interest,
-            last_interest_time,
+            last_interest_time: cdp.last_interest_time,
+            interest_rate: cdp.interest_rate,
+            rate_adjusted_time: cdp.rate_adjusted_time,
             collateralization_ratio,
             status: if matches!(cdp.status, CDPStatus::Open)
                 && collateralization_ratio < Self::minimum_collateralization_ratio(env)
@@ -445,42 +1036,158 @@ impl TokenContract {
             } else {
                 cdp.status
             },
-        }
+            collateral: cdp.collateral,
+        })
     }
 
-    fn set_cdp_from_decorated(env: &Env, lender: Address, decorated_cdp: CDPContract) {
-        env.events().publish(
-            (Symbol::new(env, "CDP"), lender.clone()),
-            crate::index_types::CDP {
-                id: lender.clone(),
-                xlm_deposited: decorated_cdp.xlm_deposited,
-                asset_lent: decorated_cdp.asset_lent,
-                accrued_interest: decorated_cdp.accrued_interest.amount,
-                interest_paid: decorated_cdp.accrued_interest.paid,
-                last_interest_time: decorated_cdp.last_interest_time,
-                status: decorated_cdp.status,
-                ledger: env.ledger().sequence(),
-                timestamp: env.ledger().timestamp(),
-            },
-        );
+    /// Decorate a CDP at the oracles' latest prices
+    fn decorate_now(env: &Env, cdp: CDPInternal, lender: Address) -> Result<CDPContract, Error> {
+        Self::decorate(
+            env,
+            cdp,
+            lender,
+            Self::lastprice_xlm(env)?.price,
+            Self::decimals_xlm_feed(env)?,
+            Self::lastprice_asset(env)?.price,
+            Self::decimals_asset_feed(env)?,
+        )
+    }
 
-        // Set CDP
-        env.storage().persistent().set(
-            &DataKey::CDP(lender.clone()),
-            &CDPInternal {
-                xlm_deposited: decorated_cdp.xlm_deposited,
-                asset_lent: decorated_cdp.asset_lent,
-                status: decorated_cdp.status,
-                last_interest_time: decorated_cdp.last_interest_time,
-                accrued_interest: decorated_cdp.accrued_interest,
-            },
-        );
+    /// Value in XLM of collateral held besides XLM, each type weighted by its collateral factor.
+    /// SAC tokens all have XLM's 7 decimals, so only the oracles' decimals need reconciling.
+    fn weighted_collateral_xlm(
+        env: &Env,
+        collateral: &Map<Address, i128>,
+        xlm_price: i128,
+        xlm_decimals: u32,
+    ) -> Result<i128, Error> {
+        let mut total: i128 = 0;
+        for (token, amount) in collateral.iter() {
+            let collateral_type = Self::collateral_type(env, &token)?;
+            let (price, decimals) = Self::lastprice_collateral(env, &collateral_type)?;
+            let value = if xlm_decimals >= decimals {
+                amount
+                    .checked_mul(price)
+                    .and_then(|value| value.checked_mul(10i128.pow(xlm_decimals - decimals)))
+                    .and_then(|value| value.checked_div(xlm_price))
+            } else {
+                amount.checked_mul(price).and_then(|value| {
+                    value.checked_div(xlm_price * 10i128.pow(decimals - xlm_decimals))
+                })
+            };
+            let Some(new_total) = value
+                .and_then(|value| value.checked_mul(i128::from(collateral_type.collateral_factor)))
+                .and_then(|value| total.checked_add(value / BASIS_POINTS))
+            else {
+                return Err(Error::ArithmeticError);
+            };
+            total = new_total;
+        }
+        Ok(total)
+    }
 
-        // Extend TTL
-        let ttl = env.storage().max_ttl();
+    /// Get the collateral types registered with the collateral registry, by SAC token address
+    fn collateral_types(env: &Env) -> Map<Address, CollateralType> {
+        match Self::get_collateral_registry(env) {
+            Some(registry) => CollateralRegistryClient::new(env, &registry).collateral_types(),
+            None => Map::new(env),
+        }
+    }
+
+    fn collateral_type(env: &Env, token: &Address) -> Result<CollateralType, Error> {
+        Self::collateral_types(env)
+            .get(token.clone())
+            .ok_or(Error::UnknownCollateralType)
+    }
+
+    fn collateral_totals(env: &Env, token: &Address) -> CollateralTotals {
         env.storage()
             .persistent()
-            .extend_ttl(&DataKey::CDP(lender.clone()), ttl, ttl);
+            .get(&DataKey::CollateralTotals(token.clone()))
+            .unwrap_or_default()
+    }
+
+    fn set_collateral_totals(env: &Env, token: &Address, totals: &CollateralTotals) {
+        let key = DataKey::CollateralTotals(token.clone());
+        env.storage().persistent().set(&key, totals);
+        let ttl = env.storage().max_ttl();
+        env.storage().persistent().extend_ttl(&key, ttl, ttl);
+    }
+
+    /// Get the most recent price of a collateral type, and its oracle's decimals
+    fn lastprice_collateral(
+        env: &Env,
+        collateral_type: &CollateralType,
+    ) -> Result<(i128, u32), Error> {
+        let client = data_feed::Client::new(env, &collateral_type.oracle);
+        let price =
+            match client.try_lastprice(&data_feed::Asset::Other(collateral_type.asset.clone())) {
+                Ok(Ok(Some(data_feed::PriceData { price, .. }))) => price,
+                _ => return Err(Error::OraclePriceFetchFailed),
+            };
+        let Ok(Ok(decimals)) = client.try_decimals() else {
+            return Err(Error::OracleDecimalsFetchFailed);
+        };
+        Ok((price, decimals))
+    }
+
+    /// Check that a CDP's principal fits the debt ceiling of each collateral type it holds, where
+    /// its share of that type's debt grows. Frozen CDPs only shrink towards liquidation.
+    fn check_debt_ceilings(
+        env: &Env,
+        previous: Option<&CDPInternal>,
+        cdp: &CDPInternal,
+    ) -> Result<(), Error> {
+        if matches!(cdp.status, CDPStatus::Frozen) {
+            return Ok(());
+        }
+        for token in cdp.collateral.keys() {
+            let previous_debt = previous
+                .filter(|previous| previous.collateral.contains_key(token.clone()))
+                .map_or(0, |previous| previous.asset_lent);
+            if cdp.asset_lent <= previous_debt {
+                continue;
+            }
+            let collateral_type = Self::collateral_type(env, &token)?;
+            let debt = Self::collateral_totals(env, &token).debt - previous_debt + cdp.asset_lent;
+            if debt > collateral_type.debt_ceiling {
+                return Err(Error::DebtCeilingExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Transfer `amount` of a collateral type
+    fn transfer_collateral(
+        env: &Env,
+        token: &Address,
+        from: &Address,
+        to: &Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        let _ = TokenClient::new(env, token)
+            .try_transfer(from, to, &amount)
+            .map_err(|_| Error::CollateralTransferFailed)?;
+        Ok(())
+    }
+
+    fn set_cdp_from_decorated(env: &Env, lender: Address, decorated_cdp: CDPContract) {
+        TokenStorage::set_cdp(
+            env,
+            lender,
+            CDPInternal {
+                xlm_deposited: decorated_cdp.xlm_deposited,
+                asset_lent: decorated_cdp.asset_lent,
+                status: decorated_cdp.status,
+                last_interest_time: decorated_cdp.last_interest_time,
+                accrued_interest: decorated_cdp.accrued_interest,
+                // Set by `set_cdp` to the index `accrued_interest` is current as of
+                interest_index: INTEREST_PRECISION,
+                interest_rate: decorated_cdp.interest_rate,
+                rate_adjusted_time: decorated_cdp.rate_adjusted_time,
+                collateral: decorated_cdp.collateral,
+            },
+        );
     }
 
     fn native(env: &Env) -> TokenClient<'_> {
@@ -494,8 +1201,14 @@ impl TokenContract {
         Self::mint_internal(env, to, amount);
     }
 
+    /// Return the total amount of xAsset in circulation
+    pub fn total_supply(env: &Env) -> i128 {
+        env.storage().instance().get(&SUPPLY_KEY).unwrap_or(0)
+    }
+
     // convenience functions for internal minting / transfering of the ft asset
     fn mint_internal(env: &Env, to: Address, amount: i128) {
+        Self::migrate_account(env, &to);
         let balance: i128 = env
             .storage()
             .persistent()
@@ -507,9 +1220,26 @@ impl TokenContract {
         env.storage()
             .persistent()
             .set(&DataKey::Balance(to.clone()), &new_balance);
+        Self::add_supply(env, amount);
+        events::Mint { to, amount }.publish(env);
+    }
+
+    fn add_supply(env: &Env, amount: i128) {
+        let Some(supply) = Self::total_supply(env).checked_add(amount) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        env.storage().instance().set(&SUPPLY_KEY, &supply);
     }
 
     fn transfer_internal(env: &Env, from: Address, to: Address, amount: i128) {
+        Self::move_balance(env, from.clone(), to.clone(), amount);
+        events::Transfer { from, to, amount }.publish(env);
+    }
+
+    // moves balance without publishing, for callers that publish their own transfer event
+    fn move_balance(env: &Env, from: Address, to: Address, amount: i128) {
+        Self::migrate_account(env, &from);
+        Self::migrate_account(env, &to);
         let curr_from_balance: i128 = env
             .storage()
             .persistent()
@@ -531,10 +1261,11 @@ impl TokenContract {
             .set(&DataKey::Balance(from.clone()), &from_balance);
         env.storage()
             .persistent()
-            .set(&DataKey::Balance(to.clone()), &to_balance);
+            .set(&DataKey::Balance(to), &to_balance);
     }
 
     fn burn_internal(env: &Env, from: Address, amount: i128) {
+        Self::migrate_account(env, &from);
         let balance: i128 = env
             .storage()
             .persistent()
@@ -546,6 +1277,8 @@ impl TokenContract {
         env.storage()
             .persistent()
             .set(&DataKey::Balance(from.clone()), &new_balance);
+        Self::add_supply(env, -amount);
+        events::Burn { from, amount }.publish(env);
     }
 
     // withdraw the amount specified unless full_withdrawal is true in which case withdraw remaining balance
@@ -555,13 +1288,18 @@ impl TokenContract {
         amount: i128,
         full_withdrawal: bool,
     ) -> Result<(), Error> {
-        let position = Self::get_deposit(env, to.clone())
-            .unwrap_or_else(|| panic_with_error!(env, Error::StakeDoesntExist));
-        let rewards = Self::calculate_rewards(env, &position);
-        if rewards > 0 {
-            return Err(Error::ClaimRewardsFirst);
+        // Withdrawing ahead of a pending liquidation would dodge its share of the loss, but
+        // liquidations left pending past the window must not lock stakers in
+        let frozen_since: Option<u64> = env.storage().instance().get(&FROZEN_SINCE_KEY);
+        if frozen_since.is_some_and(|since| {
+            env.ledger().timestamp() < since.saturating_add(LIQUIDATION_WINDOW)
+        }) {
+            return Err(Error::LiquidationsPending);
         }
-        let xasset_owed = Self::calculate_current_deposit(env, &position);
+        let mut position = Self::get_deposit(env, to.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::StakeDoesntExist));
+        let rewards = Self::settle_rewards(env, &to, &mut position)?;
+        let xasset_owed = position.xasset_deposit;
         let amount_to_withdraw = if full_withdrawal { xasset_owed } else { amount };
         if xasset_owed < amount_to_withdraw {
             return Err(Error::InsufficientStake);
@@ -569,15 +1307,18 @@ impl TokenContract {
         if xasset_owed == amount_to_withdraw {
             //close the position
 
-            // Return 2 XLM fee upon closing the SP account
-            let _ = Self::native(env)
-                .try_transfer(
-                    &env.current_contract_address(),
-                    &to,
-                    &Self::get_unstake_return(env),
-                )
-                .map_err(|_| Error::XLMTransferFailed)?;
-            Self::subtract_fees_collected(env, Self::get_unstake_return(env));
+            // Return part of the stake fee upon closing the SP account
+            if position.unstake_return > 0 {
+                let _ = Self::native(env)
+                    .try_transfer(
+                        &env.current_contract_address(),
+                        &to,
+                        &position.unstake_return,
+                    )
+                    .map_err(|_| Error::XLMTransferFailed)?;
+                Self::subtract_fees_collected(env, position.unstake_return);
+                Self::add_unstake_returns_owed(env, -position.unstake_return);
+            }
 
             // transfer xasset to address from pool
             Self::transfer_internal(
@@ -593,10 +1334,12 @@ impl TokenContract {
                     xasset_deposit: 0,
                     product_constant: Self::get_product_constant(env),
                     compounded_constant: Self::get_compounded_constant(env),
+                    interest_constant: Self::get_interest_constant(env),
                     ledger: env.ledger().sequence(),
                     timestamp: env.ledger().timestamp(),
                     epoch: Self::get_epoch(env),
-                    rewards_claimed: 0,
+                    scale: TokenStorage::get_state(env).scale,
+                    rewards_claimed: rewards,
                 },
             );
 
@@ -605,12 +1348,7 @@ impl TokenContract {
             return Ok(());
         }
 
-        let mut position = Self::get_deposit(env, to.clone()).unwrap_or_default();
-
         position.xasset_deposit = xasset_owed - amount_to_withdraw;
-
-        position.compounded_constant = Self::get_compounded_constant(env);
-        position.product_constant = Self::get_product_constant(env);
         // transfer xasset from pool to address
         Self::transfer_internal(
             env,
@@ -618,72 +1356,611 @@ impl TokenContract {
             to.clone(),
             amount_to_withdraw,
         );
-        Self::set_deposit(env, to, position, 0);
+        Self::set_deposit(env, to, position, rewards);
         Self::add_total_xasset(env, -amount_to_withdraw);
         Ok(())
     }
 
-    fn calculate_current_deposit(env: &Env, position: &StakerPosition) -> i128 {
-        if position.epoch == Self::get_epoch(env) {
-            let value =
-                (DEFAULT_PRECISION * position.xasset_deposit * Self::get_product_constant(env))
-                    / position.product_constant;
-            bankers_round(value, DEFAULT_PRECISION)
-        } else {
-            0
-        }
+    /// Pay a staker's rewards to its rewards recipient
+    fn claim_rewards_internal(env: &Env, to: Address) -> Result<i128, Error> {
+        let mut position = Self::get_deposit(env, to.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::StakeDoesntExist));
+
+        let xlm_reward = Self::calculate_rewards(env, &position);
+        let interest_reward = Self::calculate_interest_rewards(env, &position);
+        let Some(total_reward) = xlm_reward.checked_add(interest_reward) else {
+            return Err(Error::ArithmeticError);
+        };
+
+        let _ = Self::native(env)
+            .try_transfer(
+                &env.current_contract_address(),
+                Self::rewards_recipient(env, to.clone()),
+                &total_reward,
+            )
+            .map_err(|_| Error::XLMTransferFailed)?;
+        Self::subtract_total_collateral(env, xlm_reward);
+        Self::subtract_interest_rewards(env, interest_reward);
+        let collateral_rewards = Self::calculate_collateral_rewards(env, &position);
+        Self::pay_collateral_rewards(
+            env,
+            &Self::rewards_recipient(env, to.clone()),
+            &collateral_rewards,
+        )?;
+        position.xasset_deposit = Self::get_staker_deposit_amount(env, to.clone())?;
+        Self::snapshot_constants(env, &mut position);
+        Self::set_deposit(env, to, position, total_reward);
+        Ok(total_reward)
+    }
+
+    /// Add collateral to `lender`'s CDP from `payer`'s balance
+    fn add_collateral_from(
+        env: &Env,
+        lender: Address,
+        payer: &Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        assert_positive(env, amount);
+        let mut cdp: CDPInternal = TokenStorage::get_cdp(env, lender.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
+
+        if matches!(cdp.status, CDPStatus::Closed) || matches!(cdp.status, CDPStatus::Frozen) {
+            return Err(Error::CDPNotOpenOrInsolvent);
+        }
+
+        // Calculate the new deposit amount and check for overflow before we transfer
+        let Some(new_deposit) = cdp.xlm_deposited.checked_add(amount) else {
+            return Err(Error::ArithmeticError);
+        };
+
+        // Transfer XLM from payer to contract
+        let _ = Self::native(env)
+            .try_transfer(payer, env.current_contract_address(), &amount)
+            .map_err(|_| Error::XLMTransferFailed)?;
+
+        cdp.xlm_deposited = new_deposit;
+        TokenStorage::set_cdp(env, lender, cdp);
+        Ok(())
+    }
+
+    /// Repay `lender`'s CDP debt and accrued interest from `payer`'s balances
+    fn repay_debt_from(
+        env: &Env,
+        lender: Address,
+        payer: &Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        assert_positive(env, amount);
+        let mut cdp = TokenStorage::get_cdp(env, lender.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
+
+        if matches!(cdp.status, CDPStatus::Closed) || matches!(cdp.status, CDPStatus::Frozen) {
+            return Err(Error::CDPNotOpenOrInsolventForRepay);
+        }
+
+        // Pay off any interest first
+        Self::pay_accrued_interest(env, &mut cdp, payer, 0, true)?;
+
+        // Now continue with debt repayment
+        if cdp.asset_lent < amount {
+            return Err(Error::RepaymentExceedsDebt);
+        }
+
+        // Check to ensure enough xasset is available
+        if Self::balance(env.clone(), payer.clone()) < amount {
+            return Err(Error::InsufficientBalance);
+        }
+
+        // Calculate the new asset lent amount and check for overflow before we burn
+        let Some(asset_lent) = cdp.asset_lent.checked_sub(amount) else {
+            return Err(Error::ArithmeticError);
+        };
+
+        // Burn the xasset
+        Self::burn_internal(env, payer.clone(), amount);
+
+        cdp.asset_lent = asset_lent;
+
+        if cdp.asset_lent == 0 && cdp.xlm_deposited == 0 && cdp.collateral.is_empty() {
+            Self::close_cdp(env, lender)?;
+        } else {
+            TokenStorage::set_cdp(env, lender, cdp);
+        }
+        Ok(())
+    }
+
+    /// Settle a position's pending rewards before its deposit changes, compounding them if it
+    /// opted in and paying them out otherwise. Fails if compounding does. Leaves the position's
+    /// deposit current and its constants snapshotted, and returns the rewards settled, in XLM.
+    fn settle_rewards(
+        env: &Env,
+        staker: &Address,
+        position: &mut StakerPosition,
+    ) -> Result<i128, Error> {
+        let xlm_reward = Self::calculate_rewards(env, position);
+        let interest_reward = Self::calculate_interest_rewards(env, position);
+        let Some(total_reward) = xlm_reward.checked_add(interest_reward) else {
+            return Err(Error::ArithmeticError);
+        };
+        let mut compounded = 0;
+        if total_reward > 0 {
+            match position.compound_collat_ratio {
+                Some(ratio) => {
+                    compounded = Self::compound_into_cdp(env, staker, total_reward, ratio)?;
+                }
+                None => {
+                    let _ = Self::native(env)
+                        .try_transfer(
+                            &env.current_contract_address(),
+                            Self::rewards_recipient(env, staker.clone()),
+                            &total_reward,
+                        )
+                        .map_err(|_| Error::XLMTransferFailed)?;
+                }
+            }
+            Self::subtract_total_collateral(env, xlm_reward);
+            Self::subtract_interest_rewards(env, interest_reward);
+        }
+        let collateral_rewards = Self::calculate_collateral_rewards(env, position);
+        Self::pay_collateral_rewards(
+            env,
+            &Self::rewards_recipient(env, staker.clone()),
+            &collateral_rewards,
+        )?;
+        position.xasset_deposit = Self::calculate_current_deposit(env, position) + compounded;
+        Self::snapshot_constants(env, position);
+        Ok(total_reward)
+    }
+
+    /// Open `lender`'s CDP with `xlm` and `collateral` deposited, minting `asset_lent` of xAsset
+    fn open_cdp_with(
+        env: &Env,
+        lender: Address,
+        xlm: i128,
+        collateral: Map<Address, i128>,
+        asset_lent: i128,
+    ) -> Result<(), Error> {
+        assert_positive(env, asset_lent);
+        lender.require_auth();
+        Self::require_authorized(env, &lender)?;
+
+        // 1. check if lender already has a CDP
+        if env
+            .storage()
+            .persistent()
+            .has(&DataKey::CDP(lender.clone()))
+        {
+            return Err(Error::CDPAlreadyExists);
+        }
+
+        // 2. check that `lastprice` gives collateralization ratio over `min_collat_ratio`,
+        // counting the borrowing fee as debt, and that the debt fits its collateral's ceilings
+        let fee = Self::calculate_borrow_fee(env, asset_lent);
+        let Some(debt) = asset_lent.checked_add(fee) else {
+            return Err(Error::ArithmeticError);
+        };
+        let mut cdp = CDPInternal::new(
+            env,
+            xlm,
+            debt,
+            env.ledger().timestamp(),
+            Self::accrue_interest_index(env),
+        );
+        cdp.collateral = collateral;
+        let CDPContract {
+            collateralization_ratio,
+            ..
+        } = Self::decorate_now(env, cdp.clone(), lender.clone())?;
+        if collateralization_ratio < Self::minimum_collateralization_ratio(env) {
+            return Err(Error::InsufficientCollateralization);
+        }
+        Self::check_debt_ceilings(env, None, &cdp)?;
+
+        // 3. transfer attached collateral to this contract
+        if xlm > 0 {
+            let _ = Self::native(env)
+                .try_transfer(&lender, env.current_contract_address(), &xlm)
+                .map_err(|_| Error::XLMTransferFailed)?;
+        }
+        for (token, amount) in cdp.collateral.iter() {
+            Self::transfer_collateral(
+                env,
+                &token,
+                &lender,
+                &env.current_contract_address(),
+                amount,
+            )?;
+        }
+
+        // 4. mint `asset_lent` of this token to `address`, and the borrowing fee to its recipient
+        Self::mint_internal(env, lender.clone(), asset_lent);
+        Self::distribute_borrow_fee(env, fee);
+
+        // 5. create CDP
+        TokenStorage::set_cdp(env, lender, cdp);
+        Ok(())
+    }
+
+    /// Add `xlm` of rewards to `staker`'s CDP as collateral, opening one if needed, and mint
+    /// xAsset against it at `collat_ratio` into the stability pool, returning the amount minted.
+    /// Changes nothing unless it succeeds.
+    fn compound_into_cdp(
+        env: &Env,
+        staker: &Address,
+        xlm: i128,
+        collat_ratio: u32,
+    ) -> Result<i128, Error> {
+        Self::require_authorized(env, staker)?;
+        let minted =
+            Self::convert_xlm_to_xasset(env, xlm)? * BASIS_POINTS / i128::from(collat_ratio);
+        if minted <= 0 {
+            return Err(Error::ValueNotPositive);
+        }
+        let fee = Self::calculate_borrow_fee(env, minted);
+        let cdp = TokenStorage::get_cdp(env, staker.clone()).unwrap_or_else(|| {
+            CDPInternal::new(
+                env,
+                0,
+                0,
+                env.ledger().timestamp(),
+                Self::accrue_interest_index(env),
+            )
+        });
+        if !cdp.accrues_interest() {
+            return Err(Error::CDPNotOpenOrInsolvent);
+        }
+        let (Some(xlm_deposited), Some(asset_lent)) = (
+            cdp.xlm_deposited.checked_add(xlm),
+            cdp.asset_lent
+                .checked_add(minted)
+                .and_then(|debt| debt.checked_add(fee)),
+        ) else {
+            return Err(Error::ArithmeticError);
+        };
+        let new_cdp = CDPInternal {
+            xlm_deposited,
+            asset_lent,
+            ..cdp.clone()
+        };
+        Self::check_debt_ceilings(env, Some(&cdp), &new_cdp)?;
+        let new_cdp = Self::decorate_now(env, new_cdp, staker.clone())?;
+        if new_cdp.collateralization_ratio < Self::minimum_collateralization_ratio(env) {
+            return Err(Error::InsufficientCollateralization);
+        }
+
+        Self::mint_internal(env, env.current_contract_address(), minted);
+        Self::add_total_xasset(env, minted);
+        Self::distribute_borrow_fee(env, fee);
+        Self::set_cdp_from_decorated(env, staker.clone(), new_cdp);
+        Ok(minted)
+    }
+
+    fn calculate_current_deposit(env: &Env, position: &StakerPosition) -> i128 {
+        let state = TokenStorage::get_state(env);
+        if position.epoch != state.epoch {
+            return 0;
+        }
+        // A deposit shrinks by the scale factor per scale change, so after two it has vanished
+        match state.scale - position.scale {
+            0 => bankers_div(
+                position.xasset_deposit * state.product_constant,
+                position.product_constant,
+            ),
+            1 => bankers_div(
+                position.xasset_deposit * state.product_constant,
+                position.product_constant * SCALE_FACTOR,
+            ),
+            _ => 0,
+        }
+    }
+
+    /// Gain on a position from a reward sum that has grown by `sum_delta` since its snapshot
+    fn calculate_gain(position: &StakerPosition, sum_delta: i128) -> i128 {
+        bankers_div(
+            position.xasset_deposit * sum_delta,
+            position.product_constant,
+        )
+    }
+
+    fn calculate_rewards(env: &Env, position: &StakerPosition) -> i128 {
+        let (sum, _) = Self::get_constants_at(env, position.epoch, position.scale);
+        let (next_sum, _) = Self::get_constants_at(env, position.epoch, position.scale + 1);
+        Self::calculate_gain(
+            position,
+            sum - position.compounded_constant + next_sum / SCALE_FACTOR,
+        )
+    }
+
+    fn calculate_interest_rewards(env: &Env, position: &StakerPosition) -> i128 {
+        let (_, sum) = Self::get_constants_at(env, position.epoch, position.scale);
+        let (_, next_sum) = Self::get_constants_at(env, position.epoch, position.scale + 1);
+        Self::calculate_gain(
+            position,
+            sum - position.interest_constant + next_sum / SCALE_FACTOR,
+        )
+    }
+
+    /// Compounded and interest constants as of the end of `(epoch, scale)`, or as of now if that
+    /// is the current one. Both are 0 for a scale not reached yet.
+    fn get_constants_at(env: &Env, epoch: u64, scale: u64) -> (i128, i128) {
+        let state = TokenStorage::get_state(env);
+        if epoch == state.epoch && scale == state.scale {
+            return (state.compounded_constant, state.interest_constant);
+        }
+        (
+            Self::get_compound_record(env, epoch, scale).unwrap_or(0),
+            Self::get_interest_constant_record(env, epoch, scale),
+        )
+    }
+
+    /// Snapshot the stability pool's current constants into a position
+    fn snapshot_constants(env: &Env, position: &mut StakerPosition) {
+        let state = TokenStorage::get_state(env);
+        position.epoch = state.epoch;
+        position.scale = state.scale;
+        position.product_constant = state.product_constant;
+        position.compounded_constant = state.compounded_constant;
+        position.interest_constant = state.interest_constant;
+        position.collateral_constants = Self::current_collateral_constants(env);
+    }
+
+    fn update_constants(env: &Env, xasset_debited: i128, xlm_earned: i128) {
+        // Check if total_xasset is zero prior to calculation
+        let total_xasset = Self::get_total_xasset(env);
+        let product_constant = Self::get_product_constant(env);
+        if total_xasset == 0 {
+            Self::increment_epoch(env);
+            return;
+        }
+
+        // Proceed with updates if total_xasset is not zero
+        let new_compounded_constant =
+            Self::get_compounded_constant(env) + (xlm_earned * product_constant) / total_xasset;
+        Self::set_compounded_constant(env, new_compounded_constant);
+        if total_xasset == xasset_debited {
+            Self::increment_epoch(env);
+            return;
+        }
+
+        // Once the product constant would drop below the scale factor, scale it up instead so
+        // it keeps its precision
+        let remaining_xasset = total_xasset - xasset_debited;
+        let new_product_constant = product_constant * remaining_xasset / total_xasset;
+        if new_product_constant >= SCALE_FACTOR {
+            Self::set_product_constant(env, new_product_constant);
+            return;
+        }
+        let scaled_product_constant =
+            product_constant * remaining_xasset * SCALE_FACTOR / total_xasset;
+        if scaled_product_constant == 0 {
+            // What is left is too small to track; treat it as wiped out
+            Self::increment_epoch(env);
+        } else {
+            Self::increment_scale(env);
+            Self::set_product_constant(env, scaled_product_constant);
+        }
+    }
+
+    /// Distribute `amount` of a liquidated collateral type to stakers, like `update_constants`
+    /// does XLM. Call before the liquidated debt leaves the pool.
+    fn add_collateral_reward(env: &Env, token: &Address, amount: i128) {
+        let total_xasset = Self::get_total_xasset(env);
+        if total_xasset == 0 {
+            return;
+        }
+        let constant = Self::get_collateral_constant(env, token)
+            + amount * Self::get_product_constant(env) / total_xasset;
+        Self::set_collateral_constant(env, token, constant);
+        let mut totals = Self::collateral_totals(env, token);
+        let Some(pool_rewards) = totals.pool_rewards.checked_add(amount) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        totals.pool_rewards = pool_rewards;
+        Self::set_collateral_totals(env, token, &totals);
+    }
+
+    /// Pending rewards of a position in each collateral type, computed like `calculate_rewards`
+    fn calculate_collateral_rewards(env: &Env, position: &StakerPosition) -> Map<Address, i128> {
+        let mut rewards = Map::new(env);
+        for token in Self::collateral_types(env).keys() {
+            let sum = Self::get_collateral_constant_at(env, &token, position.epoch, position.scale);
+            let next_sum =
+                Self::get_collateral_constant_at(env, &token, position.epoch, position.scale + 1);
+            let snapshot = position
+                .collateral_constants
+                .get(token.clone())
+                .unwrap_or(0);
+            let reward = Self::calculate_gain(position, sum - snapshot + next_sum / SCALE_FACTOR);
+            if reward > 0 {
+                rewards.set(token, reward);
+            }
+        }
+        rewards
+    }
+
+    /// Pay out rewards in collateral types other than XLM
+    fn pay_collateral_rewards(
+        env: &Env,
+        to: &Address,
+        rewards: &Map<Address, i128>,
+    ) -> Result<(), Error> {
+        for (token, amount) in rewards.iter() {
+            Self::transfer_collateral(env, &token, &env.current_contract_address(), to, amount)?;
+            let mut totals = Self::collateral_totals(env, &token);
+            let Some(pool_rewards) = totals.pool_rewards.checked_sub(amount) else {
+                return Err(Error::ArithmeticError);
+            };
+            totals.pool_rewards = pool_rewards;
+            Self::set_collateral_totals(env, &token, &totals);
+        }
+        Ok(())
+    }
+
+    /// Current reward constants of each collateral type
+    fn current_collateral_constants(env: &Env) -> Map<Address, i128> {
+        let mut constants = Map::new(env);
+        for token in Self::collateral_types(env).keys() {
+            let constant = Self::get_collateral_constant(env, &token);
+            constants.set(token, constant);
+        }
+        constants
+    }
+
+    /// Reward constant of a collateral type as of the end of `(epoch, scale)`, or as of now if
+    /// that is the current one
+    fn get_collateral_constant_at(env: &Env, token: &Address, epoch: u64, scale: u64) -> i128 {
+        let state = TokenStorage::get_state(env);
+        if epoch == state.epoch && scale == state.scale {
+            return Self::get_collateral_constant(env, token);
+        }
+        env.storage()
+            .persistent()
+            .get(&DataKey::CollateralRecord(token.clone(), epoch, scale))
+            .unwrap_or(0)
+    }
+
+    fn get_collateral_constant(env: &Env, token: &Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::CollateralConstant(token.clone()))
+            .unwrap_or(0)
+    }
+
+    fn set_collateral_constant(env: &Env, token: &Address, value: i128) {
+        let key = DataKey::CollateralConstant(token.clone());
+        env.storage().persistent().set(&key, &value);
+        let ttl = env.storage().max_ttl();
+        env.storage().persistent().extend_ttl(&key, ttl, ttl);
+    }
+
+    /// Mint `amount` of xAsset into the stability pool, growing every staker's deposit pro rata
+    fn add_xasset_to_pool(env: &Env, amount: i128) {
+        let total_xasset = Self::get_total_xasset(env);
+        let product_constant = Self::get_product_constant(env);
+        let Some(new_total) = total_xasset.checked_add(amount) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        Self::set_product_constant(env, product_constant * new_total / total_xasset);
+        Self::mint_internal(env, env.current_contract_address(), amount);
+        Self::add_total_xasset(env, amount);
+    }
+
+    fn increment_epoch(env: &Env) {
+        Self::record_constants(env);
+        let mut state = TokenStorage::get_state(env);
+        state.epoch += 1;
+        state.scale = 0;
+        // reset constants
+        state.product_constant = PRODUCT_CONSTANT;
+        state.compounded_constant = 0;
+        state.interest_constant = 0;
+        TokenStorage::set_state(env, &state);
+    }
+
+    fn increment_scale(env: &Env) {
+        Self::record_constants(env);
+        let mut state = TokenStorage::get_state(env);
+        state.scale += 1;
+        state.compounded_constant = 0;
+        state.interest_constant = 0;
+        TokenStorage::set_state(env, &state);
     }
 
-    fn calculate_rewards(env: &Env, position: &StakerPosition) -> i128 {
-        if position.epoch == Self::get_epoch(env) {
-            let value = (DEFAULT_PRECISION
-                * position.xasset_deposit
-                * (Self::get_compounded_constant(env) - position.compounded_constant))
-                / position.product_constant;
-            bankers_round(value, DEFAULT_PRECISION)
-        } else {
-            let value = (DEFAULT_PRECISION
-                * position.xasset_deposit
-                * (Self::get_compounded_epoch(env, position.epoch)
-                    .expect("The historical compounded constant should always be recorded")
-                    - position.compounded_constant))
-                / position.product_constant;
-            bankers_round(value, DEFAULT_PRECISION)
+    /// Record the compounded and interest constants reached in the current epoch and scale, and
+    /// those of collateral types, which are reset for the next
+    fn record_constants(env: &Env) {
+        let state = TokenStorage::get_state(env);
+        Self::set_compound_record(env, state.epoch, state.scale, &state.compounded_constant);
+        Self::set_interest_constant_record(env, state.epoch, state.scale, &state.interest_constant);
+        for token in Self::collateral_types(env).keys() {
+            let key = DataKey::CollateralRecord(token.clone(), state.epoch, state.scale);
+            env.storage()
+                .persistent()
+                .set(&key, &Self::get_collateral_constant(env, &token));
+            let ttl = env.storage().max_ttl();
+            env.storage().persistent().extend_ttl(&key, ttl, ttl);
+            Self::set_collateral_constant(env, &token, 0);
         }
     }
 
-    fn update_constants(env: &Env, xasset_debited: i128, xlm_earned: i128) {
-        // Check if total_xasset is zero prior to calculation
-        let total_xasset = Self::get_total_xasset(env);
-        let product_constant = Self::get_product_constant(env);
-        if total_xasset == 0 {
-            Self::increment_epoch(env);
+    /// Convert `account` from the layout stored before storage was versioned, once, while
+    /// migrating. Its balance counts towards the supply as it stood before it first changed.
+    fn migrate_account(env: &Env, account: &Address) {
+        if !env.storage().instance().has(&MIGRATING_KEY) {
             return;
         }
+        let migrated = DataKey::Migrated(account.clone());
+        if env.storage().persistent().has(&migrated) {
+            return;
+        }
+        env.storage().persistent().set(&migrated, &true);
+        let ttl = env.storage().max_ttl();
+        env.storage().persistent().extend_ttl(&migrated, ttl, ttl);
+        Self::add_supply(env, Self::balance(env.clone(), account.clone()));
 
-        // Proceed with updates if total_xasset is not zero
-        let new_product_constant =
-            (product_constant * (total_xasset - xasset_debited)) / total_xasset;
-        let new_compounded_constant =
-            Self::get_compounded_constant(env) + (xlm_earned * product_constant) / total_xasset;
-
-        Self::set_product_constant(env, new_product_constant);
-        Self::set_compounded_constant(env, new_compounded_constant);
-        if total_xasset == xasset_debited {
-            Self::increment_epoch(env);
+        let now = env.ledger().timestamp();
+        let key = DataKey::CDP(account.clone());
+        if let Some(legacy) = env
+            .storage()
+            .persistent()
+            .get::<_, Map<Symbol, Val>>(&key)
+            .filter(|cdp| cdp.contains_key(Symbol::new(env, "accrued_interest")))
+        {
+            let legacy: LegacyCDP = legacy.to_val().into_val(env);
+            let mut interest = legacy.accrued_interest;
+            if matches!(legacy.status, CDPStatus::Open | CDPStatus::Insolvent)
+                && legacy.last_interest_time > 0
+            {
+                interest.amount += Self::simple_interest(
+                    legacy.asset_lent,
+                    Self::get_annual_interest_rate(env),
+                    now.saturating_sub(legacy.last_interest_time),
+                );
+            }
+            // Interest paid used to be counted in XLM, and is kept apart as it was
+            if interest.paid > 0 {
+                let paid_key = DataKey::LegacyInterestPaid(account.clone());
+                env.storage().persistent().set(&paid_key, &interest.paid);
+                let ttl = env.storage().max_ttl();
+                env.storage().persistent().extend_ttl(&paid_key, ttl, ttl);
+            }
+            interest.paid = 0;
+            env.storage().persistent().remove(&key);
+            let mut cdp = CDPInternal::new(env, legacy.xlm_deposited, legacy.asset_lent, now, 0);
+            cdp.status = legacy.status;
+            cdp.accrued_interest = interest;
+            TokenStorage::set_cdp(env, account.clone(), cdp);
         }
-    }
 
-    fn increment_epoch(env: &Env) {
-        let epoch = Self::get_epoch(env);
-        Self::set_compound_record(env, epoch, &Self::get_compounded_constant(env));
-        Self::set_epoch(env, epoch + 1);
-        // reset constants
-        Self::set_product_constant(env, PRODUCT_CONSTANT);
-        Self::set_compounded_constant(env, 0);
+        let key = DataKey::StakerPosition(account.clone());
+        if let Some(mut position) = env
+            .storage()
+            .persistent()
+            .get::<_, Map<Symbol, Val>>(&key)
+            .filter(|position| !position.contains_key(symbol_short!("scale")))
+        {
+            let unstake_return = TokenStorage::get_state(env).pool_fees.unstake_return;
+            Self::add_unstake_returns_owed(env, unstake_return);
+            position.set(symbol_short!("scale"), 0_u64.into_val(env));
+            position.set(Symbol::new(env, "interest_constant"), 0_i128.into_val(env));
+            position.set(
+                Symbol::new(env, "unstake_return"),
+                unstake_return.into_val(env),
+            );
+            position.set(
+                Symbol::new(env, "compound_collat_ratio"),
+                Option::<u32>::None.into_val(env),
+            );
+            position.set(
+                Symbol::new(env, "collateral_constants"),
+                Map::<Address, i128>::new(env).into_val(env),
+            );
+            env.storage().persistent().set(&key, &position);
+        }
     }
 
     fn get_deposit(env: &Env, address: Address) -> Option<StakerPosition> {
+        Self::migrate_account(env, &address);
         env.storage()
             .persistent()
             .get(&DataKey::StakerPosition(address))
@@ -697,8 +1974,10 @@ impl TokenContract {
                 xasset_deposit: position.xasset_deposit,
                 product_constant: position.product_constant,
                 compounded_constant: position.compounded_constant,
+                interest_constant: position.interest_constant,
                 rewards_claimed: _rewards,
                 epoch: position.epoch,
+                scale: position.scale,
                 ledger: env.ledger().sequence(),
                 timestamp: env.ledger().timestamp(),
             },
@@ -712,20 +1991,39 @@ impl TokenContract {
             .extend_ttl(&DataKey::StakerPosition(address), ttl, ttl);
     }
 
-    fn get_compound_record(env: &Env, epoch: u64) -> Option<i128> {
+    fn get_compound_record(env: &Env, epoch: u64, scale: u64) -> Option<i128> {
         env.storage()
             .persistent()
-            .get(&DataKey::CompoundRecord(epoch))
+            .get(&DataKey::CompoundRecord(epoch, scale))
     }
 
-    fn set_compound_record(env: &Env, epoch: u64, amount: &i128) {
+    fn set_compound_record(env: &Env, epoch: u64, scale: u64, amount: &i128) {
         env.storage()
             .persistent()
-            .set(&DataKey::CompoundRecord(epoch), amount);
+            .set(&DataKey::CompoundRecord(epoch, scale), amount);
         let ttl = env.storage().max_ttl();
         env.storage()
             .persistent()
-            .extend_ttl(&DataKey::CompoundRecord(epoch), ttl, ttl);
+            .extend_ttl(&DataKey::CompoundRecord(epoch, scale), ttl, ttl);
+    }
+
+    fn get_interest_constant_record(env: &Env, epoch: u64, scale: u64) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::InterestConstantRecord(epoch, scale))
+            .unwrap_or(0)
+    }
+
+    fn set_interest_constant_record(env: &Env, epoch: u64, scale: u64, amount: &i128) {
+        env.storage()
+            .persistent()
+            .set(&DataKey::InterestConstantRecord(epoch, scale), amount);
+        let ttl = env.storage().max_ttl();
+        env.storage().persistent().extend_ttl(
+            &DataKey::InterestConstantRecord(epoch, scale),
+            ttl,
+            ttl,
+        );
     }
 
     fn get_interest_record(env: &Env, epoch: u64) -> i128 {
@@ -753,6 +2051,7 @@ impl TokenContract {
         };
         state.total_xasset = new_total;
         TokenStorage::set_state(env, &state);
+        Self::update_interest_rate(env);
     }
 
     fn subtract_total_xasset(env: &Env, amount: i128) {
@@ -762,6 +2061,16 @@ impl TokenContract {
         };
         state.total_xasset = new_total;
         TokenStorage::set_state(env, &state);
+        Self::update_interest_rate(env);
+    }
+
+    fn subtract_interest_rewards(env: &Env, amount: i128) {
+        let mut state = TokenStorage::get_state(env);
+        let Some(new_total) = state.interest_rewards.checked_sub(amount) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        state.interest_rewards = new_total;
+        TokenStorage::set_state(env, &state);
     }
 
     fn add_total_collateral(env: &Env, amount: i128) {
@@ -802,24 +2111,12 @@ impl TokenContract {
         TokenStorage::set_state(env, &state);
     }
 
-    fn get_epoch(env: &Env) -> u64 {
-        TokenStorage::get_state(env).epoch
-    }
-
-    fn set_epoch(env: &Env, value: u64) {
-        let mut state = TokenStorage::get_state(env);
-        state.epoch = value;
-        TokenStorage::set_state(env, &state);
-    }
-
-    fn get_compounded_epoch(env: &Env, epoch: u64) -> Option<i128> {
-        env.storage()
-            .persistent()
-            .get(&DataKey::CompoundRecord(epoch))
+    fn get_interest_constant(env: &Env) -> i128 {
+        TokenStorage::get_state(env).interest_constant
     }
 
-    fn get_fees_collected(env: &Env) -> i128 {
-        TokenStorage::get_state(env).fees_collected
+    fn get_epoch(env: &Env) -> u64 {
+        TokenStorage::get_state(env).epoch
     }
 
     fn add_fees_collected(env: &Env, amount: i128) {
@@ -840,16 +2137,70 @@ impl TokenContract {
         TokenStorage::set_state(env, &state);
     }
 
-    // fn get_stake_fee(env: &Env) -> i128 {
-    //     TokenStorage::get_state(env).stake_fee
-    // }
+    fn add_xasset_fees_collected(env: &Env, amount: i128) {
+        let mut state = TokenStorage::get_state(env);
+        let Some(new_total) = state.xasset_fees_collected.checked_add(amount) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        state.xasset_fees_collected = new_total;
+        TokenStorage::set_state(env, &state);
+    }
+
+    fn add_unstake_returns_owed(env: &Env, amount: i128) {
+        let mut state = TokenStorage::get_state(env);
+        let Some(new_total) = state.unstake_returns_owed.checked_add(amount) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        state.unstake_returns_owed = new_total;
+        TokenStorage::set_state(env, &state);
+    }
 
-    fn get_deposit_fee(env: &Env) -> i128 {
-        TokenStorage::get_state(env).deposit_fee
+    /// Collect a stability pool fee of `fee` XLM from `from`, in XLM or its value in xAsset
+    fn collect_pool_fee(
+        env: &Env,
+        from: &Address,
+        fee: i128,
+        fee_asset: FeeAsset,
+    ) -> Result<(), Error> {
+        if fee == 0 {
+            return Ok(());
+        }
+        match fee_asset {
+            FeeAsset::Xlm => {
+                let _ = Self::native(env)
+                    .try_transfer(from, env.current_contract_address(), &fee)
+                    .map_err(|_| Error::XLMTransferFailed)?;
+                Self::add_fees_collected(env, fee);
+            }
+            FeeAsset::XAsset => {
+                let fee = Self::convert_xlm_to_xasset(env, fee)?;
+                if Self::balance(env.clone(), from.clone()) < fee {
+                    return Err(Error::InsufficientBalance);
+                }
+                Self::transfer_internal(env, from.clone(), env.current_contract_address(), fee);
+                Self::add_xasset_fees_collected(env, fee);
+            }
+        }
+        Ok(())
     }
 
-    fn get_unstake_return(env: &Env) -> i128 {
-        TokenStorage::get_state(env).unstake_return
+    /// Collected fees and treasury interest that can be swept, in XLM. Neither may dip into XLM
+    /// backing CDP collateral, stability pool rewards, or unstake returns still owed to stakers.
+    fn sweepable(env: &Env) -> (i128, i128) {
+        let state = TokenStorage::get_state(env);
+        let unstake_returns_owed = state.unstake_returns_owed;
+        let backing = state.total_cdp_collateral
+            + state.total_collateral
+            + state.interest_rewards
+            + unstake_returns_owed;
+        let balance = Self::native(env).balance(&env.current_contract_address());
+        let surplus = cmp::max(0, balance - backing);
+        let interest = cmp::min(state.treasury_interest, surplus);
+        let fees = cmp::min(
+            cmp::max(0, state.fees_collected - unstake_returns_owed),
+            surplus - interest,
+        );
+        (fees, interest)
     }
 
     fn remove_deposit(env: &Env, address: Address) {
@@ -868,38 +2219,168 @@ impl TokenContract {
         TokenStorage::set_state(env, &state);
     }
 
-    fn get_updated_accrued_interest(
-        env: &Env,
-        cdp: &CDPInternal,
-    ) -> Result<(Interest, u64), Error> {
+    /// Interest accrued on a stored CDP up to now, and the current timestamp
+    fn get_updated_accrued_interest(env: &Env, cdp: &CDPInternal) -> (Interest, u64) {
+        let now = env.ledger().timestamp();
+        (Self::get_projected_interest(env, cdp, now), now)
+    }
+
+    /// Interest index at `timestamp`, compounding the stored index at the current rate
+    fn interest_index_at(env: &Env, timestamp: u64) -> i128 {
+        let state = TokenStorage::get_state(env);
+        let time_elapsed = timestamp.saturating_sub(state.interest_index_time);
+        // Nothing accrues at the index while no debt does
+        if time_elapsed == 0 || state.total_normalized_debt == 0 {
+            return state.interest_index;
+        }
+        state.interest_index * Self::compound_factor(state.interest_rate, time_elapsed)
+            / INTEREST_PRECISION
+    }
+
+    /// Bring the stored interest index up to now, and return it
+    fn accrue_interest_index(env: &Env) -> i128 {
         let now = env.ledger().timestamp();
-        let last_time = cdp.last_interest_time;
+        let index = Self::interest_index_at(env, now);
+        let mut state = TokenStorage::get_state(env);
+        if state.interest_index_time != now {
+            state.interest_index = index;
+            state.interest_index_time = now;
+            TokenStorage::set_state(env, &state);
+        }
+        index
+    }
 
-        // If this is a new CDP or first interest calculation
-        if last_time == 0 {
-            return Ok((Interest::default(), now));
+    /// Factor, scaled by `INTEREST_PRECISION`, by which debt grows at an annual `rate` in basis
+    /// points over `time_elapsed` seconds. Compounding is continuous, so accruing in steps
+    /// comes to the same as accruing at once.
+    fn compound_factor(rate: u32, time_elapsed: u64) -> i128 {
+        // e^x by its Taylor series, on x halved until the series converges quickly, then
+        // squared back
+        let mut x = INTEREST_PRECISION * i128::from(rate) * i128::from(time_elapsed)
+            / (BASIS_POINTS * i128::from(SECONDS_PER_YEAR));
+        let mut halvings = 0;
+        while x > INTEREST_PRECISION / 2 {
+            x /= 2;
+            halvings += 1;
+        }
+        let mut factor = INTEREST_PRECISION;
+        let mut term = INTEREST_PRECISION;
+        let mut n = 1;
+        while term > 0 {
+            term = term * x / (n * INTEREST_PRECISION);
+            factor += term;
+            n += 1;
         }
+        for _ in 0..halvings {
+            factor = factor * factor / INTEREST_PRECISION;
+        }
+        factor
+    }
 
-        // Do not accrue interest after it has been frozen
-        if matches!(cdp.status, CDPStatus::Closed) || matches!(cdp.status, CDPStatus::Frozen) {
-            return Ok((cdp.accrued_interest, now));
+    /// Interest on `debt` at an annual `rate` in basis points over `time_elapsed` seconds
+    fn simple_interest(debt: i128, rate: u32, time_elapsed: u64) -> i128 {
+        debt * i128::from(rate) * i128::from(time_elapsed)
+            / (BASIS_POINTS * i128::from(SECONDS_PER_YEAR))
+    }
+
+    /// Debt of the CDPs with borrower-chosen rates, by rate. Each rate's total compounds on its
+    /// own, as its CDPs do.
+    fn rate_debts(env: &Env) -> Map<u32, RateDebt> {
+        env.storage()
+            .instance()
+            .get(&RATE_DEBT_KEY)
+            .unwrap_or_else(|| Map::new(env))
+    }
+
+    /// Debt of the CDPs at `rate` as of `timestamp`
+    fn rate_debt_at(total: &RateDebt, rate: u32, timestamp: u64) -> i128 {
+        total.debt * Self::compound_factor(rate, timestamp.saturating_sub(total.time))
+            / INTEREST_PRECISION
+    }
+
+    /// Principal plus interest owed across all CDPs accruing interest, as of now
+    fn total_debt(env: &Env) -> i128 {
+        let now = env.ledger().timestamp();
+        let state = TokenStorage::get_state(env);
+        let mut debt = state.total_normalized_debt * Self::interest_index_at(env, now)
+            / NORMALIZED_DEBT_PRECISION;
+        for (rate, total) in Self::rate_debts(env).iter() {
+            debt += Self::rate_debt_at(&total, rate, now);
+        }
+        debt
+    }
+
+    /// Settle interest at the rate in force up to now, then re-derive the rate from the rate model.
+    /// If the model's inputs are unavailable, the previous rate stays in force.
+    fn update_interest_rate(env: &Env) {
+        Self::accrue_interest_index(env);
+        let mut state = TokenStorage::get_state(env);
+        if matches!(state.rate_model, RateModel::Fixed) {
+            return;
+        }
+        match Self::model_rate(env, &state.rate_model) {
+            Ok(rate) if rate != state.interest_rate => {
+                state.interest_rate = rate;
+                TokenStorage::set_state(env, &state);
+            }
+            _ => {}
+        }
+    }
+
+    /// Annual interest rate the given model gives as of now
+    fn model_rate(env: &Env, model: &RateModel) -> Result<u32, Error> {
+        match model {
+            RateModel::Fixed => Ok(Self::get_annual_interest_rate(env)),
+            RateModel::PegDeviation(curve, _) | RateModel::PoolCoverage(curve) => {
+                Ok(curve.rate(Self::rate_model_utilization(env, model)?))
+            }
         }
-        let interest = Self::get_projected_interest(env, cdp, last_time, now)?;
+    }
 
-        Ok((interest, now))
+    /// Utilization fed into the rate model's curve, in basis points
+    fn rate_model_utilization(env: &Env, model: &RateModel) -> Result<u32, Error> {
+        match model {
+            RateModel::Fixed => Ok(0),
+            RateModel::PegDeviation(_, feed) => {
+                let peg = Self::lastprice_asset(env)?.price;
+                let peg_decimals = Self::decimals_asset_feed(env)?;
+                let client = data_feed::Client::new(env, &feed.contract);
+                let Ok(Ok(Some(market))) =
+                    client.try_lastprice(&data_feed::Asset::Other(feed.asset.clone()))
+                else {
+                    return Err(Error::OraclePriceFetchFailed);
+                };
+                let Ok(Ok(market_decimals)) = client.try_decimals() else {
+                    return Err(Error::OracleDecimalsFetchFailed);
+                };
+                // Market price as a share of the peg, both scaled to the same decimals
+                let ratio = BASIS_POINTS * market.price * 10i128.pow(peg_decimals)
+                    / (peg * 10i128.pow(market_decimals));
+                Ok(cmp::max(0, BASIS_POINTS - ratio) as u32)
+            }
+            RateModel::PoolCoverage(_) => {
+                let state = TokenStorage::get_state(env);
+                let debt = Self::total_debt(env);
+                if debt <= 0 {
+                    return Ok(0);
+                }
+                let covered = cmp::min(BASIS_POINTS, BASIS_POINTS * state.total_xasset / debt);
+                Ok((BASIS_POINTS - covered) as u32)
+            }
+        }
     }
 
-    fn apply_interest_payment<F>(
+    /// Pay `amount_in_xasset` of `cdp`'s accrued interest, or all of it if 0, from `payer`'s
+    /// balance. In XLM, `payer` either transfers it or, if `approved`, has approved this contract
+    /// to pull it; only then are prices needed.
+    fn pay_accrued_interest(
         env: &Env,
-        lender: Address,
+        cdp: &mut CDPInternal,
+        payer: &Address,
         amount_in_xasset: i128,
-        pay_fn: F,
-    ) -> Result<CDPContract, Error>
-    where
-        F: FnOnce(&Address, &i128) -> Result<(), Error>,
-    {
-        let cdp = Self::cdp(env, lender.clone()).unwrap();
-        let mut interest = cdp.accrued_interest;
+        approved: bool,
+    ) -> Result<(), Error> {
+        let interest = &mut cdp.accrued_interest;
         // if called with 0, it means we want to pay off all currently accrued interest
         let amount_to_pay = if amount_in_xasset == 0 {
             interest.amount
@@ -910,53 +2391,54 @@ impl TokenContract {
             amount_in_xasset
         };
         if amount_to_pay == 0 {
-            return Ok(cdp);
+            return Ok(());
         }
-        let price = Self::lastprice_asset(env).unwrap();
-        let xlmprice = Self::lastprice_xlm(env).unwrap();
-        let xasset_decimals = Self::decimals_asset_feed(env)?;
-        let xlm_decimals = Self::decimals_xlm_feed(env)?;
-        let amount_in_xlm = Self::convert_xasset_to_xlm(env, amount_to_pay)?;
-        if Self::native(env).balance(&lender) < amount_in_xlm {
-            return Err(Error::InsufficientXLMForInterest);
+        let payment = TokenStorage::get_state(env).interest_payment;
+        match payment {
+            InterestPayment::Xlm => {
+                let amount_in_xlm = Self::convert_xasset_to_xlm(env, amount_to_pay)?;
+                if Self::native(env).balance(payer) < amount_in_xlm {
+                    return Err(Error::InsufficientXLMForInterest);
+                }
+                let contract = env.current_contract_address();
+                let transferred = if approved {
+                    Self::native(env).try_transfer_from(&contract, payer, &contract, &amount_in_xlm)
+                } else {
+                    Self::native(env).try_transfer(payer, &contract, &amount_in_xlm)
+                };
+                match transferred {
+                    Ok(Ok(())) => {}
+                    Ok(Err(_)) if approved => {
+                        return Err(Error::InsufficientApprovedXLMForInterestRepayment);
+                    }
+                    Ok(Err(_)) => return Err(Error::XLMTransferFailed),
+                    Err(_) => return Err(Error::XLMInvocationFailed),
+                }
+                // Only XLM is held by the protocol as collected interest; xAsset interest has
+                // already been burned or distributed to the stability pool
+                Self::distribute_interest(env, amount_in_xlm);
+            }
+            InterestPayment::Burn | InterestPayment::StabilityPool => {
+                if Self::balance(env.clone(), payer.clone()) < amount_to_pay {
+                    return Err(Error::InsufficientBalance);
+                }
+                Self::burn_internal(env, payer.clone(), amount_to_pay);
+                if payment == InterestPayment::StabilityPool && Self::get_total_xasset(env) > 0 {
+                    Self::add_xasset_to_pool(env, amount_to_pay);
+                }
+            }
         }
 
-        pay_fn(&lender, &amount_in_xlm)?;
-
         let Some(new_interest) = interest.amount.checked_sub(amount_to_pay) else {
             return Err(Error::ArithmeticError);
         };
         interest.amount = new_interest;
 
-        let Some(new_paid) = interest.paid.checked_add(amount_in_xlm) else {
+        let Some(new_paid) = interest.paid.checked_add(amount_to_pay) else {
             return Err(Error::ArithmeticError);
         };
         interest.paid = new_paid;
-
-        let decorated_cdp = Self::decorate(
-            env,
-            CDPInternal {
-                xlm_deposited: cdp.xlm_deposited,
-                asset_lent: cdp.asset_lent,
-                accrued_interest: interest,
-                status: cdp.status,
-                last_interest_time: cdp.last_interest_time,
-            },
-            lender.clone(),
-            xlmprice.price,
-            xlm_decimals,
-            price.price,
-            xasset_decimals,
-        );
-
-        Self::set_cdp_from_decorated(env, lender, decorated_cdp.clone());
-        TokenStorage::set_interest_collected(
-            env,
-            Self::get_total_interest_collected(env) + amount_in_xlm,
-        );
-        Self::increment_interest_for_current_epoch(env, &amount_in_xlm);
-
-        Ok(decorated_cdp)
+        Ok(())
     }
 
     fn convert_xasset_to_xlm(env: &Env, amount_in_xasset: i128) -> Result<i128, Error> {
@@ -974,41 +2456,123 @@ impl TokenContract {
         ))
     }
 
+    fn convert_xlm_to_xasset(env: &Env, amount_in_xlm: i128) -> Result<i128, Error> {
+        let price = Self::lastprice_asset(env)?;
+        let xlmprice = Self::lastprice_xlm(env)?;
+        let xasset_decimals = Self::decimals_asset_feed(env)?;
+        let xlm_decimals = Self::decimals_xlm_feed(env)?;
+        Ok(bankers_round(
+            (DEFAULT_PRECISION * amount_in_xlm * xlmprice.price)
+                / (price.price * 10i128.pow(xlm_decimals - xasset_decimals)),
+            DEFAULT_PRECISION,
+        ))
+    }
+
     fn increment_interest_for_current_epoch(env: &Env, amount: &i128) {
         let current_epoch = Self::get_epoch(env);
         let current_interest = Self::get_interest_record(env, current_epoch);
         Self::set_and_extend_interest_record(env, current_epoch, &(current_interest + amount));
     }
 
+    /// Record interest collected in XLM, and split it between stability pool stakers, by the
+    /// interest share, and the treasury. While the pool is empty, it all goes to the treasury.
+    fn distribute_interest(env: &Env, amount_in_xlm: i128) {
+        Self::increment_interest_for_current_epoch(env, &amount_in_xlm);
+        let mut state = TokenStorage::get_state(env);
+        let to_pool = if state.total_xasset == 0 {
+            0
+        } else {
+            amount_in_xlm * i128::from(state.interest_share) / BASIS_POINTS
+        };
+        let (Some(interest_collected), Some(interest_rewards), Some(treasury_interest)) = (
+            state.interest_collected.checked_add(amount_in_xlm),
+            state.interest_rewards.checked_add(to_pool),
+            state.treasury_interest.checked_add(amount_in_xlm - to_pool),
+        ) else {
+            panic_with_error!(env, Error::ArithmeticError);
+        };
+        state.interest_collected = interest_collected;
+        state.interest_rewards = interest_rewards;
+        state.treasury_interest = treasury_interest;
+        if to_pool > 0 {
+            state.interest_constant += to_pool * state.product_constant / state.total_xasset;
+        }
+        TokenStorage::set_state(env, &state);
+    }
+
     // Helper to calculate projected interest at a future timestamp
-    fn get_projected_interest(
-        env: &Env,
-        cdp: &CDPInternal,
-        from_time: u64,
-        to_time: u64,
-    ) -> Result<Interest, Error> {
-        if from_time == 0 {
-            return Ok(Interest::default());
+    fn get_projected_interest(env: &Env, cdp: &CDPInternal, to_time: u64) -> Interest {
+        // Do not accrue interest after it has been frozen
+        if !cdp.accrues_interest() {
+            return cdp.accrued_interest;
+        }
+        let debt = cdp.asset_lent + cdp.accrued_interest.amount;
+        let debt = match cdp.interest_rate {
+            None => debt * Self::interest_index_at(env, to_time) / cdp.interest_index,
+            // Borrower-chosen rates compound on their own since the CDP was last stored
+            Some(rate) => {
+                debt * Self::compound_factor(rate, to_time.saturating_sub(cdp.last_interest_time))
+                    / INTEREST_PRECISION
+            }
+        };
+        Interest {
+            amount: cmp::max(0, debt - cdp.asset_lent),
+            paid: cdp.accrued_interest.paid,
         }
+    }
 
-        let annual_rate = Self::get_annual_interest_rate(env) as i128;
-        let time_elapsed = to_time.saturating_sub(from_time);
-        if time_elapsed == 0 {
-            return Ok(cdp.accrued_interest);
+    /// Base rate after decaying it by the time elapsed since its last update.
+    /// Decay halves the rate once per half-life and is linear within a half-life.
+    fn decayed_base_rate(env: &Env) -> u32 {
+        let fee = TokenStorage::get_state(env).borrow_fee;
+        let elapsed = env.ledger().timestamp().saturating_sub(fee.base_rate_time);
+        let halvings = elapsed / fee.base_rate_half_life;
+        if halvings >= u64::from(u32::BITS) {
+            return 0;
         }
+        let rate = u64::from(fee.base_rate >> halvings);
+        let remainder = elapsed % fee.base_rate_half_life;
+        (rate - rate * remainder / (2 * fee.base_rate_half_life)) as u32
+    }
 
-        let interest_amount = bankers_round(
-            cdp.asset_lent * annual_rate * (time_elapsed as i128) * INTEREST_PRECISION
-                / (BASIS_POINTS * (SECONDS_PER_YEAR as i128)),
-            INTEREST_PRECISION,
-        );
-        Ok(Interest {
-            amount: cdp.accrued_interest.amount + interest_amount,
-            paid: cdp.accrued_interest.paid,
-        })
+    /// Current borrowing fee in basis points: floor plus decayed base rate, capped
+    fn borrow_fee_rate(env: &Env) -> u32 {
+        let fee = TokenStorage::get_state(env).borrow_fee;
+        cmp::min(
+            fee.cap,
+            fee.floor.saturating_add(Self::decayed_base_rate(env)),
+        )
+    }
+
+    /// Borrowing fee owed for minting `amount` of xAsset. The fee is waived if there is
+    /// no recipient and nobody is staked in the stability pool to receive it.
+    fn calculate_borrow_fee(env: &Env, amount: i128) -> i128 {
+        let state = TokenStorage::get_state(env);
+        if state.borrow_fee.recipient.is_none() && state.total_xasset == 0 {
+            return 0;
+        }
+        bankers_round(
+            DEFAULT_PRECISION * amount * i128::from(Self::borrow_fee_rate(env)) / BASIS_POINTS,
+            DEFAULT_PRECISION,
+        )
+    }
+
+    /// Mint a borrowing fee to the fee recipient, or to the stability pool if none is set
+    fn distribute_borrow_fee(env: &Env, fee: i128) {
+        if fee == 0 {
+            return;
+        }
+        match TokenStorage::get_state(env).borrow_fee.recipient {
+            Some(recipient) => Self::mint_internal(env, recipient, fee),
+            None => Self::add_xasset_to_pool(env, fee),
+        }
     }
 }
 
+// SEP-41 is implemented here rather than on stellar-fungible: every balance change passes through
+// the compliance check, the per-account migration and the supply count, and permits, the
+// stability pool and CDPs write balances and allowances directly, none of which its storage
+// accommodates without converting every `Balance` and `Txn` entry.
 #[contractimpl]
 impl TokenInterface for TokenContract {
     /// Return the allowance for `spender` to transfer from `from`.
@@ -1029,24 +2593,7 @@ impl TokenInterface for TokenContract {
     /// Set the allowance by `amount` for `spender` to transfer/burn from `from`
     fn approve(env: Env, from: Address, spender: Address, amount: i128, live_until_ledger: u32) {
         from.require_auth();
-        let current_ledger = env.ledger().sequence();
-        assert_positive(&env, amount);
-        assert_with_error!(
-            env,
-            live_until_ledger >= current_ledger,
-            Error::InvalidLedgerSequence
-        );
-        let max_ttl = env.storage().max_ttl();
-        env.storage().persistent().set(
-            &Txn(from.clone(), spender.clone()),
-            &Allowance {
-                amount,
-                live_until_ledger,
-            },
-        );
-        env.storage()
-            .persistent()
-            .extend_ttl(&Txn(from, spender), max_ttl, max_ttl);
+        Self::set_and_extend_allowance(&env, from, spender, amount, live_until_ledger);
     }
 
     /// Return the balance of `id`
@@ -1066,9 +2613,27 @@ impl TokenInterface for TokenContract {
             to.address() != from,
             Error::CannotTransferToSelf
         );
+        let to_address = to.address();
+        for id in [&from, &to_address] {
+            if let Err(e) = Self::require_authorized(&env, id) {
+                panic_with_error!(env, e);
+            }
+        }
         let balance = Self::balance(env.clone(), from.clone());
         assert_with_error!(env, balance >= amount, Error::InsufficientBalance);
-        Self::transfer_internal(&env, from, to.address(), amount);
+        match to.id() {
+            Some(to_muxed_id) => {
+                Self::move_balance(&env, from.clone(), to_address.clone(), amount);
+                events::TransferMuxed {
+                    from,
+                    to: to_address,
+                    to_muxed_id,
+                    amount,
+                }
+                .publish(&env);
+            }
+            None => Self::transfer_internal(&env, from, to_address, amount),
+        }
     }
 
     /// Transfer `amount` from `from` to `to`, consuming the allowance of `spender`
@@ -1081,6 +2646,11 @@ impl TokenInterface for TokenContract {
             allowance >= amount,
             Error::InsufficientAllowance
         );
+        for id in [&from, &to] {
+            if let Err(e) = Self::require_authorized(&env, id) {
+                panic_with_error!(env, e);
+            }
+        }
         assert_with_error!(
             env.clone(),
             Self::balance(env.clone(), from.clone()) >= amount,
@@ -1224,87 +2794,151 @@ impl IsCollateralized for TokenContract {
         asset_lent: i128,
     ) -> Result<(), Error> {
         assert_positive(env, collateral);
-        assert_positive(env, asset_lent);
-        lender.require_auth();
+        Self::open_cdp_with(env, lender, collateral, Map::new(env), asset_lent)
+    }
 
-        let cdp: Option<CDPInternal> = env
-            .storage()
-            .persistent()
-            .get(&DataKey::CDP(lender.clone()));
-        // 1. check if lender already has a CDP
-        if cdp.is_some() {
-            return Err(Error::CDPAlreadyExists);
+    /// Open a new CDP by depositing a registered collateral type and minting xAsset
+    fn open_cdp_in(
+        env: &Env,
+        lender: Address,
+        collateral_type: Address,
+        collateral: i128,
+        asset_lent: i128,
+    ) -> Result<(), Error> {
+        assert_positive(env, collateral);
+        Self::collateral_type(env, &collateral_type)?;
+        Self::open_cdp_with(
+            env,
+            lender,
+            0,
+            Map::from_array(env, [(collateral_type, collateral)]),
+            asset_lent,
+        )
+    }
+
+    /// Retrieve the CDP information for a specific lender
+    fn cdp(env: &Env, lender: Address) -> Result<CDPContract, Error> {
+        let cdp = TokenStorage::get_cdp(env, lender.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
+        Self::decorate_now(env, cdp, lender)
+    }
+
+    /// Get a CDP's liquidation price and borrowing and withdrawal headroom, searching for where
+    /// its collateralization ratio meets the minimum ratio
+    fn cdp_health(env: &Env, lender: Address) -> Result<CDPHealth, Error> {
+        let cdp = TokenStorage::get_cdp(env, lender.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
+        if matches!(cdp.status, CDPStatus::Closed) || matches!(cdp.status, CDPStatus::Frozen) {
+            return Err(Error::CDPNotOpenOrInsolvent);
         }
-
-        // 2. check that `lastprice` gives collateralization ratio over `min_collat_ratio`
-        let cdp = CDPInternal::new(collateral, asset_lent, env.ledger().timestamp());
-        let xlm_price = Self::lastprice_xlm(env)?;
+        let xlm_price = Self::lastprice_xlm(env)?.price;
         let xlm_decimals = Self::decimals_xlm_feed(env)?;
-        let xasset_price = Self::lastprice_asset(env)?;
+        let xasset_price = Self::lastprice_asset(env)?.price;
         let xasset_decimals = Self::decimals_asset_feed(env)?;
-        let CDPContract {
-            collateralization_ratio,
-            ..
-        } = Self::decorate(
-            env,
-            cdp,
-            lender.clone(),
-            xlm_price.price,
-            xlm_decimals,
-            xasset_price.price,
-            xasset_decimals,
-        );
-        if collateralization_ratio < Self::minimum_collateralization_ratio(env) {
-            return Err(Error::InsufficientCollateralization);
-        }
+        let min_ratio = Self::minimum_collateralization_ratio(env);
+        let weighted =
+            Self::weighted_collateral_xlm(env, &cdp.collateral, xlm_price, xlm_decimals)?;
+        let debt = cdp.asset_lent;
+
+        // Whether the CDP meets the minimum ratio with `xlm` besides its other collateral, at
+        // `price` for XLM, with `debt`, as `decorate` computes it. Other collateral keeps its
+        // value as XLM's price moves.
+        let meets_min = |xlm: i128, price: i128, debt: i128| {
+            let collateral = xlm + weighted * xlm_price / price;
+            calculate_collateralization_ratio(
+                debt,
+                xasset_price,
+                collateral,
+                price,
+                xlm_decimals,
+                xasset_decimals,
+                cdp.accrued_interest.amount,
+            ) >= min_ratio
+        };
 
-        // 3. transfer attached XLM to this contract
-        let _ = Self::native(env)
-            .try_transfer(&lender, &env.current_contract_address(), &collateral)
-            .map_err(|_| Error::XLMTransferFailed)?;
+        // The ratio rises with XLM's price, so search down from one at which it is met
+        let net_xlm = cdp
+            .xlm_deposited
+            .saturating_sub(cdp.accrued_interest.amount);
+        let liquidation_price =
+            if debt == 0 || net_xlm <= 0 || meets_min(cdp.xlm_deposited, 1, debt) {
+                None
+            } else {
+                let mut above = xlm_price;
+                while !meets_min(cdp.xlm_deposited, above, debt) {
+                    above *= 2;
+                }
+                let below = max_where(1, above, |price| !meets_min(cdp.xlm_deposited, price, debt));
+                Some(below + 1)
+            };
 
-        // 4. mint `asset_lent` of this token to `address`
-        Self::mint_internal(env, lender.clone(), asset_lent);
+        // Borrowing adds its fee to the debt, as in `projected_cdp_ratio`
+        let can_borrow = |amount: i128| {
+            let fee = if amount > 0 {
+                Self::calculate_borrow_fee(env, amount)
+            } else {
+                0
+            };
+            meets_min(cdp.xlm_deposited, xlm_price, debt + amount + fee)
+        };
+        let mut beyond = 1;
+        while can_borrow(beyond) {
+            beyond *= 2;
+        }
+        let max_borrow = max_where(0, beyond, can_borrow);
 
-        // 5. create CDP
-        env.storage()
-            .persistent()
-            .set(&DataKey::CDP(lender.clone()), &cdp);
+        // Without debt the ratio is unbounded, so all XLM can be withdrawn
+        let max_withdraw = if debt == 0 {
+            cdp.xlm_deposited
+        } else {
+            max_where(0, cdp.xlm_deposited, |amount| {
+                meets_min(cdp.xlm_deposited - amount, xlm_price, debt)
+            })
+        };
 
-        env.events().publish(
-            (Symbol::new(env, "CDP"), lender.clone()),
-            crate::index_types::CDP {
-                id: lender.clone(),
-                xlm_deposited: cdp.xlm_deposited, // From your existing cdp
-                asset_lent: cdp.asset_lent,
-                accrued_interest: cdp.accrued_interest.amount,
-                interest_paid: cdp.accrued_interest.paid,
-                last_interest_time: cdp.last_interest_time,
-                status: cdp.status,
-                ledger: env.ledger().sequence(),
-                timestamp: env.ledger().timestamp(),
-            },
-        );
-        Ok(())
+        Ok(CDPHealth {
+            liquidation_price,
+            max_borrow,
+            max_withdraw,
+        })
     }
 
-    /// Retrieve the CDP information for a specific lender
-    fn cdp(env: &Env, lender: Address) -> Result<CDPContract, Error> {
+    /// Get a CDP's collateralization ratio after a hypothetical deposit and loan
+    fn projected_cdp_ratio(
+        env: &Env,
+        lender: Address,
+        xlm: i128,
+        asset_lent: i128,
+    ) -> Result<u32, Error> {
         let cdp = TokenStorage::get_cdp(env, lender.clone())
             .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
-        let xlm_price = Self::lastprice_xlm(env)?;
-        let xlm_decimals = Self::decimals_xlm_feed(env)?;
-        let xasset_price = Self::lastprice_asset(env)?;
-        let xasset_decimals = Self::decimals_asset_feed(env)?;
-        Ok(Self::decorate(
-            env,
-            cdp,
-            lender,
-            xlm_price.price,
-            xlm_decimals,
-            xasset_price.price,
-            xasset_decimals,
-        ))
+        let fee = if asset_lent > 0 {
+            Self::calculate_borrow_fee(env, asset_lent)
+        } else {
+            0
+        };
+        let Some(xlm_deposited) = cdp.xlm_deposited.checked_add(xlm) else {
+            return Err(Error::ArithmeticError);
+        };
+        let Some(asset_lent) = cdp
+            .asset_lent
+            .checked_add(asset_lent)
+            .and_then(|debt| debt.checked_add(fee))
+        else {
+            return Err(Error::ArithmeticError);
+        };
+        if xlm_deposited < 0 {
+            return Err(Error::InsufficientCollateral);
+        }
+        if asset_lent < 0 {
+            return Err(Error::RepaymentExceedsDebt);
+        }
+        let projected = CDPInternal {
+            xlm_deposited,
+            asset_lent,
+            ..cdp
+        };
+        Ok(Self::decorate_now(env, projected, lender)?.collateralization_ratio)
     }
 
     /// Freeze a CDP if its Collateralization Ratio (CR) is below the xAsset's Minimum Collateralization Ratio (MCR).
@@ -1322,27 +2956,54 @@ impl IsCollateralized for TokenContract {
 
     /// Increase the Collateralization Ratio (CR) by depositing more collateral to an existing CDP.
     fn add_collateral(env: &Env, lender: Address, amount: i128) -> Result<(), Error> {
+        lender.require_auth();
+        Self::add_collateral_from(env, lender.clone(), &lender, amount)
+    }
+
+    /// Add collateral to a CDP as one of its owner's operators, paying from the operator's balance
+    fn add_collateral_for(
+        env: &Env,
+        operator: Address,
+        lender: Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        Self::require_operator(env, &lender, &operator);
+        Self::add_collateral_from(env, lender, &operator, amount)
+    }
+
+    /// Increase the Collateralization Ratio (CR) by depositing a registered collateral type
+    fn add_collateral_in(
+        env: &Env,
+        lender: Address,
+        collateral_type: Address,
+        amount: i128,
+    ) -> Result<(), Error> {
         assert_positive(env, amount);
         lender.require_auth();
-        let mut cdp: CDPInternal = TokenStorage::get_cdp(env, lender.clone())
+        Self::collateral_type(env, &collateral_type)?;
+        let cdp = TokenStorage::get_cdp(env, lender.clone())
             .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
 
         if matches!(cdp.status, CDPStatus::Closed) || matches!(cdp.status, CDPStatus::Frozen) {
             return Err(Error::CDPNotOpenOrInsolvent);
         }
 
-        // Calculate the new deposit amount and check for overflow before we transfer
-        let Some(new_deposit) = cdp.xlm_deposited.checked_add(amount) else {
+        let held = cdp.collateral.get(collateral_type.clone()).unwrap_or(0);
+        let Some(new_deposit) = held.checked_add(amount) else {
             return Err(Error::ArithmeticError);
         };
+        let mut new_cdp = cdp.clone();
+        new_cdp.collateral.set(collateral_type.clone(), new_deposit);
+        Self::check_debt_ceilings(env, Some(&cdp), &new_cdp)?;
 
-        // Transfer XLM from lender to contract
-        let _ = Self::native(env)
-            .try_transfer(&lender, env.current_contract_address(), &amount)
-            .map_err(|_| Error::XLMTransferFailed)?;
-
-        cdp.xlm_deposited = new_deposit;
-        TokenStorage::set_cdp(env, lender, cdp);
+        Self::transfer_collateral(
+            env,
+            &collateral_type,
+            &lender,
+            &env.current_contract_address(),
+            amount,
+        )?;
+        TokenStorage::set_cdp(env, lender, new_cdp);
         Ok(())
     }
 
@@ -1366,17 +3027,14 @@ impl IsCollateralized for TokenContract {
             env,
             CDPInternal {
                 xlm_deposited: cdp.xlm_deposited - amount,
-                asset_lent: cdp.asset_lent,
-                status: cdp.status,
-                accrued_interest: cdp.accrued_interest,
-                last_interest_time: cdp.last_interest_time,
+                ..cdp.clone()
             },
             lender.clone(),
             Self::lastprice_xlm(env)?.price,
             Self::decimals_xlm_feed(env)?,
             Self::lastprice_asset(env)?.price,
             Self::decimals_asset_feed(env)?,
-        );
+        )?;
 
         if new_cdp.collateralization_ratio < Self::minimum_collateralization_ratio(env) {
             return Err(Error::InvalidWithdrawal);
@@ -1397,8 +3055,14 @@ impl IsCollateralized for TokenContract {
         Ok(())
     }
 
-    /// Lower the Collateralization Ratio (CR) by minting additional xAsset against existing collateral
-    fn borrow_xasset(env: &Env, lender: Address, amount: i128) -> Result<(), Error> {
+    /// Lower the Collateralization Ratio (CR) by withdrawing part or all of a registered
+    /// collateral type from a CDP
+    fn withdraw_collateral_in(
+        env: &Env,
+        lender: Address,
+        collateral_type: Address,
+        amount: i128,
+    ) -> Result<(), Error> {
         assert_positive(env, amount);
         lender.require_auth();
         let cdp = TokenStorage::get_cdp(env, lender.clone())
@@ -1408,89 +3072,84 @@ impl IsCollateralized for TokenContract {
             return Err(Error::CDPNotOpenOrInsolvent);
         }
 
-        let Some(asset_lent) = cdp.asset_lent.checked_add(amount) else {
-            return Err(Error::ArithmeticError);
-        };
+        let held = cdp.collateral.get(collateral_type.clone()).unwrap_or(0);
+        if held < amount {
+            return Err(Error::InsufficientCollateral);
+        }
+        let mut collateral = cdp.collateral.clone();
+        if held == amount {
+            collateral.remove(collateral_type.clone());
+        } else {
+            collateral.set(collateral_type.clone(), held - amount);
+        }
+        let new_cdp = CDPInternal { collateral, ..cdp };
 
-        let new_cdp = Self::decorate(
-            env,
-            CDPInternal {
-                xlm_deposited: cdp.xlm_deposited,
-                asset_lent,
-                status: cdp.status,
-                accrued_interest: cdp.accrued_interest,
-                last_interest_time: cdp.last_interest_time,
-            },
-            lender.clone(),
-            Self::lastprice_xlm(env)?.price,
-            Self::decimals_xlm_feed(env)?,
-            Self::lastprice_asset(env)?.price,
-            Self::decimals_asset_feed(env)?,
-        );
+        let decorated_cdp = Self::decorate_now(env, new_cdp.clone(), lender.clone())?;
 
-        if new_cdp.collateralization_ratio < Self::minimum_collateralization_ratio(env) {
-            return Err(Error::InsufficientCollateralization);
+        if decorated_cdp.collateralization_ratio < Self::minimum_collateralization_ratio(env) {
+            return Err(Error::InvalidWithdrawal);
         }
 
-        // mint xasset
-        Self::mint_internal(env, lender.clone(), amount);
-
-        Self::set_cdp_from_decorated(env, lender, new_cdp);
+        Self::transfer_collateral(
+            env,
+            &collateral_type,
+            &env.current_contract_address(),
+            &lender,
+            amount,
+        )?;
+        TokenStorage::set_cdp(env, lender, new_cdp);
         Ok(())
     }
 
-    /// Increase the Collateralization Ratio (CR) by repaying debt in the form of xAsset
-    fn repay_debt(env: &Env, lender: Address, amount: i128) -> Result<(), Error> {
+    /// Lower the Collateralization Ratio (CR) by minting additional xAsset against existing collateral
+    fn borrow_xasset(env: &Env, lender: Address, amount: i128) -> Result<(), Error> {
         assert_positive(env, amount);
         lender.require_auth();
-        let mut cdp = TokenStorage::get_cdp(env, lender.clone())
+        let cdp = TokenStorage::get_cdp(env, lender.clone())
             .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
 
         if matches!(cdp.status, CDPStatus::Closed) || matches!(cdp.status, CDPStatus::Frozen) {
-            return Err(Error::CDPNotOpenOrInsolventForRepay);
+            return Err(Error::CDPNotOpenOrInsolvent);
         }
 
-        // Pay off any interest first
-        // cdp = Self::pay_interest_from(env, lender.clone())?;
-        Self::apply_interest_payment(env, lender.clone(), 0, |from, amount_in_xlm| {
-            match Self::native(env).try_transfer_from(
-                &env.current_contract_address(),
-                from,
-                &env.current_contract_address(),
-                amount_in_xlm,
-            ) {
-                Ok(Ok(())) => Ok(()),
-                Ok(Err(_)) => Err(Error::InsufficientApprovedXLMForInterestRepayment),
-                Err(_) => Err(Error::XLMInvocationFailed),
-            }
-        })?;
+        let fee = Self::calculate_borrow_fee(env, amount);
+        let Some(asset_lent) = cdp
+            .asset_lent
+            .checked_add(amount)
+            .and_then(|debt| debt.checked_add(fee))
+        else {
+            return Err(Error::ArithmeticError);
+        };
 
-        // Now continue with debt repayment
-        if cdp.asset_lent < amount {
-            return Err(Error::RepaymentExceedsDebt);
-        }
+        let new_cdp = Self::decorate_now(env, CDPInternal { asset_lent, ..cdp }, lender.clone())?;
 
-        // Check to ensure enough xasset is available
-        if Self::balance(env.clone(), lender.clone()) < amount {
-            return Err(Error::InsufficientBalance);
+        if new_cdp.collateralization_ratio < Self::minimum_collateralization_ratio(env) {
+            return Err(Error::InsufficientCollateralization);
         }
 
-        // Calculate the new asset lent amount and check for overflow before we burn
-        let Some(asset_lent) = cdp.asset_lent.checked_sub(amount) else {
-            return Err(Error::ArithmeticError);
-        };
+        // mint xasset, and the borrowing fee to its recipient
+        Self::mint_internal(env, lender.clone(), amount);
+        Self::distribute_borrow_fee(env, fee);
 
-        // Burn the xasset
-        Self::burn_internal(env, lender.clone(), amount);
+        Self::set_cdp_from_decorated(env, lender, new_cdp);
+        Ok(())
+    }
 
-        cdp.asset_lent = asset_lent;
+    /// Increase the Collateralization Ratio (CR) by repaying debt in the form of xAsset
+    fn repay_debt(env: &Env, lender: Address, amount: i128) -> Result<(), Error> {
+        lender.require_auth();
+        Self::repay_debt_from(env, lender.clone(), &lender, amount)
+    }
 
-        if cdp.asset_lent == 0 && cdp.xlm_deposited == 0 {
-            Self::close_cdp(env, lender)?;
-        } else {
-            TokenStorage::set_cdp(env, lender, cdp);
-        }
-        Ok(())
+    /// Repay a CDP's debt as one of its owner's operators, paying from the operator's balances
+    fn repay_debt_for(
+        env: &Env,
+        operator: Address,
+        lender: Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        Self::require_operator(env, &lender, &operator);
+        Self::repay_debt_from(env, lender, &operator, amount)
     }
 
     /// Liquidate a frozen CDP. Upon liquidation, CDP debt is repaid by withdrawing xAsset from a Stability Pool
@@ -1507,13 +3166,23 @@ impl IsCollateralized for TokenContract {
         let mut total_xlm: i128 = 0;
         let mut total_asset: i128 = 0;
         let mut total_interest: Interest = Interest::default();
+        let mut total_collateral: Map<Address, i128> = Map::new(env);
+        let mut interest_rate = None;
+        let mut rate_adjusted_time = 0;
 
-        for lender in lenders.iter() {
+        for (i, lender) in lenders.iter().enumerate() {
             let cdp = TokenStorage::get_cdp(env, lender.clone())
                 .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
             if !matches!(cdp.status, CDPStatus::Frozen) {
                 return Err(Error::InvalidMerge);
             }
+            // The merged CDP keeps its borrower-chosen rate, so all must share it
+            if i == 0 {
+                interest_rate = cdp.interest_rate;
+            } else if cdp.interest_rate != interest_rate {
+                return Err(Error::InvalidMerge);
+            }
+            rate_adjusted_time = rate_adjusted_time.max(cdp.rate_adjusted_time);
 
             let Some(new_total_xlm) = total_xlm.checked_add(cdp.xlm_deposited) else {
                 return Err(Error::ArithmeticError);
@@ -1532,6 +3201,13 @@ impl IsCollateralized for TokenContract {
             else {
                 return Err(Error::ArithmeticError);
             };
+            for (token, amount) in cdp.collateral.iter() {
+                let held = total_collateral.get(token.clone()).unwrap_or(0);
+                let Some(new_held) = held.checked_add(amount) else {
+                    return Err(Error::ArithmeticError);
+                };
+                total_collateral.set(token, new_held);
+            }
             total_xlm = new_total_xlm;
             total_asset = new_total_asset;
             total_interest.amount = new_total_interest_amount;
@@ -1545,6 +3221,10 @@ impl IsCollateralized for TokenContract {
             status: CDPStatus::Frozen,
             accrued_interest: total_interest,
             last_interest_time: env.ledger().timestamp(),
+            interest_index: INTEREST_PRECISION,
+            interest_rate,
+            rate_adjusted_time,
+            collateral: total_collateral,
         };
         let first_lender = lenders.get(0).unwrap();
         TokenStorage::set_cdp(env, first_lender.clone(), merged_cdp);
@@ -1570,6 +3250,15 @@ impl IsCollateralized for TokenContract {
                 .try_transfer(&env.current_contract_address(), &lender, &cdp.xlm_deposited)
                 .map_err(|_| Error::XLMTransferFailed)?;
         }
+        for (token, amount) in cdp.collateral.iter() {
+            Self::transfer_collateral(
+                env,
+                &token,
+                &env.current_contract_address(),
+                &lender,
+                amount,
+            )?;
+        }
         env.events().publish(
             (Symbol::new(env, "CDP"), lender.clone()),
             crate::index_types::CDP {
@@ -1588,19 +3277,171 @@ impl IsCollateralized for TokenContract {
         Ok(())
     }
 
+    /// Move part or all of a CDP to a new owner on behalf of the CDP manager
+    fn move_cdp(
+        env: &Env,
+        from: Address,
+        to: Address,
+        xlm: i128,
+        collateral: Map<Address, i128>,
+        asset_lent: i128,
+    ) -> Result<(), Error> {
+        let manager = Self::get_cdp_manager(env).ok_or(Error::CDPManagerNotSet)?;
+        manager.require_auth();
+        Self::require_authorized(env, &to)?;
+        if from == to {
+            return Err(Error::CannotTransferToSelf);
+        }
+        if env.storage().persistent().has(&DataKey::CDP(to.clone())) {
+            return Err(Error::CDPAlreadyExists);
+        }
+        Self::migrate_account(env, &from);
+        let cdp: StoredCDP = env
+            .storage()
+            .persistent()
+            .get(&DataKey::CDP(from.clone()))
+            .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
+        if matches!(cdp.status, CDPStatus::Frozen) {
+            return Err(Error::CDPNotOpenOrInsolvent);
+        }
+        if xlm != cdp.xlm_deposited || collateral != cdp.collateral || asset_lent != cdp.asset_lent
+        {
+            // Carve part of the CDP out into a new one
+            let cdp = Self::cdp(env, from.clone())?;
+            if !matches!(cdp.status, CDPStatus::Open) {
+                return Err(Error::CDPNotOpen);
+            }
+            if xlm < 0 || asset_lent < 0 {
+                return Err(Error::ValueNotPositive);
+            }
+            if asset_lent > cdp.asset_lent {
+                return Err(Error::RepaymentExceedsDebt);
+            }
+            if xlm > cdp.xlm_deposited {
+                return Err(Error::InsufficientCollateral);
+            }
+
+            let mut remaining_collateral = cdp.collateral.clone();
+            for (token, amount) in collateral.iter() {
+                let held = cdp.collateral.get(token.clone()).unwrap_or(0);
+                if amount <= 0 {
+                    return Err(Error::ValueNotPositive);
+                }
+                if amount > held {
+                    return Err(Error::InsufficientCollateral);
+                }
+                if amount == held {
+                    remaining_collateral.remove(token);
+                } else {
+                    remaining_collateral.set(token, held - amount);
+                }
+            }
+            // Both CDPs need collateral of some kind
+            let remaining_xlm = cdp.xlm_deposited - xlm;
+            if (xlm == 0 && collateral.is_empty())
+                || (remaining_xlm == 0 && remaining_collateral.is_empty())
+            {
+                return Err(Error::InsufficientCollateral);
+            }
+
+            // Accrued interest goes along with the debt it accrued on
+            let interest = cdp.accrued_interest;
+            let carved_interest = if cdp.asset_lent == 0 {
+                0
+            } else {
+                interest.amount * asset_lent / cdp.asset_lent
+            };
+            let now = env.ledger().timestamp();
+            let index = Self::accrue_interest_index(env);
+            let remaining_cdp = CDPInternal {
+                xlm_deposited: remaining_xlm,
+                asset_lent: cdp.asset_lent - asset_lent,
+                status: CDPStatus::Open,
+                last_interest_time: now,
+                accrued_interest: Interest {
+                    amount: interest.amount - carved_interest,
+                    paid: interest.paid,
+                },
+                interest_index: index,
+                interest_rate: cdp.interest_rate,
+                rate_adjusted_time: cdp.rate_adjusted_time,
+                collateral: remaining_collateral,
+            };
+            let carved_cdp = CDPInternal {
+                xlm_deposited: xlm,
+                asset_lent,
+                accrued_interest: Interest {
+                    amount: carved_interest,
+                    paid: 0,
+                },
+                collateral,
+                ..remaining_cdp.clone()
+            };
+
+            let xlm_price = Self::lastprice_xlm(env)?.price;
+            let xlm_decimals = Self::decimals_xlm_feed(env)?;
+            let xasset_price = Self::lastprice_asset(env)?.price;
+            let xasset_decimals = Self::decimals_asset_feed(env)?;
+            for (owner, split) in [(&from, &remaining_cdp), (&to, &carved_cdp)] {
+                let decorated = Self::decorate(
+                    env,
+                    split.clone(),
+                    owner.clone(),
+                    xlm_price,
+                    xlm_decimals,
+                    xasset_price,
+                    xasset_decimals,
+                )?;
+                if decorated.collateralization_ratio < Self::minimum_collateralization_ratio(env) {
+                    return Err(Error::InsufficientCollateralization);
+                }
+            }
+            TokenStorage::set_cdp(env, from.clone(), remaining_cdp);
+            TokenStorage::set_cdp(env, to.clone(), carved_cdp);
+            events::CDPTransfer {
+                from,
+                to,
+                xlm_deposited: xlm,
+                asset_lent,
+            }
+            .publish(env);
+            return Ok(());
+        }
+
+        // The CDP moves as stored, so the totals it counts towards are unaffected
+        env.storage()
+            .persistent()
+            .remove(&DataKey::CDP(from.clone()));
+        env.storage()
+            .persistent()
+            .set(&DataKey::CDP(to.clone()), &cdp);
+        let ttl = env.storage().max_ttl();
+        env.storage()
+            .persistent()
+            .extend_ttl(&DataKey::CDP(to.clone()), ttl, ttl);
+        events::CDPTransfer {
+            from,
+            to,
+            xlm_deposited: cdp.xlm_deposited,
+            asset_lent: cdp.asset_lent,
+        }
+        .publish(env);
+        Ok(())
+    }
+
     /// Update and return the accrued interest on a CDP
     fn get_accrued_interest(env: &Env, lender: Address) -> Result<InterestDetail, Error> {
         let cdp = TokenStorage::get_cdp(env, lender.clone())
             .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
-        let (interest, last_interest_time) = Self::get_updated_accrued_interest(env, &cdp)?;
+        let interest = cdp.accrued_interest;
+        let last_interest_time = cdp.last_interest_time;
 
         // Calculate approvalAmount: Projected interest 5 minutes ahead
         let now = env.ledger().timestamp();
         let five_min_later = now + 300; // 5 minutes in seconds
 
         // Project interest 5 minutes ahead
-        let projected_interest =
-            Self::get_projected_interest(env, &cdp, cdp.last_interest_time, five_min_later)?;
+        let projected_interest = Self::get_projected_interest(env, &cdp, five_min_later);
         let approval_amount = Self::convert_xasset_to_xlm(env, projected_interest.amount)?;
 
         // Calculate interest in XLM
@@ -1624,20 +3465,61 @@ impl IsCollateralized for TokenContract {
         assert_positive(env, amount_in_xasset);
         lender.require_auth();
 
-        if amount_in_xasset <= 0 {
-            return Err(Error::ValueNotPositive);
+        if amount_in_xasset <= 0 {
+            return Err(Error::ValueNotPositive);
+        }
+        let mut cdp = TokenStorage::get_cdp(env, lender.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
+        Self::pay_accrued_interest(env, &mut cdp, &lender, amount_in_xasset, false)?;
+        let decorated_cdp = Self::decorate_now(env, cdp, lender.clone())?;
+        Self::set_cdp_from_decorated(env, lender, decorated_cdp.clone());
+        Ok(decorated_cdp)
+    }
+
+    /// Choose the annual interest rate charged on a CDP, or follow the protocol's rate
+    fn set_cdp_interest_rate(
+        env: &Env,
+        lender: Address,
+        rate: Option<u32>,
+    ) -> Result<CDPContract, Error> {
+        lender.require_auth();
+        let mut cdp = TokenStorage::get_cdp(env, lender.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
+
+        if !cdp.accrues_interest() {
+            return Err(Error::CDPNotOpenOrInsolvent);
         }
-        Self::apply_interest_payment(env, lender, amount_in_xasset, |lender, amount_in_xlm| {
-            match Self::native(env).try_transfer(
-                lender,
-                &env.current_contract_address(),
-                amount_in_xlm,
-            ) {
-                Ok(Ok(())) => Ok(()), // both contract invocation and logic succeeded
-                Ok(Err(_)) => Err(Error::XLMTransferFailed), // invocation succeeded but logic failed
-                Err(_) => Err(Error::XLMInvocationFailed),   // invocation (host error) failed
-            }
-        })
+
+        let state = TokenStorage::get_state(env);
+        if rate.is_some_and(|rate| rate < state.min_cdp_rate || rate > state.max_cdp_rate) {
+            return Err(Error::InvalidInterestRate);
+        }
+
+        // Changing the rate again before the cooldown is over costs the new rate's interest
+        // on the CDP's debt over the cooldown period
+        let now = env.ledger().timestamp();
+        if cdp.rate_adjusted_time > 0
+            && now < cdp.rate_adjusted_time + state.rate_adjustment_cooldown
+        {
+            let annual_rate = rate.unwrap_or(state.interest_rate);
+            let fee = bankers_round(
+                DEFAULT_PRECISION
+                    * (cdp.asset_lent + cdp.accrued_interest.amount)
+                    * i128::from(annual_rate)
+                    * i128::from(state.rate_adjustment_cooldown)
+                    / (BASIS_POINTS * i128::from(SECONDS_PER_YEAR)),
+                DEFAULT_PRECISION,
+            );
+            let Some(accrued_interest) = cdp.accrued_interest.amount.checked_add(fee) else {
+                return Err(Error::ArithmeticError);
+            };
+            cdp.accrued_interest.amount = accrued_interest;
+        }
+
+        cdp.interest_rate = rate;
+        cdp.rate_adjusted_time = now;
+        TokenStorage::set_cdp(env, lender.clone(), cdp);
+        Self::cdp(env, lender)
     }
 }
 
@@ -1687,6 +3569,8 @@ impl IsCDPAdmin for TokenContract {
     /// Set annual interest rate
     fn set_interest_rate(env: &Env, new_rate: u32) -> u32 {
         Self::require_admin(env);
+        // Accrue at the old rate up to now, so the new rate only applies going forward
+        Self::accrue_interest_index(env);
         Self::set_annual_interest_rate(env, new_rate);
         new_rate
     }
@@ -1701,6 +3585,283 @@ impl IsCDPAdmin for TokenContract {
         TokenStorage::get_state(env).interest_collected
     }
 
+    /// Get the current interest index
+    fn get_interest_index(env: &Env) -> i128 {
+        Self::interest_index_at(env, env.ledger().timestamp())
+    }
+
+    /// Set the interest rate model
+    fn set_rate_model(env: &Env, model: RateModel) {
+        Self::require_admin(env);
+        if let RateModel::PegDeviation(curve, _) | RateModel::PoolCoverage(curve) = &model {
+            assert_with_error!(env, curve.is_valid(), Error::InvalidRateModel);
+        }
+        // Accrue at the old rate up to now, so the new model only applies going forward
+        Self::accrue_interest_index(env);
+        let mut state = TokenStorage::get_state(env);
+        state.rate_model = model;
+        TokenStorage::set_state(env, &state);
+        Self::update_interest_rate(env);
+    }
+
+    /// Get the interest rate model
+    fn get_rate_model(env: &Env) -> RateModel {
+        TokenStorage::get_state(env).rate_model
+    }
+
+    /// Get the interest rate model's current inputs and output
+    fn get_rate_inputs(env: &Env) -> Result<RateInputs, Error> {
+        let model = TokenStorage::get_state(env).rate_model;
+        Ok(RateInputs {
+            utilization: Self::rate_model_utilization(env, &model)?,
+            rate: Self::model_rate(env, &model)?,
+            model,
+        })
+    }
+
+    /// Get total outstanding interest across all CDPs accruing interest
+    fn get_total_outstanding_interest(env: &Env) -> i128 {
+        cmp::max(
+            0,
+            Self::total_debt(env) - TokenStorage::get_state(env).total_principal,
+        )
+    }
+
+    /// Set the range of annual rates borrowers may choose for their CDPs
+    fn set_cdp_rate_bounds(env: &Env, min: u32, max: u32) {
+        Self::require_admin(env);
+        assert_with_error!(
+            env,
+            min <= max && max <= MAX_CDP_INTEREST_RATE,
+            Error::InvalidInterestRate
+        );
+        let mut state = TokenStorage::get_state(env);
+        state.min_cdp_rate = min;
+        state.max_cdp_rate = max;
+        TokenStorage::set_state(env, &state);
+    }
+
+    /// Set the cooldown after a CDP rate change during which another change is charged a fee
+    fn set_rate_adjustment_cooldown(env: &Env, seconds: u64) -> u64 {
+        Self::require_admin(env);
+        assert_with_error!(
+            env,
+            seconds <= SECONDS_PER_YEAR,
+            Error::InvalidRateAdjustmentCooldown
+        );
+        let mut state = TokenStorage::get_state(env);
+        state.rate_adjustment_cooldown = seconds;
+        TokenStorage::set_state(env, &state);
+        seconds
+    }
+
+    /// Set the one-time borrowing fee floor and cap, in basis points
+    fn set_borrow_fee(env: &Env, floor: u32, cap: u32) {
+        Self::require_admin(env);
+        assert_with_error!(
+            env,
+            floor <= cap && i128::from(cap) <= BASIS_POINTS,
+            Error::InvalidBorrowFee
+        );
+        let mut state = TokenStorage::get_state(env);
+        state.borrow_fee.floor = floor;
+        state.borrow_fee.cap = cap;
+        TokenStorage::set_state(env, &state);
+    }
+
+    /// Set the base rate added on top of the borrowing fee floor
+    fn set_base_rate(env: &Env, rate: u32) -> u32 {
+        Self::require_admin(env);
+        assert_with_error!(
+            env,
+            i128::from(rate) <= BASIS_POINTS,
+            Error::InvalidBorrowFee
+        );
+        let mut state = TokenStorage::get_state(env);
+        state.borrow_fee.base_rate = rate;
+        state.borrow_fee.base_rate_time = env.ledger().timestamp();
+        TokenStorage::set_state(env, &state);
+        rate
+    }
+
+    /// Set the half-life, in seconds, of the base rate's decay
+    fn set_base_rate_half_life(env: &Env, seconds: u64) -> u64 {
+        Self::require_admin(env);
+        assert_with_error!(env, seconds > 0, Error::InvalidBorrowFee);
+        // Settle the decay accrued under the previous half-life
+        let rate = Self::decayed_base_rate(env);
+        let mut state = TokenStorage::get_state(env);
+        state.borrow_fee.base_rate = rate;
+        state.borrow_fee.base_rate_time = env.ledger().timestamp();
+        state.borrow_fee.base_rate_half_life = seconds;
+        TokenStorage::set_state(env, &state);
+        seconds
+    }
+
+    /// Set the recipient of borrowing fees
+    fn set_fee_recipient(env: &Env, to: Option<Address>) {
+        Self::require_admin(env);
+        let mut state = TokenStorage::get_state(env);
+        state.borrow_fee.recipient = to;
+        TokenStorage::set_state(env, &state);
+    }
+
+    /// Set the asset CDP interest is paid in
+    fn set_interest_payment(env: &Env, payment: InterestPayment) {
+        Self::require_admin(env);
+        let mut state = TokenStorage::get_state(env);
+        state.interest_payment = payment;
+        TokenStorage::set_state(env, &state);
+    }
+
+    /// Get the asset CDP interest is paid in
+    fn get_interest_payment(env: &Env) -> InterestPayment {
+        TokenStorage::get_state(env).interest_payment
+    }
+
+    /// Set the share of interest distributed to stability pool stakers
+    fn set_interest_share(env: &Env, share: u32) -> u32 {
+        Self::require_admin(env);
+        assert_with_error!(
+            env,
+            i128::from(share) <= BASIS_POINTS,
+            Error::InvalidInterestShare
+        );
+        let mut state = TokenStorage::get_state(env);
+        state.interest_share = share;
+        TokenStorage::set_state(env, &state);
+        share
+    }
+
+    /// Get the share of interest distributed to stability pool stakers
+    fn get_interest_share(env: &Env) -> u32 {
+        TokenStorage::get_state(env).interest_share
+    }
+
+    /// Get interest retained for the treasury
+    fn get_treasury_interest(env: &Env) -> i128 {
+        TokenStorage::get_state(env).treasury_interest
+    }
+
+    /// Set the treasury
+    fn set_treasury(env: &Env, to: Address) {
+        Self::require_admin(env);
+        let mut state = TokenStorage::get_state(env);
+        state.treasury = Some(to);
+        TokenStorage::set_state(env, &state);
+    }
+
+    /// Get the treasury
+    fn get_treasury(env: &Env) -> Option<Address> {
+        TokenStorage::get_state(env).treasury
+    }
+
+    /// Get the XLM and xAsset that `sweep_to_treasury` would transfer now
+    fn get_sweepable(env: &Env) -> (i128, i128) {
+        let (fees, interest) = Self::sweepable(env);
+        (
+            fees + interest,
+            TokenStorage::get_state(env).xasset_fees_collected,
+        )
+    }
+
+    /// Transfer collected fees and the treasury's share of interest to the treasury
+    fn sweep_to_treasury(env: &Env) -> Result<(i128, i128), Error> {
+        Self::require_admin(env);
+        let Some(treasury) = TokenStorage::get_state(env).treasury else {
+            return Err(Error::TreasuryNotSet);
+        };
+        let (fees, interest) = Self::sweepable(env);
+        let amount = fees + interest;
+        let mut state = TokenStorage::get_state(env);
+        let xasset_amount = state.xasset_fees_collected;
+        state.fees_collected -= fees;
+        state.treasury_interest -= interest;
+        state.xasset_fees_collected = 0;
+        TokenStorage::set_state(env, &state);
+        if xasset_amount > 0 {
+            Self::transfer_internal(
+                env,
+                env.current_contract_address(),
+                treasury.clone(),
+                xasset_amount,
+            );
+        }
+        if amount > 0 {
+            match Self::native(env).try_transfer(
+                &env.current_contract_address(),
+                &treasury,
+                &amount,
+            ) {
+                Ok(Ok(())) => {}
+                Ok(Err(_)) => return Err(Error::XLMTransferFailed),
+                Err(_) => return Err(Error::XLMInvocationFailed),
+            }
+        }
+        Ok((amount, xasset_amount))
+    }
+
+    /// Set the stability pool fees
+    fn set_pool_fees(env: &Env, fees: PoolFees) {
+        Self::require_admin(env);
+        assert_with_error!(
+            env,
+            fees.deposit_fee >= 0
+                && fees.unstake_return >= 0
+                && fees.unstake_return <= fees.stake_fee,
+            Error::InvalidPoolFees
+        );
+        let mut state = TokenStorage::get_state(env);
+        state.pool_fees = fees;
+        TokenStorage::set_state(env, &state);
+    }
+
+    fn set_pool_receipt(env: &Env, receipt: Option<Address>) {
+        Self::require_admin(env);
+        match receipt {
+            Some(receipt) => env.storage().instance().set(&RECEIPT_KEY, &receipt),
+            None => env.storage().instance().remove(&RECEIPT_KEY),
+        }
+    }
+
+    fn get_pool_receipt(env: &Env) -> Option<Address> {
+        env.storage().instance().get(&RECEIPT_KEY)
+    }
+
+    fn set_cdp_manager(env: &Env, manager: Option<Address>) {
+        Self::require_admin(env);
+        match manager {
+            Some(manager) => env.storage().instance().set(&MANAGER_KEY, &manager),
+            None => env.storage().instance().remove(&MANAGER_KEY),
+        }
+    }
+
+    fn get_cdp_manager(env: &Env) -> Option<Address> {
+        env.storage().instance().get(&MANAGER_KEY)
+    }
+
+    fn set_collateral_registry(env: &Env, registry: Option<Address>) {
+        Self::require_admin(env);
+        match registry {
+            Some(registry) => env.storage().instance().set(&COLLATERAL_KEY, &registry),
+            None => env.storage().instance().remove(&COLLATERAL_KEY),
+        }
+    }
+
+    fn get_collateral_registry(env: &Env) -> Option<Address> {
+        env.storage().instance().get(&COLLATERAL_KEY)
+    }
+
+    /// Get the totals of a registered collateral type
+    fn get_collateral_totals(env: &Env, token: Address) -> CollateralTotals {
+        Self::collateral_totals(env, &token)
+    }
+
+    /// Get the current borrowing fee
+    fn get_borrow_fee(env: &Env) -> u32 {
+        Self::borrow_fee_rate(env)
+    }
+
     /// Report the version of this contract
     fn version(env: &Env) -> String {
         String::from_str(env, VERSION_STRING)
@@ -1711,42 +3872,37 @@ impl IsCDPAdmin for TokenContract {
 impl IsStabilityPool for TokenContract {
     /// Deposit xasset tokens into the Stability Pool
     fn deposit(env: &Env, from: Address, amount: i128) -> Result<(), Error> {
+        Self::deposit_paying_fee_in(env, from, amount, FeeAsset::Xlm)
+    }
+
+    /// Deposit xasset tokens into the Stability Pool, paying the deposit fee in the given asset
+    fn deposit_paying_fee_in(
+        env: &Env,
+        from: Address,
+        amount: i128,
+        fee_asset: FeeAsset,
+    ) -> Result<(), Error> {
         assert_positive(env, amount);
         from.require_auth();
+        Self::require_authorized(env, &from)?;
         // check if the user has sufficient xasset
         let balance = Self::balance(env.clone(), from.clone());
         if balance < amount {
             return Err(Error::InsufficientBalance);
         }
-        let current_position = Self::get_staker_deposit_amount(env, from.clone())?;
-        let mut position = Self::get_deposit(env, from.clone()).unwrap_or(StakerPosition {
-            xasset_deposit: 0,
-            product_constant: Self::get_product_constant(env),
-            compounded_constant: Self::get_compounded_constant(env),
-            epoch: Self::get_epoch(env),
-        });
-        let xlm_reward = Self::calculate_rewards(env, &position);
-        if xlm_reward > 0 {
-            return Err(Error::ClaimRewardsFirst);
-        }
-        // Collect 1 XLM fee for each new deposit
-        let _ = Self::native(env)
-            .try_transfer(
-                &from.clone(),
-                &env.current_contract_address(),
-                &Self::get_deposit_fee(env),
-            )
-            .map_err(|_| Error::XLMTransferFailed)?;
-        Self::add_fees_collected(env, Self::get_deposit_fee(env));
-        let Some(xasset_deposit) = current_position.checked_add(amount) else {
+        let mut position = Self::get_deposit(env, from.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::StakeDoesntExist));
+        let rewards = Self::settle_rewards(env, &from, &mut position)?;
+        let Some(xasset_deposit) = position.xasset_deposit.checked_add(amount) else {
             return Err(Error::ArithmeticError);
         };
         position.xasset_deposit = xasset_deposit;
-        position.compounded_constant = Self::get_compounded_constant(env);
-        position.product_constant = Self::get_product_constant(env);
         // transfer xasset from address to pool
         Self::transfer_internal(env, from.clone(), env.current_contract_address(), amount);
-        Self::set_deposit(env, from.clone(), position.clone(), 0);
+        // Collect a fee for each new deposit
+        let deposit_fee = TokenStorage::get_state(env).pool_fees.deposit_fee;
+        Self::collect_pool_fee(env, &from, deposit_fee, fee_asset)?;
+        Self::set_deposit(env, from.clone(), position.clone(), rewards);
         Self::add_total_xasset(env, amount);
         Ok(())
     }
@@ -1762,43 +3918,47 @@ impl IsStabilityPool for TokenContract {
     fn liquidate(env: &Env, lender: Address) -> Result<(i128, i128, CDPStatus), Error> {
         let mut cdp = TokenStorage::get_cdp(env, lender.clone())
             .unwrap_or_else(|| panic_with_error!(env, Error::CDPNotFound));
-        let principal_debt = cdp.asset_lent;
         let collateral = cdp.xlm_deposited;
         let mut interest = cdp.accrued_interest;
 
-        // Check if the CDP is frozen
-        if !matches!(cdp.status, CDPStatus::Frozen) {
-            return Err(Error::InvalidLiquidation);
-        }
-
-        // Ensure the debt and collateral are positive
-        if principal_debt <= 0 || collateral <= 0 {
+        // Ensure the CDP is frozen, and its debt and collateral are positive
+        if !cdp.is_liquidatable() {
             return Err(Error::InvalidLiquidation);
         }
 
         let total_xasset = Self::get_total_xasset(env);
 
-        // Handle interest first - collect all accrued interest if possible
+        // Handle interest first - collect all accrued interest if possible, out of the collateral
         let interest_to_liquidate_xasset = cmp::min(interest.amount, total_xasset);
-        let interest_to_liquidate_xlm =
-            Self::convert_xasset_to_xlm(env, interest_to_liquidate_xasset)?;
+        let interest_to_liquidate_xlm = cmp::min(
+            collateral,
+            Self::convert_xasset_to_xlm(env, interest_to_liquidate_xasset)?,
+        );
 
         if interest_to_liquidate_xlm > 0 {
             let Some(interest_amount) = interest.amount.checked_sub(interest_to_liquidate_xasset)
             else {
                 return Err(Error::ArithmeticError);
             };
-            let Some(interest_paid) = interest.paid.checked_add(interest_to_liquidate_xlm) else {
+            let Some(interest_paid) = interest.paid.checked_add(interest_to_liquidate_xasset)
+            else {
                 return Err(Error::ArithmeticError);
             };
             interest.amount = interest_amount;
             interest.paid = interest_paid;
             cdp.accrued_interest = interest;
-            TokenStorage::set_interest_collected(
-                env,
-                Self::get_total_interest_collected(env) + interest_to_liquidate_xlm,
-            );
-            Self::increment_interest_for_current_epoch(env, &interest_to_liquidate_xlm);
+            cdp.xlm_deposited = collateral - interest_to_liquidate_xlm;
+            Self::distribute_interest(env, interest_to_liquidate_xlm);
+        }
+
+        // Once its XLM is spent, a CDP's other collateral backs its remaining interest as principal
+        if interest.amount > 0 && cdp.xlm_deposited == 0 && !cdp.collateral.is_empty() {
+            let Some(asset_lent) = cdp.asset_lent.checked_add(interest.amount) else {
+                return Err(Error::ArithmeticError);
+            };
+            cdp.asset_lent = asset_lent;
+            interest.amount = 0;
+            cdp.accrued_interest = interest;
         }
 
         // if unable to cover all interest, go ahead and update rewards and return
@@ -1807,16 +3967,37 @@ impl IsStabilityPool for TokenContract {
             return Ok((0, 0, CDPStatus::Frozen));
         }
         // Now handle the principal debt with remaining available xasset
+        let principal_debt = cdp.asset_lent;
         let remaining_xasset = Self::get_total_xasset(env);
         let liquidated_debt = cmp::min(principal_debt, remaining_xasset);
+        let collateral = cdp.xlm_deposited;
+        let weighted_collateral = Self::weighted_collateral_xlm(
+            env,
+            &cdp.collateral,
+            Self::lastprice_xlm(env)?.price,
+            Self::decimals_xlm_feed(env)?,
+        )?;
 
         // Calculate the proportional amount of collateral to withdraw based on principal repaid
         let liquidated_collateral = bankers_round(
             DEFAULT_PRECISION * collateral * liquidated_debt / principal_debt,
             DEFAULT_PRECISION,
         );
+        let mut seized_collateral: Map<Address, i128> = Map::new(env);
+        for (token, amount) in cdp.collateral.iter() {
+            let seized = bankers_round(
+                DEFAULT_PRECISION * amount * liquidated_debt / principal_debt,
+                DEFAULT_PRECISION,
+            );
+            if seized > 0 {
+                seized_collateral.set(token, seized);
+            }
+        }
 
         // Update constants for the stability pool
+        for (token, seized) in seized_collateral.iter() {
+            Self::add_collateral_reward(env, &token, seized);
+        }
         Self::update_constants(env, liquidated_debt, liquidated_collateral);
 
         // Update the stability pool
@@ -1835,6 +4016,14 @@ impl IsStabilityPool for TokenContract {
         };
         cdp.xlm_deposited = xlm_deposited;
         cdp.asset_lent = asset_lent;
+        for (token, seized) in seized_collateral.iter() {
+            let held = cdp.collateral.get(token.clone()).unwrap_or(0);
+            if held > seized {
+                cdp.collateral.set(token, held - seized);
+            } else {
+                cdp.collateral.remove(token);
+            }
+        }
 
         env.events().publish(
             (Symbol::new(env, "Liquidation"), lender.clone()),
@@ -1847,7 +4036,7 @@ impl IsStabilityPool for TokenContract {
                 collateralization_ratio: calculate_collateralization_ratio(
                     cdp.asset_lent + liquidated_debt,
                     Self::lastprice_asset(env)?.price,
-                    cdp.xlm_deposited + liquidated_collateral,
+                    cdp.xlm_deposited + liquidated_collateral + weighted_collateral,
                     Self::lastprice_xlm(env)?.price,
                     Self::decimals_xlm_feed(env)?,
                     Self::decimals_asset_feed(env)?,
@@ -1878,9 +4067,7 @@ impl IsStabilityPool for TokenContract {
             );
 
             // Remove CDP from storage
-            env.storage()
-                .persistent()
-                .remove(&DataKey::CDP(lender.clone()));
+            TokenStorage::remove_cdp(env, lender.clone());
 
             Ok((liquidated_debt, liquidated_collateral, CDPStatus::Closed))
         } else {
@@ -1890,24 +4077,65 @@ impl IsStabilityPool for TokenContract {
         }
     }
 
-    /// Claim a user's share of collateral rewards
+    /// Claim a user's share of collateral and interest rewards
     fn claim_rewards(env: &Env, to: Address) -> Result<i128, Error> {
         to.require_auth();
-        let mut position = Self::get_deposit(env, to.clone())
+        Self::claim_rewards_internal(env, to)
+    }
+
+    /// Claim a staker's rewards as one of its operators
+    fn claim_rewards_for(env: &Env, operator: Address, staker: Address) -> Result<i128, Error> {
+        Self::require_operator(env, &staker, &operator);
+        Self::claim_rewards_internal(env, staker)
+    }
+
+    /// Opt a position in to compounding its rewards, or out of it
+    fn set_auto_compound(
+        env: &Env,
+        staker: Address,
+        collat_ratio: Option<u32>,
+    ) -> Result<(), Error> {
+        staker.require_auth();
+        let mut position = Self::get_deposit(env, staker.clone())
             .unwrap_or_else(|| panic_with_error!(env, Error::StakeDoesntExist));
+        if collat_ratio.is_some_and(|ratio| ratio < Self::minimum_collateralization_ratio(env)) {
+            return Err(Error::InsufficientCollateralization);
+        }
+        position.compound_collat_ratio = collat_ratio;
+        Self::set_deposit(env, staker, position, 0);
+        Ok(())
+    }
 
+    /// Compound a position's pending rewards into its deposit
+    fn compound(env: &Env, staker: Address) -> Result<i128, Error> {
+        staker.require_auth();
+        let mut position = Self::get_deposit(env, staker.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::StakeDoesntExist));
+        let Some(collat_ratio) = position.compound_collat_ratio else {
+            return Err(Error::AutoCompoundDisabled);
+        };
         let xlm_reward = Self::calculate_rewards(env, &position);
-
-        let _ = Self::native(env)
-            .try_transfer(&env.current_contract_address(), &to, &xlm_reward)
-            .map_err(|_| Error::XLMTransferFailed)?;
+        let interest_reward = Self::calculate_interest_rewards(env, &position);
+        let Some(total_reward) = xlm_reward.checked_add(interest_reward) else {
+            return Err(Error::ArithmeticError);
+        };
+        if total_reward == 0 {
+            return Ok(0);
+        }
+        let minted = Self::compound_into_cdp(env, &staker, total_reward, collat_ratio)?;
         Self::subtract_total_collateral(env, xlm_reward);
-        position.epoch = Self::get_epoch(env);
-        position.xasset_deposit = Self::get_staker_deposit_amount(env, to.clone())?;
-        position.compounded_constant = Self::get_compounded_constant(env);
-        position.product_constant = Self::get_product_constant(env);
-        Self::set_deposit(env, to, position, xlm_reward);
-        Ok(xlm_reward)
+        Self::subtract_interest_rewards(env, interest_reward);
+        // Rewards in other collateral types cannot be compounded, so they are paid out
+        let collateral_rewards = Self::calculate_collateral_rewards(env, &position);
+        Self::pay_collateral_rewards(
+            env,
+            &Self::rewards_recipient(env, staker.clone()),
+            &collateral_rewards,
+        )?;
+        position.xasset_deposit = Self::calculate_current_deposit(env, &position) + minted;
+        Self::snapshot_constants(env, &mut position);
+        Self::set_deposit(env, staker, position, total_reward);
+        Ok(minted)
     }
 
     /// Retrieve the current deposit amount for a given address
@@ -1930,7 +4158,18 @@ impl IsStabilityPool for TokenContract {
 
     /// Add a stake to the pool
     fn stake(env: &Env, from: Address, amount: i128) -> Result<(), Error> {
+        Self::stake_paying_fee_in(env, from, amount, FeeAsset::Xlm)
+    }
+
+    /// Add a stake to the pool, paying the stake fee in the given asset
+    fn stake_paying_fee_in(
+        env: &Env,
+        from: Address,
+        amount: i128,
+        fee_asset: FeeAsset,
+    ) -> Result<(), Error> {
         from.require_auth();
+        Self::require_authorized(env, &from)?;
 
         // Get current state, and use sub keys throughout
         let current_state = TokenStorage::get_state(env);
@@ -1947,32 +4186,106 @@ impl IsStabilityPool for TokenContract {
             return Err(Error::InsufficientBalance);
         }
 
-        let _ = Self::native(env)
-            .try_transfer(
-                &from.clone(),
-                &env.current_contract_address(),
-                &current_state.stake_fee,
-            )
-            .map_err(|_| Error::XLMTransferFailed)?;
-        // Add stake fee
-        Self::add_fees_collected(env, current_state.stake_fee);
-
         // Create new position
         let position = StakerPosition {
             xasset_deposit: amount,
             product_constant: current_state.product_constant,
             compounded_constant: current_state.compounded_constant,
             epoch: current_state.epoch,
+            scale: current_state.scale,
+            interest_constant: current_state.interest_constant,
+            unstake_return: match fee_asset {
+                FeeAsset::Xlm => current_state.pool_fees.unstake_return,
+                FeeAsset::XAsset => 0,
+            },
+            compound_collat_ratio: None,
+            collateral_constants: Self::current_collateral_constants(env),
         };
         // transfer xasset from address to pool
         Self::transfer_internal(env, from.clone(), env.current_contract_address(), amount);
 
+        // Collect the stake fee; paid in xAsset, it is charged without the unstake return
+        let fees = current_state.pool_fees;
+        let stake_fee = match fee_asset {
+            FeeAsset::Xlm => fees.stake_fee,
+            FeeAsset::XAsset => fees.stake_fee - fees.unstake_return,
+        };
+        Self::collect_pool_fee(env, &from, stake_fee, fee_asset)?;
+        Self::add_unstake_returns_owed(env, position.unstake_return);
+
         // Set the new position in the stability pool
         Self::set_deposit(env, from.clone(), position.clone(), 0);
         Self::add_total_xasset(env, amount);
         Ok(())
     }
 
+    /// Move part of a deposit to another staker, along with its share of pending rewards
+    fn transfer_stake(env: &Env, from: Address, to: Address, amount: i128) -> Result<(), Error> {
+        assert_positive(env, amount);
+        let receipt = Self::get_pool_receipt(env).ok_or(Error::PoolReceiptNotSet)?;
+        receipt.require_auth();
+        if from == to {
+            return Err(Error::CannotTransferToSelf);
+        }
+        Self::require_authorized(env, &to)?;
+        let mut position = Self::get_deposit(env, from.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::StakeDoesntExist));
+        let deposit = Self::calculate_current_deposit(env, &position);
+        if deposit < amount {
+            return Err(Error::InsufficientStake);
+        }
+
+        // Rewards are proportional to the initial deposit, so splitting it under the same
+        // snapshots splits pending rewards along with it
+        let remaining = position.xasset_deposit * (deposit - amount) / deposit;
+        let moved = StakerPosition {
+            xasset_deposit: position.xasset_deposit - remaining,
+            unstake_return: 0,
+            compound_collat_ratio: None,
+            ..position.clone()
+        };
+        position.xasset_deposit = remaining;
+        Self::set_deposit(env, from.clone(), position, 0);
+        events::StakeTransfer {
+            from,
+            to: to.clone(),
+            amount,
+        }
+        .publish(env);
+
+        let Some(mut existing) = Self::get_deposit(env, to.clone()) else {
+            Self::set_deposit(env, to, moved, 0);
+            return Ok(());
+        };
+        // Positions with different snapshots cannot be merged, so pay out both their rewards
+        let xlm_reward =
+            Self::calculate_rewards(env, &existing) + Self::calculate_rewards(env, &moved);
+        let interest_reward = Self::calculate_interest_rewards(env, &existing)
+            + Self::calculate_interest_rewards(env, &moved);
+        let total_reward = xlm_reward + interest_reward;
+        if total_reward > 0 {
+            let _ = Self::native(env)
+                .try_transfer(
+                    &env.current_contract_address(),
+                    Self::rewards_recipient(env, to.clone()),
+                    &total_reward,
+                )
+                .map_err(|_| Error::XLMTransferFailed)?;
+            Self::subtract_total_collateral(env, xlm_reward);
+            Self::subtract_interest_rewards(env, interest_reward);
+        }
+        let recipient = Self::rewards_recipient(env, to.clone());
+        for position in [&existing, &moved] {
+            let collateral_rewards = Self::calculate_collateral_rewards(env, position);
+            Self::pay_collateral_rewards(env, &recipient, &collateral_rewards)?;
+        }
+        existing.xasset_deposit = Self::calculate_current_deposit(env, &existing)
+            + Self::calculate_current_deposit(env, &moved);
+        Self::snapshot_constants(env, &mut existing);
+        Self::set_deposit(env, to, existing, total_reward);
+        Ok(())
+    }
+
     /// Remove a user's stake from the pool
     fn unstake(env: &Env, staker: Address) -> Result<(), Error> {
         staker.require_auth();
@@ -1985,9 +4298,12 @@ impl IsStabilityPool for TokenContract {
             Some(position) => {
                 let d = Self::calculate_current_deposit(env, &position);
                 let xlm_reward = Self::calculate_rewards(env, &position);
+                let interest_reward = Self::calculate_interest_rewards(env, &position);
                 Ok(AvailableAssets {
                     available_xasset: d,
                     available_rewards: xlm_reward,
+                    available_interest: interest_reward,
+                    available_collateral: Self::calculate_collateral_rewards(env, &position),
                 })
             }
             None => Err(Error::StakeDoesntExist),
@@ -2013,7 +4329,22 @@ impl IsStabilityPool for TokenContract {
             compounded_constant: current_state.compounded_constant,
             product_constant: current_state.product_constant,
             epoch: current_state.epoch,
+            scale: current_state.scale,
             xasset_deposit: current_state.total_xasset,
+            interest_constant: current_state.interest_constant,
+            unstake_return: current_state.unstake_returns_owed,
+            compound_collat_ratio: None,
+            collateral_constants: Self::current_collateral_constants(env),
         }
     }
+
+    /// View the stability pool's fees, in XLM
+    fn get_pool_fees(env: &Env) -> PoolFees {
+        TokenStorage::get_state(env).pool_fees
+    }
+
+    /// View the number of frozen CDPs awaiting liquidation
+    fn get_frozen_cdps(env: &Env) -> u32 {
+        TokenStorage::get_state(env).frozen_cdps
+    }
 }